@@ -0,0 +1,223 @@
+//! Source-anomaly detection for ingested snapshots.
+//!
+//! This is a cheap ingest-time screen ahead of the gap investigation in
+//! DEVELOPMENT.md. Each observed day of a feed (TAL) is compared with the
+//! feed's previous observed day by row count only. Days whose row count drops
+//! sharply are flagged, as are empty snapshots and files cut off mid-stream.
+//! The distinct record and prefix counts on D-1, D and D+1 are compared by
+//! `gaps investigate` (see [`GapInvestigator`](crate::GapInvestigator)). Flagged days can be held out of the
+//! trie (see [`RoasTrieMut::hold_out_day`](crate::RoasTrieMut::hold_out_day)),
+//! so that a broken snapshot is not recorded as a mass ROA withdrawal.
+
+use crate::{Observation, RoaCsvError, RoaCsvErrorKind, RoaFile};
+use anyhow::Result;
+use chrono::{NaiveDate, SecondsFormat};
+use serde_json::json;
+use std::io::Write;
+
+/// Thresholds and handling of detected anomalies.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnomalyConfig {
+    /// Flag a day whose row count is below this fraction of the previous
+    /// observed day's count.
+    pub drop_ratio: f64,
+    /// Remove flagged days from the trie instead of only reporting them.
+    pub hold_out: bool,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        AnomalyConfig {
+            drop_ratio: 0.5,
+            hold_out: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// The snapshot had no rows.
+    Empty,
+    /// The file ended mid-stream, e.g. a cut-off download.
+    Truncated,
+    /// The row count dropped below the configured fraction of the previous day.
+    RecordDrop,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::Empty => "empty",
+            AnomalyKind::Truncated => "truncated",
+            AnomalyKind::RecordDrop => "record_drop",
+        }
+    }
+}
+
+/// A flagged snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub tal: String,
+    pub date: NaiveDate,
+    pub kind: AnomalyKind,
+    /// Rows of the flagged snapshot; 0 for truncated files
+    pub rows: u64,
+    /// Rows of the feed's previous observed day, if any
+    pub baseline: Option<u64>,
+    /// Whether the day was held out of the trie
+    pub held_out: bool,
+}
+
+/// Flag empty days and sharp row-count drops among the observed days of
+/// `days`. Each day is compared with the previous observed day of its feed
+/// only; neither distinct prefixes nor the following day are looked at.
+/// `observations` must hold the whole calendar of each feed, so the previous
+/// observed day of the first checked day is known.
+pub fn detect_anomalies(
+    observations: &[Observation],
+    days: impl Fn(&str, NaiveDate) -> bool,
+    config: &AnomalyConfig,
+) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    let mut previous: Option<&Observation> = None;
+    for o in observations {
+        let baseline = previous.filter(|p| p.tal == o.tal).map(|p| p.rows);
+        previous = Some(o);
+        if !days(&o.tal, o.date) {
+            continue;
+        }
+        let kind = if o.rows == 0 {
+            AnomalyKind::Empty
+        } else if baseline.is_some_and(|b| (o.rows as f64) < b as f64 * config.drop_ratio) {
+            AnomalyKind::RecordDrop
+        } else {
+            continue;
+        };
+        anomalies.push(Anomaly {
+            tal: o.tal.clone(),
+            date: o.date,
+            kind,
+            rows: o.rows,
+            baseline,
+            held_out: false,
+        });
+    }
+    anomalies
+}
+
+/// If reading `file` failed because its data ended mid-stream, the anomaly
+/// to report for it. Errors before the first line (e.g. a missing file) are
+/// not truncation.
+pub fn truncation_anomaly(file: &RoaFile, error: &anyhow::Error) -> Option<Anomaly> {
+    match error.downcast_ref::<RoaCsvError>() {
+        Some(RoaCsvError {
+            line,
+            kind: RoaCsvErrorKind::Io(_),
+            ..
+        }) if *line > 0 => Some(Anomaly {
+            tal: file.tal.clone(),
+            date: file.file_date,
+            kind: AnomalyKind::Truncated,
+            rows: 0,
+            baseline: None,
+            held_out: false,
+        }),
+        _ => None,
+    }
+}
+
+/// Write `anomalies` as a JSON report to `path` (local path or any `oneio`
+/// writer target; a `.gz` suffix compresses it).
+pub fn write_anomaly_report(
+    path: &str,
+    config: &AnomalyConfig,
+    anomalies: &[Anomaly],
+) -> Result<()> {
+    let report = json!({
+        "generated_at": chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        "drop_ratio": config.drop_ratio,
+        "hold_out": config.hold_out,
+        "anomalies": anomalies
+            .iter()
+            .map(|a| json!({
+                "tal": a.tal,
+                "date": a.date.to_string(),
+                "kind": a.kind.as_str(),
+                "rows": a.rows,
+                "baseline": a.baseline,
+                "held_out": a.held_out,
+            }))
+            .collect::<Vec<_>>(),
+    });
+    let mut writer = oneio::get_writer(path)?;
+    serde_json::to_writer_pretty(&mut writer, &report)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_empty_days_and_sharp_drops() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let obs = |tal: &str, d, rows| Observation {
+            tal: tal.to_string(),
+            date: day(d),
+            rows,
+        };
+        let observations = vec![
+            obs("apnic", 1, 100),
+            obs("apnic", 2, 40),
+            obs("apnic", 3, 100),
+            obs("ripencc", 1, 10),
+            obs("ripencc", 2, 0),
+            obs("ripencc", 3, 6),
+        ];
+        let config = AnomalyConfig::default();
+
+        let anomalies = detect_anomalies(&observations, |_, _| true, &config);
+        let flagged: Vec<_> = anomalies
+            .iter()
+            .map(|a| (a.tal.as_str(), a.date, a.kind, a.baseline))
+            .collect();
+        // the first ripencc day is not compared with the last apnic day, and
+        // the day after an empty one is a recovery, not a drop
+        assert_eq!(
+            flagged,
+            vec![
+                ("apnic", day(2), AnomalyKind::RecordDrop, Some(100)),
+                ("ripencc", day(2), AnomalyKind::Empty, Some(10)),
+            ]
+        );
+
+        let only_new = detect_anomalies(&observations, |_, d| d > day(2), &config);
+        assert!(only_new.is_empty());
+    }
+
+    #[test]
+    fn test_truncation_needs_a_read_error_after_the_header() {
+        let file = RoaFile {
+            url: "ripencc.tal/2024/01/01/roas.csv.xz".to_string(),
+            tal: "ripencc".to_string(),
+            file_date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            rows_count: 0,
+            processed: false,
+        };
+        let error = |line| {
+            anyhow::Error::new(RoaCsvError {
+                file: file.url.clone(),
+                line,
+                column: None,
+                kind: RoaCsvErrorKind::Io("unexpected end of file".to_string()),
+            })
+        };
+        assert_eq!(
+            truncation_anomaly(&file, &error(1200)).map(|a| a.kind),
+            Some(AnomalyKind::Truncated)
+        );
+        assert!(truncation_anomaly(&file, &error(0)).is_none());
+    }
+}
@@ -1,17 +1,27 @@
 use crate::legacy::LegacyRoasTrie;
-use crate::{RoasTrie, RpkiValidation};
-use axum::extract::{Query, State};
-use axum::http::{Method, StatusCode};
+use crate::{
+    DateRangeFilter, MatchMode, RangeMode, RoaRecordRef, RoasLookupEntry, RoasTrie, SearchCursor,
+    SearchPosition, SearchQuery, SortKey, SortableRecord, ValidationReport,
+};
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, Query, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
 use axum::response::IntoResponse;
 use axum::routing::get;
 use axum::{Json, Router};
-use chrono::{DateTime, NaiveDate};
+use chrono::{DateTime, NaiveDate, SecondsFormat};
 use clap::Args;
 use ipnet::IpNet;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use serde_json::value::RawValue;
+use sha2::{Digest, Sha256};
+use std::collections::BinaryHeap;
+use std::convert::Infallible;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
+use tokio_stream::wrappers::ReceiverStream;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::warn;
 
@@ -22,26 +32,113 @@ pub enum TrieBackend {
     V1(LegacyRoasTrie),
 }
 
+/// A search result of either backend. v2 results stay borrowed from the
+/// archive until they are converted.
+pub enum SearchHit<'a> {
+    V2(RoaRecordRef<'a>),
+    V1(RoasLookupEntry),
+}
+
+impl SearchHit<'_> {
+    pub fn into_entry(self) -> RoasLookupEntry {
+        match self {
+            SearchHit::V2(r) => r.to_entry(),
+            SearchHit::V1(entry) => entry,
+        }
+    }
+}
+
+impl SortableRecord for SearchHit<'_> {
+    fn prefix(&self) -> IpNet {
+        match self {
+            SearchHit::V2(r) => r.prefix(),
+            SearchHit::V1(entry) => entry.prefix,
+        }
+    }
+
+    fn origin(&self) -> u32 {
+        match self {
+            SearchHit::V2(r) => r.origin(),
+            SearchHit::V1(entry) => entry.origin,
+        }
+    }
+
+    fn max_len(&self) -> u8 {
+        match self {
+            SearchHit::V2(r) => r.max_len(),
+            SearchHit::V1(entry) => entry.max_len,
+        }
+    }
+
+    fn tal(&self) -> &str {
+        match self {
+            SearchHit::V2(r) => r.tal(),
+            SearchHit::V1(entry) => &entry.tal,
+        }
+    }
+
+    fn first_seen(&self) -> Option<NaiveDate> {
+        match self {
+            SearchHit::V2(r) => r.first_seen(),
+            SearchHit::V1(entry) => entry.first_seen(),
+        }
+    }
+
+    fn last_seen(&self) -> Option<NaiveDate> {
+        match self {
+            SearchHit::V2(r) => r.last_seen(),
+            SearchHit::V1(entry) => entry.last_seen(),
+        }
+    }
+}
+
 impl TrieBackend {
-    pub fn search(
+    pub fn search(&self, query: &SearchQuery) -> Vec<RoasLookupEntry> {
+        self.search_iter(query).map(SearchHit::into_entry).collect()
+    }
+
+    /// Lazy search; see [`RoasTrie::search_iter`]. v1 archives are searched
+    /// eagerly.
+    pub fn search_iter(&self, query: &SearchQuery) -> Box<dyn Iterator<Item = SearchHit<'_>> + '_> {
+        self.search_iter_after(query, None)
+    }
+
+    /// Lazy search resumed after `after`; see [`RoasTrie::search_iter_after`].
+    /// v1 results are put in trie order and skipped up to `after`.
+    pub fn search_iter_after(
         &self,
-        prefix: Option<IpNet>,
-        origin: Option<u32>,
-        max_len: Option<u8>,
-        date: Option<NaiveDate>,
-        current: Option<bool>,
-        exact: bool,
-    ) -> Vec<crate::RoasLookupEntry> {
+        query: &SearchQuery,
+        after: Option<SearchPosition>,
+    ) -> Box<dyn Iterator<Item = SearchHit<'_>> + '_> {
         match self {
-            TrieBackend::V2(t) => t.search(prefix, origin, max_len, date, current, exact),
-            TrieBackend::V1(t) => t.search(prefix, origin, max_len, date, current, exact),
+            TrieBackend::V2(t) => Box::new(t.search_iter_after(query, after).map(SearchHit::V2)),
+            TrieBackend::V1(t) => {
+                let mut entries = t.search(query);
+                entries.sort_by_cached_key(|e| SortKey::Prefix.position(e));
+                Box::new(
+                    entries
+                        .into_iter()
+                        .filter(move |e| {
+                            after
+                                .as_ref()
+                                .map_or(true, |a| SortKey::Prefix.position(e) > *a)
+                        })
+                        .map(SearchHit::V1),
+                )
+            }
         }
     }
 
-    pub fn validate(&self, prefix: &IpNet, origin: u32, date_ts: i64) -> RpkiValidation {
+    pub fn validate(
+        &self,
+        prefix: &IpNet,
+        origin: u32,
+        date_ts: i64,
+        strict: bool,
+    ) -> ValidationReport {
         match self {
-            TrieBackend::V2(t) => t.validate(prefix, origin, date_ts),
-            TrieBackend::V1(t) => t.validate(prefix, origin, date_ts),
+            TrieBackend::V2(t) => t.validate(prefix, origin, date_ts, strict),
+            TrieBackend::V1(t) => t.validate(prefix, origin, date_ts, strict),
         }
     }
 
@@ -70,15 +167,74 @@ impl TrieBackend {
         }
     }
 
+    /// Latest ingested date per TAL; empty for v1 archives, which only track
+    /// a global latest date.
+    pub fn tal_latest_dates(&self) -> Vec<(String, NaiveDate)> {
+        match self {
+            TrieBackend::V2(t) => t.tal_latest_dates(),
+            TrieBackend::V1(_) => Vec::new(),
+        }
+    }
+
+    /// Observation calendar; v1 archives do not record one.
+    pub fn observations(&self) -> Vec<crate::Observation> {
+        match self {
+            TrieBackend::V2(t) => t.observations(),
+            TrieBackend::V1(_) => Vec::new(),
+        }
+    }
+
+    /// Days held out as source anomalies; v1 archives hold out none.
+    pub fn held_out_days(&self) -> Vec<(String, NaiveDate)> {
+        match self {
+            TrieBackend::V2(t) => t.held_out_days(),
+            TrieBackend::V1(_) => Vec::new(),
+        }
+    }
+
+    /// Hash of the applied known-gap policy; v1 archives do not record it.
+    pub fn gap_policy_hash(&self) -> Option<String> {
+        match self {
+            TrieBackend::V2(t) => t.gap_policy_hash(),
+            TrieBackend::V1(_) => None,
+        }
+    }
+
+    /// Snapshots queued for retry; v1 archives have no retry queue.
+    pub fn failed_files(&self) -> Vec<crate::FailedFile> {
+        match self {
+            TrieBackend::V2(t) => t.failed_files(),
+            TrieBackend::V1(_) => Vec::new(),
+        }
+    }
+
     pub fn format_version(&self) -> u32 {
         match self {
             TrieBackend::V2(_) => crate::FORMAT_VERSION,
             TrieBackend::V1(_) => 1,
         }
     }
+
+    /// Identifier of the loaded archive build. v1 archives have none and use
+    /// their latest date.
+    pub fn build_id(&self) -> u64 {
+        match self {
+            TrieBackend::V2(t) => t.build_id(),
+            TrieBackend::V1(t) => t.latest_date as u64,
+        }
+    }
 }
 
-pub type SharedTrie = Arc<RwLock<TrieBackend>>;
+/// The serving trie, shared by the request handlers and the updater.
+/// Handlers clone the inner `Arc` under a short read lock and query the clone,
+/// so a slow response never holds the lock; the updater swaps in a new `Arc`
+/// and the old archive is dropped when the last response reading it ends.
+pub type SharedTrie = Arc<RwLock<Arc<TrieBackend>>>;
+
+/// The archive currently being served.
+async fn snapshot(state: &SharedTrie) -> Arc<TrieBackend> {
+    state.read().await.clone()
+}
 
 #[derive(Args, Debug, Serialize, Deserialize)]
 pub struct RoasSearchQuery {
@@ -97,32 +253,134 @@ pub struct RoasSearchQuery {
     /// filter results to whether ROA is still current
     current: Option<bool>,
 
+    /// first day of a date window, format: YYYY-MM-DD, e.g. `?from=2021-03-01`
+    from: Option<String>,
+
+    /// last day of a date window, format: YYYY-MM-DD (default: latest date)
+    until: Option<String>,
+
+    /// how ROAs must match the window: `overlap` (default, active on any day)
+    /// or `within` (active on every day; requires `from`)
+    range: Option<String>,
+
+    /// only ROAs first seen on or after this day, format: YYYY-MM-DD
+    first_seen_from: Option<String>,
+
+    /// only ROAs first seen on or before this day, format: YYYY-MM-DD
+    first_seen_until: Option<String>,
+
+    /// only ROAs last seen on or after this day, format: YYYY-MM-DD
+    last_seen_from: Option<String>,
+
+    /// only ROAs last seen on or before this day, format: YYYY-MM-DD
+    last_seen_until: Option<String>,
+
+    /// order of the results: `prefix` (default), `origin`, `first_seen` or `last_seen`
+    sort: Option<String>,
+
+    /// opaque cursor from the `next_cursor` of a previous page; replaces `page`
+    cursor: Option<String>,
+
+    /// response format: `json` (default, paginated), or `ndjson` or `csv` to
+    /// stream every result; overrides the `Accept` header
+    format: Option<String>,
+
     /// page number, starting from 0
     page: Option<usize>,
 
     /// number of items per page, maximum 1000
     page_size: Option<usize>,
 
-    /// if true (default), only return exact prefix matches; if false, include supernets and subnets
+    /// how stored prefixes relate to `prefix`: `exact` (default), `covering`,
+    /// `covered_by` or `overlapping`, e.g. `?match=covering`
+    #[serde(rename = "match")]
+    match_mode: Option<String>,
+
+    /// deprecated, use `match`: true is `exact`, false is `overlapping`
     exact: Option<bool>,
+
+    /// filter results by trust anchor, e.g. `?tal=ripencc`
+    tal: Option<String>,
+
+    /// if true, treat interpolated days as absent: they do not match `date` or
+    /// `current` and are left out of `date_ranges` (default: false)
+    observed_only: Option<bool>,
+}
+
+impl RoasSearchQuery {
+    /// Fingerprint of the filters and sort order, which a cursor must match.
+    fn fingerprint(&self) -> u64 {
+        let filters = json!([
+            self.asn,
+            self.prefix,
+            self.max_len,
+            self.date,
+            self.current,
+            self.from,
+            self.until,
+            self.range,
+            self.first_seen_from,
+            self.first_seen_until,
+            self.last_seen_from,
+            self.last_seen_until,
+            self.match_mode,
+            self.exact,
+            self.tal,
+            self.observed_only,
+            self.sort,
+        ]);
+        let digest = Sha256::digest(filters.to_string().as_bytes());
+        u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+    }
+
+    /// The date-window filters of the query, or the error message for a
+    /// malformed parameter.
+    fn window(&self) -> Result<DateRangeFilter, String> {
+        let date = |name: &str, value: &Option<String>| match value.as_ref().map(|d| d.parse()) {
+            Some(Ok(d)) => Ok(Some(d)),
+            Some(Err(_)) => Err(format!("invalid {}", name)),
+            None => Ok(None),
+        };
+        let mode = match self.range.as_deref().map(RangeMode::parse) {
+            Some(Some(m)) => m,
+            Some(None) => return Err("invalid range".to_string()),
+            None => RangeMode::default(),
+        };
+        let window = DateRangeFilter {
+            from: date("from", &self.from)?,
+            until: date("until", &self.until)?,
+            mode,
+            first_seen_from: date("first_seen_from", &self.first_seen_from)?,
+            first_seen_until: date("first_seen_until", &self.first_seen_until)?,
+            last_seen_from: date("last_seen_from", &self.last_seen_from)?,
+            last_seen_until: date("last_seen_until", &self.last_seen_until)?,
+        };
+        window.check()?;
+        Ok(window)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct RoasSearchResult {
-    /// total number of matching entries (before pagination)
-    pub total: usize,
+    /// total number of matching entries (before pagination); null on pages
+    /// read with a cursor, which do not walk the results before them
+    pub total: Option<usize>,
     /// error message if any
     pub error: Option<String>,
     pub data: Vec<RoasSearchResultEntry>,
     pub meta: Option<Meta>,
     pub page: usize,
     pub page_size: usize,
+    /// cursor of the next page, or null on the last page
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Meta {
     pub latest_date: String,
     pub format_version: u32,
+    /// build id of the archive the results were read from, as hex
+    pub build_id: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -130,8 +388,120 @@ pub struct RoasSearchResultEntry {
     pub prefix: String,
     pub max_len: u8,
     pub asn: u32,
+    pub tal: String,
     pub date_ranges: Vec<(String, String)>,
+    /// the days of `date_ranges` that were interpolated (bridged by the
+    /// known-gap policy or carried over a held-out day) rather than observed
+    pub interpolated_ranges: Vec<(String, String)>,
     pub current: bool,
+    /// ROA objects the record was published in: the first and the latest
+    /// ones; empty when the source data did not name them
+    pub objects: Vec<RoasSearchResultObject>,
+    /// number of distinct ROA objects, including those left out of `objects`
+    pub object_count: u32,
+    /// latest expiry a validator reported for the ROA (RFC 3339), when known
+    pub expires: Option<String>,
+    /// why a ROA that is no longer published ended: `expired` when its last
+    /// day is the expiry of its latest object, `revoked` when it disappeared
+    /// before; null while current or when no expiry is known
+    pub end: Option<String>,
+}
+
+impl RoasSearchResultEntry {
+    /// Append the entry as a CSV row (see [`CSV_HEADER`]). Date ranges are
+    /// written as `start:end` and, like object URIs, separated by spaces;
+    /// absent `expires` and `end` are empty.
+    fn write_csv_row(&self, out: &mut String) {
+        let ranges = |ranges: &[(String, String)]| {
+            ranges
+                .iter()
+                .map(|(from, to)| format!("{}:{}", from, to))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let uris = self
+            .objects
+            .iter()
+            .map(|o| o.uri.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let fields = [
+            self.prefix.clone(),
+            self.max_len.to_string(),
+            self.asn.to_string(),
+            self.tal.clone(),
+            self.current.to_string(),
+            ranges(&self.date_ranges),
+            ranges(&self.interpolated_ranges),
+            uris,
+            self.object_count.to_string(),
+            self.expires.clone().unwrap_or_default(),
+            self.end.clone().unwrap_or_default(),
+        ];
+        for (idx, field) in fields.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            if field.contains([',', '"', '\n', '\r']) {
+                out.push('"');
+                out.push_str(&field.replace('"', "\"\""));
+                out.push('"');
+            } else {
+                out.push_str(field);
+            }
+        }
+        out.push('\n');
+    }
+
+    fn new(entry: &RoasLookupEntry, latest_ts: i64) -> Self {
+        RoasSearchResultEntry {
+            prefix: entry.prefix.to_string(),
+            max_len: entry.max_len,
+            asn: entry.origin,
+            tal: entry.tal.clone(),
+            date_ranges: entry
+                .dates_ranges
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+            interpolated_ranges: entry
+                .interpolated_ranges
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+            current: entry.dates_ranges.iter().any(|(_from, to)| {
+                to.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() >= latest_ts
+            }),
+            objects: entry
+                .objects
+                .iter()
+                .map(|o| RoasSearchResultObject {
+                    uri: o.uri.clone(),
+                    not_before: format_validity_time(o.not_before),
+                    not_after: format_validity_time(o.not_after),
+                })
+                .collect(),
+            object_count: entry.object_count,
+            expires: format_validity_time(entry.expires),
+            end: DateTime::from_timestamp(latest_ts, 0)
+                .and_then(|latest| entry.end(latest.date_naive()))
+                .map(|end| end.as_str().to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RoasSearchResultObject {
+    pub uri: String,
+    /// start of the EE certificate validity, RFC 3339
+    pub not_before: Option<String>,
+    /// end of the EE certificate validity, RFC 3339
+    pub not_after: Option<String>,
+}
+
+fn format_validity_time(ts: Option<i64>) -> Option<String> {
+    ts.and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::Secs, true))
 }
 
 #[derive(Args, Debug, Serialize, Deserialize)]
@@ -144,6 +514,43 @@ pub struct ValidateQuery {
 
     /// date for historical validation, format: YYYY-MM-DD (default: latest)
     date: Option<String>,
+
+    /// if true, ignore ROAs whose state on the date is interpolated rather
+    /// than observed (default: false)
+    strict: Option<bool>,
+}
+
+/// Query parameters of `POST /validate`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BulkValidateQuery {
+    /// if true, ignore ROAs whose state on the date is interpolated rather
+    /// than observed (default: false)
+    strict: Option<bool>,
+}
+
+/// One route of a `POST /validate` body.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateItem {
+    prefix: String,
+    asn: u32,
+    /// format: YYYY-MM-DD (default: latest)
+    date: Option<String>,
+}
+
+/// One line of a `POST /validate` response: the result for the item at
+/// `index` of the request, or why it could not be validated.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BulkValidateLine {
+    Result {
+        index: usize,
+        #[serde(flatten)]
+        result: ValidateResult,
+    },
+    Error {
+        index: usize,
+        error: String,
+    },
 }
 
 #[derive(Serialize)]
@@ -151,20 +558,73 @@ pub struct ValidateResult {
     pub prefix: String,
     pub asn: u32,
     pub date: String,
+    pub strict: bool,
     pub result: String,
+    /// the covering VRPs active on `date` and why each does or does not
+    /// match the route
+    pub vrps: Vec<ValidateResultVrp>,
+}
+
+#[derive(Serialize)]
+pub struct ValidateResultVrp {
+    pub prefix: String,
+    pub max_len: u8,
+    pub asn: u32,
+    pub tal: String,
+    /// `matched`, `origin_mismatch` or `max_length_exceeded`
+    pub status: String,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct CoverageQuery {
+    /// limit to one trust anchor, e.g. `?tal=ripencc`
+    tal: Option<String>,
+
+    /// first day to include, format: YYYY-MM-DD
+    from: Option<String>,
+
+    /// last day to include, format: YYYY-MM-DD
+    until: Option<String>,
+
+    /// if true, list every observed day with its row count (default: false)
+    days: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CoverageResult {
+    pub data: Vec<CoverageEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CoverageEntry {
+    pub tal: String,
+    pub first: Option<String>,
+    pub last: Option<String>,
+    pub observed_days: usize,
+    /// days between `first` and `last` without an ingested snapshot
+    pub missing_days: i64,
+    pub missing_ranges: Vec<(String, String)>,
+    /// observed days whose snapshot had no rows
+    pub empty_days: Vec<String>,
+    /// days held out of the trie as source anomalies
+    pub held_out_days: Vec<String>,
+    /// every observed day as (date, rows_count); only with `?days=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<Vec<(String, u64)>>,
 }
 
 fn bad_request(msg: &str) -> axum::response::Response {
-    (
-        StatusCode::BAD_REQUEST,
-        Json(serde_json::json!({"error": msg})),
-    )
-        .into_response()
+    error_response(StatusCode::BAD_REQUEST, msg)
+}
+
+fn error_response(status: StatusCode, msg: &str) -> axum::response::Response {
+    (status, Json(serde_json::json!({"error": msg}))).into_response()
 }
 
 async fn health(State(state): State<SharedTrie>) -> impl IntoResponse {
-    let trie = state.read().await;
+    let trie = snapshot(&state).await;
     let (ipv4_count, ipv6_count) = trie.counts();
+    let failed_files = trie.failed_files();
     Json(json!({
         "ipv4_roas_count": ipv4_count,
         "ipv6_roas_count": ipv6_count,
@@ -174,14 +634,87 @@ async fn health(State(state): State<SharedTrie>) -> impl IntoResponse {
             .date()
             .to_string(),
         "format_version": trie.format_version(),
+        "build_id": format!("{:016x}", trie.build_id()),
+        "tal_latest_dates": trie
+            .tal_latest_dates()
+            .into_iter()
+            .map(|(tal, date)| (tal, date.to_string()))
+            .collect::<std::collections::BTreeMap<String, String>>(),
+        "pending_retries": failed_files.iter().filter(|f| !f.abandoned()).count(),
+        "abandoned_files": failed_files
+            .iter()
+            .filter(|f| f.abandoned())
+            .map(|f| json!({
+                "tal": f.tal,
+                "date": DateTime::from_timestamp(f.date, 0).unwrap().date_naive().to_string(),
+                "url": f.url,
+                "attempts": f.attempts,
+                "error": f.error,
+            }))
+            .collect::<Vec<_>>(),
+        "gap_policy_hash": trie.gap_policy_hash(),
     }))
     .into_response()
 }
 
+async fn coverage(
+    query: Query<CoverageQuery>,
+    State(state): State<SharedTrie>,
+) -> impl IntoResponse {
+    let parse_date = |d: &Option<String>| d.as_ref().map(|d| d.parse::<NaiveDate>()).transpose();
+    let (from, until) = match (parse_date(&query.from), parse_date(&query.until)) {
+        (Ok(from), Ok(until)) => (from, until),
+        _ => return bad_request("invalid date"),
+    };
+
+    let trie = snapshot(&state).await;
+    let held_out = trie.held_out_days();
+    let coverage =
+        crate::summarize_coverage(&trie.observations(), query.tal.as_deref(), from, until);
+    let data = coverage
+        .iter()
+        .map(|c| CoverageEntry {
+            tal: c.tal.clone(),
+            first: c.first().map(|d| d.to_string()),
+            last: c.last().map(|d| d.to_string()),
+            observed_days: c.days.len(),
+            missing_days: c.missing_days(),
+            missing_ranges: c
+                .missing_ranges()
+                .iter()
+                .map(|(from, to)| (from.to_string(), to.to_string()))
+                .collect(),
+            empty_days: c.empty_days().iter().map(|d| d.to_string()).collect(),
+            held_out_days: held_out
+                .iter()
+                .filter(|(tal, date)| {
+                    *tal == c.tal
+                        && from.map_or(true, |f| *date >= f)
+                        && until.map_or(true, |u| *date <= u)
+                })
+                .map(|(_, date)| date.to_string())
+                .collect(),
+            days: query.days.unwrap_or(false).then(|| {
+                c.days
+                    .iter()
+                    .map(|(d, rows)| (d.to_string(), *rows))
+                    .collect()
+            }),
+        })
+        .collect();
+
+    Json(CoverageResult { data }).into_response()
+}
+
 async fn search(
     query: Query<RoasSearchQuery>,
+    headers: HeaderMap,
     State(state): State<SharedTrie>,
 ) -> impl IntoResponse {
+    let format = match SearchFormat::negotiate(query.format.as_deref(), &headers) {
+        Some(format) => format,
+        None => return bad_request("invalid format"),
+    };
     let page = query.page.unwrap_or(0);
     let mut page_size = query.page_size.unwrap_or(100);
     if page_size > 1000 {
@@ -201,46 +734,101 @@ async fn search(
         Some(Err(_)) => return bad_request("invalid date"),
         None => None,
     };
+    let window = match query.window() {
+        Ok(window) => window,
+        Err(e) => return bad_request(&e),
+    };
+    let match_mode = match query.match_mode.as_deref().map(MatchMode::parse) {
+        Some(Some(m)) => m,
+        Some(None) => return bad_request("invalid match mode"),
+        None => MatchMode::from_exact(query.exact.unwrap_or(true)),
+    };
+    let sort = match query.sort.as_deref().map(SortKey::parse) {
+        Some(Some(k)) => k,
+        Some(None) => return bad_request("invalid sort"),
+        None => SortKey::default(),
+    };
+    let params = SearchParams {
+        query: SearchQuery {
+            prefix,
+            origin: query.asn,
+            max_len: query.max_len,
+            date,
+            current: query.current,
+            window,
+            match_mode,
+            tal: query.tal.clone(),
+            observed_only: query.observed_only.unwrap_or(false),
+        },
+        sort,
+    };
+    if format != SearchFormat::Json {
+        return stream_search(state, params, format).await;
+    }
+
+    let fingerprint = query.fingerprint();
+    let cursor = match query.cursor.as_deref().map(SearchCursor::decode) {
+        Some(Ok(c)) if c.query != fingerprint => {
+            return bad_request("cursor does not match the query")
+        }
+        Some(Ok(c)) => Some(c),
+        Some(Err(_)) => return bad_request("invalid cursor"),
+        None => None,
+    };
 
-    let trie = state.read().await;
+    let trie = snapshot(&state).await;
     let latest_ts = trie.latest_date_ts();
     let format_version = trie.format_version();
+    let build_id = trie.build_id();
     let latest_date = DateTime::from_timestamp(latest_ts, 0)
         .unwrap()
         .naive_utc()
         .date();
+    if cursor.as_ref().is_some_and(|c| c.build_id != build_id) {
+        return error_response(
+            StatusCode::GONE,
+            "cursor expired: the archive was updated, restart the query",
+        );
+    }
 
-    let results = trie.search(
-        prefix,
-        query.asn,
-        query.max_len,
-        date,
-        query.current,
-        query.exact.unwrap_or(true),
-    );
-
-    let total = results.len();
-
-    // Results come back in deterministic trie (lexicographic prefix) order;
-    // paginate without an additional full sort.
-    let result_entries = results
-        .iter()
-        .skip(page * page_size)
-        .take(page_size)
-        .map(|entry| RoasSearchResultEntry {
-            prefix: entry.prefix.to_string(),
-            max_len: entry.max_len,
-            asn: entry.origin,
-            date_ranges: entry
-                .dates_ranges
-                .iter()
-                .map(|(from, to)| (from.to_string(), to.to_string()))
-                .collect(),
-            current: entry.dates_ranges.iter().any(|(_from, to)| {
-                to.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() >= latest_ts
-            }),
-        })
-        .collect::<Vec<_>>();
+    // A cursor resumes after its last result; otherwise count every result
+    // and keep only the requested page.
+    let (page, total, hits, more) = match cursor {
+        Some(c) => {
+            let mut hits: Vec<SearchHit> = params
+                .run(&trie, Some(c.last), Some(page_size + 1))
+                .collect();
+            let more = hits.len() > page_size;
+            hits.truncate(page_size);
+            (c.page, None, hits, more)
+        }
+        None => {
+            let offset = page * page_size;
+            let mut total = 0;
+            let mut hits = Vec::new();
+            for hit in params.run(&trie, None, None) {
+                if total >= offset && hits.len() < page_size {
+                    hits.push(hit);
+                }
+                total += 1;
+            }
+            let more = offset + hits.len() < total;
+            (page, Some(total), hits, more)
+        }
+    };
+    let next_cursor = hits.last().filter(|_| more).map(|last| {
+        SearchCursor {
+            build_id,
+            query: fingerprint,
+            page: page + 1,
+            last: params.sort.position(last),
+        }
+        .encode()
+    });
+    let result_entries = hits
+        .into_iter()
+        .map(|hit| RoasSearchResultEntry::new(&hit.into_entry(), latest_ts))
+        .collect();
 
     Json(RoasSearchResult {
         total,
@@ -249,48 +837,336 @@ async fn search(
         meta: Some(Meta {
             latest_date: latest_date.to_string(),
             format_version,
+            build_id: format!("{:016x}", build_id),
         }),
         page,
         page_size,
+        next_cursor,
     })
     .into_response()
 }
 
+/// Parsed filters and order of a `/search` request.
+struct SearchParams {
+    query: SearchQuery,
+    sort: SortKey,
+}
+
+impl SearchParams {
+    /// The matching results in `sort` order after the result at `after`, at
+    /// most `limit` of them. Results in trie (prefix) order are produced
+    /// lazily, resuming the trie walk at `after`. Any other order still walks
+    /// every match: with a `limit` it keeps only the first `limit` after
+    /// `after` in a bounded heap, otherwise it collects them all, still
+    /// borrowed, before sorting.
+    fn run<'a>(
+        &self,
+        trie: &'a TrieBackend,
+        after: Option<SearchPosition>,
+        limit: Option<usize>,
+    ) -> Box<dyn Iterator<Item = SearchHit<'a>> + 'a> {
+        let in_trie_order = self.sort == SortKey::Prefix;
+        let hits = trie.search_iter_after(&self.query, after.clone().filter(|_| in_trie_order));
+        if in_trie_order {
+            return Box::new(hits.take(limit.unwrap_or(usize::MAX)));
+        }
+        let sort = self.sort;
+        let Some(limit) = limit else {
+            let mut hits: Vec<SearchHit> = hits.collect();
+            sort.sort(&mut hits);
+            let skip = after.map_or(0, |a| hits.partition_point(|h| sort.position(h) <= a));
+            return Box::new(hits.into_iter().skip(skip));
+        };
+        // max-heap of the first `limit` positions after `after`
+        let mut heap = BinaryHeap::with_capacity(limit.saturating_add(1).min(1024));
+        for hit in hits {
+            let position = sort.position(&hit);
+            if after.as_ref().is_some_and(|a| position <= *a) {
+                continue;
+            }
+            if heap.len() == limit && heap.peek().map_or(true, |t: &Ranked| position >= t.0) {
+                continue;
+            }
+            heap.push(Ranked(position, hit));
+            if heap.len() > limit {
+                heap.pop();
+            }
+        }
+        Box::new(heap.into_sorted_vec().into_iter().map(|r| r.1))
+    }
+}
+
+/// A search hit ordered by its [`SearchPosition`] alone.
+struct Ranked<'a>(SearchPosition, SearchHit<'a>);
+
+impl PartialEq for Ranked<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Ranked<'_> {}
+
+impl PartialOrd for Ranked<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+/// Response format of `/search`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SearchFormat {
+    /// One paginated JSON document
+    Json,
+    /// Every result, one JSON object per line
+    Ndjson,
+    /// Every result, one CSV row per line
+    Csv,
+}
+
+impl SearchFormat {
+    /// The `format` parameter if given, otherwise the `Accept` header;
+    /// `None` for an unknown `format`.
+    fn negotiate(format: Option<&str>, headers: &HeaderMap) -> Option<Self> {
+        match format {
+            Some("json") => Some(SearchFormat::Json),
+            Some("ndjson") => Some(SearchFormat::Ndjson),
+            Some("csv") => Some(SearchFormat::Csv),
+            Some(_) => None,
+            None => {
+                let accept = headers
+                    .get(header::ACCEPT)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default();
+                if accept.contains("application/x-ndjson") {
+                    Some(SearchFormat::Ndjson)
+                } else if accept.contains("text/csv") {
+                    Some(SearchFormat::Csv)
+                } else {
+                    Some(SearchFormat::Json)
+                }
+            }
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            SearchFormat::Json => "application/json",
+            SearchFormat::Ndjson => "application/x-ndjson",
+            SearchFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+/// Size of the chunks a streamed search response is sent in.
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+
+const CSV_HEADER: &str = "prefix,max_len,asn,tal,current,date_ranges,interpolated_ranges,objects,\
+                          object_count,expires,end\n";
+
+/// Stream every result of a search as NDJSON or CSV, without a page cap.
+/// Results are read from a snapshot of the archive on a blocking thread as
+/// the client consumes them, so an archive swap does not wait for running
+/// downloads; they finish on the archive they started on.
+async fn stream_search(
+    state: SharedTrie,
+    params: SearchParams,
+    format: SearchFormat,
+) -> axum::response::Response {
+    let trie = snapshot(&state).await;
+    let (tx, rx) = mpsc::channel::<Result<String, Infallible>>(4);
+    tokio::task::spawn_blocking(move || {
+        let latest_ts = trie.latest_date_ts();
+        let mut buf = String::new();
+        if format == SearchFormat::Csv {
+            buf.push_str(CSV_HEADER);
+        }
+        for hit in params.run(&trie, None, None) {
+            let entry = RoasSearchResultEntry::new(&hit.into_entry(), latest_ts);
+            match format {
+                SearchFormat::Csv => entry.write_csv_row(&mut buf),
+                _ => {
+                    buf.push_str(&serde_json::to_string(&entry).expect("entry serializes"));
+                    buf.push('\n');
+                }
+            }
+            if buf.len() >= STREAM_CHUNK_BYTES
+                && tx.blocking_send(Ok(std::mem::take(&mut buf))).is_err()
+            {
+                // client went away
+                return;
+            }
+        }
+        if !buf.is_empty() {
+            let _ = tx.blocking_send(Ok(buf));
+        }
+    });
+    (
+        [(header::CONTENT_TYPE, format.content_type())],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
 async fn validate(
     query: Query<ValidateQuery>,
     State(state): State<SharedTrie>,
 ) -> impl IntoResponse {
-    let prefix: IpNet = match query.prefix.parse() {
-        Ok(p) => p,
-        Err(_) => return bad_request("invalid prefix"),
-    };
-    let trie = state.read().await;
-    let latest_ts = trie.latest_date_ts();
-    let latest_date = DateTime::from_timestamp(latest_ts, 0)
+    if query.prefix.parse::<IpNet>().is_err() {
+        return bad_request("invalid prefix");
+    }
+    let trie = snapshot(&state).await;
+    let latest_date = DateTime::from_timestamp(trie.latest_date_ts(), 0)
         .unwrap()
         .naive_utc()
         .date();
-    let date: NaiveDate = match query.date.as_ref().map(|d| d.parse()) {
-        Some(Ok(d)) => d,
-        Some(Err(_)) => return bad_request("invalid date"),
+    let strict = query.strict.unwrap_or(false);
+    match validate_route(
+        &trie,
+        &query.prefix,
+        query.asn,
+        query.date.as_deref(),
+        latest_date,
+        strict,
+    ) {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => bad_request(e),
+    }
+}
+
+/// Validate one route; `date` defaults to `latest_date`.
+fn validate_route(
+    trie: &TrieBackend,
+    prefix: &str,
+    asn: u32,
+    date: Option<&str>,
+    latest_date: NaiveDate,
+    strict: bool,
+) -> Result<ValidateResult, &'static str> {
+    let prefix: IpNet = prefix.parse().map_err(|_| "invalid prefix")?;
+    let date: NaiveDate = match date {
+        Some(d) => d.parse().map_err(|_| "invalid date")?,
         None => latest_date,
     };
     let date_ts = date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
-
-    let result = trie.validate(&prefix, query.asn, date_ts);
-    let result_str = match result {
-        RpkiValidation::Valid => "valid",
-        RpkiValidation::Invalid => "invalid",
-        RpkiValidation::Unknown => "unknown",
-    };
-
-    Json(ValidateResult {
+    let report = trie.validate(&prefix, asn, date_ts, strict);
+    Ok(ValidateResult {
         prefix: prefix.to_string(),
-        asn: query.asn,
+        asn,
         date: date.to_string(),
-        result: result_str.to_string(),
+        strict,
+        result: report.result.to_string(),
+        vrps: report
+            .vrps
+            .into_iter()
+            .map(|v| ValidateResultVrp {
+                prefix: v.prefix.to_string(),
+                max_len: v.max_len,
+                asn: v.origin,
+                tal: v.tal,
+                status: v.status.as_str().to_string(),
+            })
+            .collect(),
     })
-    .into_response()
+}
+
+/// Largest `POST /validate` body accepted; enough for a full routing table.
+/// The body is buffered, but its items are parsed straight into
+/// [`ValidateItem`]s without an intermediate JSON tree.
+const VALIDATE_BODY_LIMIT: usize = 256 * 1024 * 1024;
+
+/// Number of items validated in parallel per response chunk.
+const VALIDATE_CHUNK_ITEMS: usize = 4096;
+
+/// Parse a `POST /validate` body: a JSON array of items, or NDJSON with one
+/// item per line. An array element or NDJSON line that is not an item
+/// becomes an error for that item only, so the results still line up with
+/// the input. Array elements are split as raw slices of the body and each
+/// is decoded on its own.
+fn parse_validate_items(body: &[u8]) -> Result<Vec<Result<ValidateItem, String>>, String> {
+    let start = body.iter().position(|b| !b.is_ascii_whitespace());
+    if start.is_some_and(|i| body[i] == b'[') {
+        let items: Vec<&RawValue> =
+            serde_json::from_slice(body).map_err(|e| format!("invalid JSON body: {}", e))?;
+        return Ok(items
+            .into_iter()
+            .map(|item| {
+                serde_json::from_str(item.get()).map_err(|e| format!("invalid item: {}", e))
+            })
+            .collect());
+    }
+    let body = std::str::from_utf8(body).map_err(|_| "body is not UTF-8".to_string())?;
+    Ok(body
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|e| format!("invalid item: {}", e)))
+        .collect())
+}
+
+/// Validate many routes in one request. The body is a JSON array or NDJSON
+/// of `{prefix, asn, date?}`; the response streams one NDJSON line per item,
+/// in request order. All items are validated against a snapshot of the
+/// archive taken when the request arrived, in parallel on the rayon pool.
+async fn validate_bulk(
+    query: Query<BulkValidateQuery>,
+    State(state): State<SharedTrie>,
+    body: Bytes,
+) -> impl IntoResponse {
+    let items = match parse_validate_items(&body) {
+        Ok(items) => items,
+        Err(e) => return bad_request(&e),
+    };
+    let strict = query.strict.unwrap_or(false);
+    let trie = snapshot(&state).await;
+    let (tx, rx) = mpsc::channel::<Result<String, Infallible>>(4);
+    tokio::task::spawn_blocking(move || {
+        let latest_date = DateTime::from_timestamp(trie.latest_date_ts(), 0)
+            .unwrap()
+            .naive_utc()
+            .date();
+        for (chunk_idx, chunk) in items.chunks(VALIDATE_CHUNK_ITEMS).enumerate() {
+            let lines: Vec<String> = chunk
+                .par_iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let index = chunk_idx * VALIDATE_CHUNK_ITEMS + i;
+                    let result = item.as_ref().map_err(String::clone).and_then(|item| {
+                        validate_route(
+                            &trie,
+                            &item.prefix,
+                            item.asn,
+                            item.date.as_deref(),
+                            latest_date,
+                            strict,
+                        )
+                        .map_err(str::to_string)
+                    });
+                    let line = match result {
+                        Ok(result) => BulkValidateLine::Result { index, result },
+                        Err(error) => BulkValidateLine::Error { index, error },
+                    };
+                    let mut line = serde_json::to_string(&line).expect("line serializes");
+                    line.push('\n');
+                    line
+                })
+                .collect();
+            if tx.blocking_send(Ok(lines.concat())).is_err() {
+                // client went away
+                return;
+            }
+        }
+    });
+    (
+        [(header::CONTENT_TYPE, SearchFormat::Ndjson.content_type())],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
 }
 
 pub async fn start_api_service(
@@ -307,8 +1183,14 @@ pub async fn start_api_service(
 
     let app = Router::new()
         .route("/search", get(search))
-        .route("/validate", get(validate))
+        .route(
+            "/validate",
+            get(validate)
+                .post(validate_bulk)
+                .layer(DefaultBodyLimit::max(VALIDATE_BODY_LIMIT)),
+        )
         .route("/health", get(health))
+        .route("/coverage", get(coverage))
         .with_state(trie_lock)
         .layer(cors_layer);
     let root_app = if root == "/" {
@@ -326,3 +1208,256 @@ pub async fn start_api_service(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RoaEntry;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// A v1 backend with `count` /24 ROAs.
+    fn test_backend(count: u32) -> TrieBackend {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let entries: Vec<RoaEntry> = (0..count)
+            .map(|i| RoaEntry {
+                tal: "test".to_string(),
+                prefix: IpNet::new(Ipv4Addr::from(0x0a00_0000 + (i << 8)).into(), 24).unwrap(),
+                max_len: 24,
+                asn: 64500,
+                date,
+                object: None,
+                expires: None,
+            })
+            .collect();
+        let mut trie = LegacyRoasTrie::new();
+        trie.process_entries(&entries, true);
+        trie.compress_dates();
+        TrieBackend::V1(trie)
+    }
+
+    async fn body_bytes(response: axum::response::Response) -> Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stream_does_not_block_archive_swap() {
+        let state: SharedTrie = Arc::new(RwLock::new(Arc::new(test_backend(5000))));
+        let params = SearchParams {
+            query: SearchQuery::default(),
+            sort: SortKey::Prefix,
+        };
+        // the body is not read yet, so the stream stalls on its full channel
+        let response = stream_search(state.clone(), params, SearchFormat::Ndjson).await;
+
+        let swap = async { *state.write().await = Arc::new(test_backend(1)) };
+        assert!(
+            timeout(Duration::from_secs(5), swap).await.is_ok(),
+            "the archive swap waited for the stream"
+        );
+        let health = timeout(Duration::from_secs(5), health(State(state.clone())))
+            .await
+            .expect("requests are served during the stream");
+        let health: serde_json::Value =
+            serde_json::from_slice(&body_bytes(health.into_response()).await).unwrap();
+        assert_eq!(health["ipv4_roas_count"], 1);
+
+        // the stream finishes on the archive it started on
+        let body = body_bytes(response).await;
+        assert_eq!(
+            body.split(|b| *b == b'\n')
+                .filter(|l| !l.is_empty())
+                .count(),
+            5000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_csv_has_every_entry_field() {
+        let state: SharedTrie = Arc::new(RwLock::new(Arc::new(test_backend(2))));
+        let params = SearchParams {
+            query: SearchQuery::default(),
+            sort: SortKey::Prefix,
+        };
+        let response = stream_search(state, params, SearchFormat::Csv).await;
+        let body = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+        let mut lines = body.lines();
+        let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+        assert_eq!(
+            &header[header.len() - 3..],
+            &["object_count", "expires", "end"]
+        );
+        let rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 2);
+        for row in rows {
+            assert_eq!(row.len(), header.len());
+            assert_eq!(&row[row.len() - 3..], &["0", "", ""]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_cursor_resumes_after_last_result() {
+        let state: SharedTrie = Arc::new(RwLock::new(Arc::new(test_backend(100))));
+        for sort in ["prefix", "origin"] {
+            let mut prefixes = Vec::new();
+            let mut cursor: Option<String> = None;
+            for page in 0..3 {
+                let query: RoasSearchQuery = serde_json::from_value(
+                    json!({"page_size": 40, "sort": sort, "cursor": cursor}),
+                )
+                .unwrap();
+                let response = search(Query(query), HeaderMap::new(), State(state.clone()))
+                    .await
+                    .into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+                let result: serde_json::Value =
+                    serde_json::from_slice(&body_bytes(response).await).unwrap();
+                assert_eq!(result["page"], page);
+                // only the first page walks every result to count them
+                let total = if page == 0 { json!(100) } else { json!(null) };
+                assert_eq!(result["total"], total);
+                prefixes.extend(
+                    result["data"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|e| e["prefix"].as_str().unwrap().to_string()),
+                );
+                cursor = result["next_cursor"].as_str().map(str::to_string);
+            }
+            assert!(cursor.is_none());
+            let expected: Vec<String> = (0..100u32)
+                .map(|i| IpNet::new(Ipv4Addr::from(0x0a00_0000 + (i << 8)).into(), 24).unwrap())
+                .map(|p| p.to_string())
+                .collect();
+            assert_eq!(prefixes, expected, "sort={}", sort);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_cursor_pages_by_sort_key() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let entries: Vec<RoaEntry> = (0..50u32)
+            .map(|i| RoaEntry {
+                tal: "test".to_string(),
+                prefix: IpNet::new(Ipv4Addr::from(0x0a00_0000 + (i << 8)).into(), 24).unwrap(),
+                max_len: 24,
+                asn: 64500 + i * 7 % 13,
+                date: start + chrono::Days::new((i * 5 % 11) as u64),
+                object: None,
+                expires: None,
+            })
+            .collect();
+        let mut trie = LegacyRoasTrie::new();
+        trie.process_entries(&entries, true);
+        trie.compress_dates();
+        let state: SharedTrie = Arc::new(RwLock::new(Arc::new(TrieBackend::V1(trie))));
+
+        let page = |query: serde_json::Value| {
+            let state = state.clone();
+            async move {
+                let query: RoasSearchQuery = serde_json::from_value(query).unwrap();
+                let response = search(Query(query), HeaderMap::new(), State(state))
+                    .await
+                    .into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+                let result: serde_json::Value =
+                    serde_json::from_slice(&body_bytes(response).await).unwrap();
+                let prefixes: Vec<String> = result["data"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|e| e["prefix"].as_str().unwrap().to_string())
+                    .collect();
+                (prefixes, result["next_cursor"].as_str().map(str::to_string))
+            }
+        };
+        for sort in ["origin", "first_seen", "last_seen"] {
+            let (expected, _) = page(json!({"page_size": 1000, "sort": sort})).await;
+            assert_eq!(expected.len(), 50);
+
+            // cursor pages keep only their own slice of the sorted results
+            let (mut prefixes, mut cursor) = page(json!({"page_size": 7, "sort": sort})).await;
+            let mut pages = 1;
+            while cursor.is_some() {
+                let (more, next) =
+                    page(json!({"page_size": 7, "sort": sort, "cursor": cursor})).await;
+                assert!(more.len() <= 7);
+                prefixes.extend(more);
+                cursor = next;
+                pages += 1;
+            }
+            assert_eq!(pages, 8, "sort={}", sort);
+            assert_eq!(prefixes, expected, "sort={}", sort);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_rejects_within_window_without_from() {
+        let state: SharedTrie = Arc::new(RwLock::new(Arc::new(test_backend(1))));
+        let query: RoasSearchQuery =
+            serde_json::from_value(json!({"until": "2024-01-01", "range": "within"})).unwrap();
+        let response = search(Query(query), HeaderMap::new(), State(state.clone()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        assert_eq!(body["error"], "range=within requires from");
+    }
+
+    #[tokio::test]
+    async fn test_bulk_validate_reports_item_errors() {
+        let state: SharedTrie = Arc::new(RwLock::new(Arc::new(test_backend(1))));
+        let bodies = [
+            r#"[{"prefix": "10.0.0.0/24", "asn": 64500}, {"prefix": "10.0.0.0/24"},
+                {"prefix": "bad", "asn": 1}, {"prefix": "10.0.0.0/24", "asn": 1}]"#,
+            "{\"prefix\": \"10.0.0.0/24\", \"asn\": 64500}\n{\"prefix\": \"10.0.0.0/24\"}\n\
+             {\"prefix\": \"bad\", \"asn\": 1}\n{\"prefix\": \"10.0.0.0/24\", \"asn\": 1}\n",
+        ];
+        for body in bodies {
+            let response = validate_bulk(
+                Query(BulkValidateQuery { strict: None }),
+                State(state.clone()),
+                Bytes::from(body),
+            )
+            .await
+            .into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            let lines: Vec<serde_json::Value> = body_bytes(response)
+                .await
+                .split(|b| *b == b'\n')
+                .filter(|l| !l.is_empty())
+                .map(|l| serde_json::from_slice(l).unwrap())
+                .collect();
+            assert_eq!(lines.len(), 4);
+            assert_eq!(lines[0]["result"], "valid");
+            assert!(lines[1]["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("invalid item"));
+            assert_eq!(lines[2]["error"], "invalid prefix");
+            assert_eq!(lines[3]["index"], 3);
+            assert_eq!(lines[3]["result"], "invalid");
+        }
+
+        // a mistyped element does not derail the ones after it
+        let items = parse_validate_items(
+            br#"[42, {"prefix": "10.0.0.0/24", "asn": "x"}, {"prefix": "10.0.0.0/24", "asn": 1}]"#,
+        )
+        .unwrap();
+        assert!(items[0].is_err() && items[1].is_err());
+        assert_eq!(items[2].as_ref().unwrap().asn, 1);
+
+        let response = validate_bulk(
+            Query(BulkValidateQuery { strict: None }),
+            State(state),
+            Bytes::from("[{\"prefix\": "),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -4,7 +4,7 @@ use tikv_jemallocator::Jemalloc;
 static ALLOC: Jemalloc = Jemalloc;
 
 use chrono::NaiveDate;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use indicatif::{ProgressBar, ProgressStyle};
 use ipnet::IpNet;
 use rayon::prelude::*;
@@ -40,12 +40,207 @@ struct Cli {
     subcommands: Opts,
 }
 
+/// Where `rebuild` and `update` read ROA snapshots from.
+#[derive(Args)]
+struct SourceOpts {
+    /// crawl a local mirror (directory or `file://` URL) with the
+    /// `<tal>.tal/YYYY/MM/DD/roas.csv.xz` layout instead of ftp.ripe.net
+    #[clap(long)]
+    mirror: Option<String>,
+
+    /// read daily VRP exports (e.g. rpki-client JSON, rpkiviews tarballs) from a
+    /// local directory instead of the RIPE archive; dates are taken from file paths.
+    /// An export is a single feed covering every TAL, so `rebuild` and `update`
+    /// do not accept `--tal` with it
+    #[clap(long, conflicts_with_all = ["mirror", "cache_dir"])]
+    vrp_dir: Option<String>,
+
+    /// read ROA files through a persistent cache directory, downloading only
+    /// the files not cached yet
+    #[clap(long)]
+    cache_dir: Option<String>,
+
+    /// use only the files already in `--cache-dir`, without any network access
+    #[clap(long, requires = "cache_dir", conflicts_with = "mirror")]
+    offline: bool,
+}
+
+impl SourceOpts {
+    /// Build the ROA source selected on the command line.
+    fn build(self, tal: Option<String>) -> Box<dyn RoaSource> {
+        if let Some(dir) = self.vrp_dir {
+            return Box::new(VrpDirectory::new(&dir, tal));
+        }
+        match self.cache_dir {
+            // the cache has the archive layout, so it can be crawled as a mirror
+            Some(cache_dir) if self.offline => Box::new(RipeArchive::new(&cache_dir, tal)),
+            Some(cache_dir) => {
+                let root = self.mirror.unwrap_or_else(|| RIPE_RPKI_ROOT.to_string());
+                Box::new(RipeArchive::new(&root, tal).with_cache(RoaCache::new(&cache_dir)))
+            }
+            None => {
+                let root = self.mirror.unwrap_or_else(|| RIPE_RPKI_ROOT.to_string());
+                Box::new(RipeArchive::new(&root, tal))
+            }
+        }
+    }
+}
+
+/// Source-anomaly checks of `rebuild` and `update`.
+#[derive(Args)]
+struct AnomalyOpts {
+    /// write a JSON report of the flagged source anomalies to this path
+    #[clap(long)]
+    anomaly_report: Option<String>,
+
+    /// hold flagged days out of the trie, carrying each record's previous-day
+    /// state forward instead of recording a mass withdrawal
+    #[clap(long)]
+    hold_out_anomalies: bool,
+
+    /// flag a day whose row count is below this fraction of the previous day's
+    #[clap(long, default_value = "0.5")]
+    drop_ratio: f64,
+}
+
+impl AnomalyOpts {
+    fn config(&self) -> AnomalyConfig {
+        AnomalyConfig {
+            drop_ratio: self.drop_ratio,
+            hold_out: self.hold_out_anomalies,
+        }
+    }
+
+    /// Write the anomaly report, if one was requested.
+    fn write_report(&self, anomalies: &[Anomaly]) {
+        if let Some(path) = &self.anomaly_report {
+            match write_anomaly_report(path, &self.config(), anomalies) {
+                Ok(()) => info!("anomaly report written to {}", path),
+                Err(e) => error!("failed to write anomaly report {}: {}", path, e),
+            }
+        }
+    }
+}
+
+/// Known-gap policy of `fix`, `update` and `serve`.
+#[derive(Args)]
+struct GapPolicyOpts {
+    /// known-gap policy file (TOML, or JSON with a `.json` suffix) to apply
+    /// instead of the built-in list
+    #[clap(long)]
+    gap_policy: Option<String>,
+}
+
+impl GapPolicyOpts {
+    fn load(&self) -> GapPolicy {
+        match &self.gap_policy {
+            None => GapPolicy::builtin(),
+            Some(path) => load_gap_policy(path),
+        }
+    }
+}
+
+/// Load a known-gap policy file, exiting if it is unreadable or invalid.
+fn load_gap_policy(path: &str) -> GapPolicy {
+    match GapPolicy::load(path) {
+        Ok(policy) => {
+            info!(
+                "loaded gap policy {} ({} intervals, sha256 {})",
+                path,
+                policy.gaps.len(),
+                policy.hash()
+            );
+            policy
+        }
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum CacheOpts {
+    /// Download ROA files into the cache without building a trie
+    Populate {
+        /// cache directory
+        #[clap(long)]
+        cache_dir: String,
+
+        /// limit to specific tal: afrinic, apnic, arin, lacnic, ripencc
+        #[clap(short, long)]
+        tal: Option<String>,
+
+        /// Date to start from, default no limit
+        #[clap(short, long)]
+        from: Option<NaiveDate>,
+
+        /// Date to stop at, default no limit
+        #[clap(short, long)]
+        until: Option<NaiveDate>,
+    },
+    /// Remove partial downloads and, optionally, cached files older than a date
+    Prune {
+        /// cache directory
+        #[clap(long)]
+        cache_dir: String,
+
+        /// remove cached files dated before this day
+        #[clap(long)]
+        before: Option<NaiveDate>,
+
+        /// limit to specific tal: afrinic, apnic, arin, lacnic, ripencc
+        #[clap(short, long)]
+        tal: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum GapsOpts {
+    /// Compare raw snapshots around candidate gap dates with the archive and
+    /// classify each date (see DEVELOPMENT.md)
+    Investigate {
+        /// single candidate date
+        #[clap(short, long, conflicts_with_all = ["from", "until"], required_unless_present = "from")]
+        date: Option<NaiveDate>,
+
+        /// first candidate date of a range
+        #[clap(short, long)]
+        from: Option<NaiveDate>,
+
+        /// last candidate date of a range, default: `--from`
+        #[clap(short, long, requires = "from")]
+        until: Option<NaiveDate>,
+
+        /// limit to specific tal: afrinic, apnic, arin, lacnic, ripencc
+        #[clap(short, long)]
+        tal: Option<String>,
+
+        /// compare against a JSONL transport (`.jsonl` or `.jsonl.gz`) instead
+        /// of the archive at PATH
+        #[clap(long)]
+        transport: Option<String>,
+
+        #[clap(flatten)]
+        source: SourceOpts,
+
+        /// a raw snapshot below this fraction of its neighbors' record count
+        /// counts as reduced
+        #[clap(long, default_value = "0.5")]
+        drop_ratio: f64,
+
+        /// write the investigation as a JSON report to this path
+        #[clap(long)]
+        report: Option<String>,
+    },
+}
+
 #[derive(Subcommand)]
 enum Opts {
     /// Rebuild the entire RPKI ROA history data from scratch
     Rebuild {
         /// limit to specific tal: afrinic, apnic, arin, lacnic, ripencc
-        #[clap(short, long)]
+        #[clap(short, long, conflicts_with = "vrp_dir")]
         tal: Option<String>,
 
         /// Number of parallel chunks
@@ -59,16 +254,50 @@ enum Opts {
         /// Date to stop at, default no limit
         #[clap(short, long)]
         until: Option<NaiveDate>,
+
+        #[clap(flatten)]
+        source: SourceOpts,
+
+        #[clap(flatten)]
+        anomalies: AnomalyOpts,
+
+        /// fail a whole ROA file on its first malformed row instead of
+        /// skipping (and counting) the bad rows
+        #[clap(long)]
+        strict: bool,
+
+        /// continue an interrupted rebuild from `<path>.checkpoint`, skipping
+        /// the files recorded in `<path>.ledger.jsonl`
+        #[clap(long)]
+        resume: bool,
+
+        /// write a checkpoint after this many processed files; 0 disables checkpoints
+        #[clap(long, default_value = "1000")]
+        checkpoint_every: usize,
     },
     /// Find new ROA files and apply changes
     Update {
         /// TAL: afrinic, apnic, arin, lacnic, ripencc; default: all
-        #[clap(short, long)]
+        #[clap(short, long, conflicts_with = "vrp_dir")]
         tal: Option<String>,
 
         /// Date to stop at, default no limit
         #[clap(short, long)]
         until: Option<NaiveDate>,
+
+        #[clap(flatten)]
+        source: SourceOpts,
+
+        #[clap(flatten)]
+        anomalies: AnomalyOpts,
+
+        /// fail a whole ROA file on its first malformed row instead of
+        /// skipping (and counting) the bad rows
+        #[clap(long)]
+        strict: bool,
+
+        #[clap(flatten)]
+        gap_policy: GapPolicyOpts,
     },
     /// Apply the known-gap policy to a JSONL transport (`.jsonl` or `.jsonl.gz`)
     Fix {
@@ -78,6 +307,9 @@ enum Opts {
         /// output JSONL transport; if omitted, overwrites the input in place
         #[clap(short, long)]
         output: Option<String>,
+
+        #[clap(flatten)]
+        gap_policy: GapPolicyOpts,
     },
     /// Search for ROAs in history
     Search {
@@ -101,13 +333,79 @@ enum Opts {
         #[clap(short, long)]
         current: Option<bool>,
 
-        /// only return exact prefix matches (default: true)
+        /// first day of a date window, format: YYYY-MM-DD
+        #[clap(long)]
+        from: Option<NaiveDate>,
+
+        /// last day of a date window, format: YYYY-MM-DD (default: latest date)
+        #[clap(long)]
+        until: Option<NaiveDate>,
+
+        /// how ROAs must match the window: overlap (default, active on any day)
+        /// or within (active on every day; requires `--from`)
+        #[clap(long)]
+        range: Option<RangeMode>,
+
+        /// only ROAs first seen on or after this day
+        #[clap(long)]
+        first_seen_from: Option<NaiveDate>,
+
+        /// only ROAs first seen on or before this day
+        #[clap(long)]
+        first_seen_until: Option<NaiveDate>,
+
+        /// only ROAs last seen on or after this day
+        #[clap(long)]
+        last_seen_from: Option<NaiveDate>,
+
+        /// only ROAs last seen on or before this day
+        #[clap(long)]
+        last_seen_until: Option<NaiveDate>,
+
+        /// how stored prefixes relate to `--prefix`: exact (default), covering,
+        /// covered_by or overlapping
+        #[clap(long = "match")]
+        match_mode: Option<MatchMode>,
+
+        /// deprecated, use `--match`: true is exact, false is overlapping
         #[clap(short, long)]
         exact: Option<bool>,
+
+        /// filter by trust anchor: afrinic, apnic, arin, lacnic, ripencc
+        #[clap(short, long)]
+        tal: Option<String>,
+
+        /// treat interpolated days as absent: they do not match `--date` or
+        /// `--current` and are left out of the date ranges
+        #[clap(long)]
+        observed_only: bool,
+
+        /// order of the results: prefix (default), origin, first_seen or last_seen
+        #[clap(long)]
+        sort: Option<SortKey>,
+    },
+    /// Show which days each TAL was ingested on, and the gaps between them
+    Coverage {
+        /// limit to specific tal: afrinic, apnic, arin, lacnic, ripencc
+        #[clap(short, long)]
+        tal: Option<String>,
+
+        /// first day to include, default no limit
+        #[clap(short, long)]
+        from: Option<NaiveDate>,
+
+        /// last day to include, default no limit
+        #[clap(short, long)]
+        until: Option<NaiveDate>,
+
+        /// list every observed day with its row count
+        #[clap(long)]
+        days: bool,
     },
-    /// Convert a legacy v1 (bincode + ipnet-trie) archive to the v2 rkyv format
+    /// Convert a legacy v1 (bincode + ipnet-trie) archive, or an rkyv archive
+    /// of an older format, to the current rkyv format
     Convert {
-        /// path to the legacy v1 archive (e.g. roas_trie.bin.gz)
+        /// path to the archive to convert (e.g. roas_trie.bin.gz or an old roas_trie.rkyv)
         #[clap(short, long)]
         from: String,
     },
@@ -117,6 +415,16 @@ enum Opts {
         #[clap(short, long)]
         output: String,
     },
+    /// Investigate candidate historical data gaps
+    Gaps {
+        #[clap(subcommand)]
+        command: GapsOpts,
+    },
+    /// Manage the on-disk cache of downloaded ROA files
+    Cache {
+        #[clap(subcommand)]
+        command: CacheOpts,
+    },
     /// Serve the API
     Serve {
         /// Additional path to backup the trie
@@ -132,6 +440,9 @@ enum Opts {
         /// update interval in seconds between data refreshes (default: 8 hours)
         #[clap(short, long, default_value = "28800")]
         update_interval: u64,
+
+        #[clap(flatten)]
+        gap_policy: GapPolicyOpts,
     },
 }
 
@@ -172,6 +483,14 @@ fn auto_convert(bin_path: &str, rkyv_path: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn parse_mode(strict: bool) -> ParseMode {
+    if strict {
+        ParseMode::Strict
+    } else {
+        ParseMode::default()
+    }
+}
+
 fn export_archive(path: &str, output: &str) -> anyhow::Result<()> {
     if !is_rkyv_path(path) {
         anyhow::bail!("JSONL export requires a v2 .rkyv archive: {path}");
@@ -181,11 +500,56 @@ fn export_archive(path: &str, output: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Import a JSONL transport, apply `KNOWN_GAPS_STR`, and export a new JSONL transport.
+/// Compare the raw snapshots of `source` around `from..=until` with `trie`,
+/// print the counts and classification of each date, and optionally write
+/// the JSON report.
+fn investigate_gaps(
+    trie: &RoasTrie,
+    archive_name: &str,
+    source: &dyn RoaSource,
+    from: NaiveDate,
+    until: NaiveDate,
+    drop_ratio: f64,
+    report: Option<&str>,
+) -> anyhow::Result<()> {
+    let mut investigator = GapInvestigator::new(trie, source, drop_ratio);
+    let investigations = investigator.investigate_range(from, until);
+    for inv in &investigations {
+        println!(
+            "{}: {}{}",
+            inv.date,
+            inv.classification.as_str(),
+            if inv.synthetic_interpolation() {
+                " (archive state is synthetic interpolation)"
+            } else {
+                ""
+            }
+        );
+        let rows: Vec<GapDayCountsTabled> = inv.counts.iter().map(|c| c.into()).collect();
+        println!("{}", Table::new(rows).with(Style::markdown()));
+        for (tal, status) in &inv.statuses {
+            println!("  {}: {}", tal, status.as_str());
+        }
+        println!();
+    }
+    if let Some(report) = report {
+        write_gap_report(
+            report,
+            archive_name,
+            &source.name(),
+            drop_ratio,
+            &investigations,
+        )?;
+        info!("gap report written to {}", report);
+    }
+    Ok(())
+}
+
+/// Import a JSONL transport, apply `policy`, and export a new JSONL transport.
 /// Uses a temporary rkyv archive internally; it is deleted after export.
-fn fix_jsonl_transport(input: &str, output: &str) -> anyhow::Result<()> {
+fn fix_jsonl_transport(input: &str, output: &str, policy: &GapPolicy) -> anyhow::Result<()> {
     let mut trie = RoasTrieMut::from_jsonl(input)?;
-    trie.fill_gaps();
+    trie.fill_gaps(policy);
 
     let archive_path = std::env::temp_dir().join(format!(
         "wayback-rpki-fix-{}-{}.rkyv",
@@ -239,18 +603,45 @@ fn main() {
             chunks_opt,
             from,
             until,
+            source,
+            anomalies,
+            strict,
+            resume,
+            checkpoint_every,
         } => {
+            let mode = parse_mode(strict);
             let chunks = chunks_opt.unwrap_or_else(num_threads);
-            let all_files = get_tal_urls(tal)
-                .into_iter()
-                .flat_map(|tal_url| crawl_tal_after(tal_url.as_str(), from, until))
-                .collect::<Vec<RoaFile>>();
+            let source = source.build(tal);
+            let mut all_files = source.list_all_snapshots(from, until);
+
+            let checkpoint = RebuildCheckpoint::new(&path);
+            let trie = if resume && checkpoint.exists() {
+                let (trie, processed) = checkpoint.load().unwrap();
+                all_files.retain(|f| !processed.contains(&(f.tal.clone(), f.file_date)));
+                trie
+            } else {
+                if resume {
+                    warn!(
+                        "no checkpoint found at {}, starting from scratch",
+                        checkpoint.checkpoint_path()
+                    );
+                } else if checkpoint.exists() {
+                    warn!(
+                        "discarding existing checkpoint {}; pass --resume to continue from it",
+                        checkpoint.checkpoint_path()
+                    );
+                }
+                checkpoint.clear().unwrap();
+                RoasTrieMut::new()
+            };
+            let trie = trie.with_anomaly_config(anomalies.config());
 
             info!("total of {} roa files to process", all_files.len());
 
-            let (sender_pb, receiver_pb) = std::sync::mpsc::sync_channel::<(String, i32)>(20);
+            let (sender_pb, receiver_pb) =
+                std::sync::mpsc::sync_channel::<(RoaFile, anyhow::Result<ParsedSnapshot>)>(20);
             let (sender_entries, receiver_entries) =
-                std::sync::mpsc::sync_channel::<Vec<RoaEntry>>(2000);
+                std::sync::mpsc::sync_channel::<(RoaFile, Result<Vec<RoaEntry>, String>)>(2000);
 
             let total_files = all_files.len();
 
@@ -263,67 +654,119 @@ fn main() {
                 .progress_chars("##-");
             pb.set_style(sty);
 
-            // dedicated thread for showing progress of the parsing
-            thread::spawn(move || {
-                let mut writer = oneio::get_writer("wayback-rpki.bootstrap.log").unwrap();
-                for (url, _count) in receiver_pb.iter() {
-                    writeln!(writer, "{}", url).unwrap();
-                    pb.set_message(url);
+            // dedicated thread for showing progress of the parsing and
+            // collecting the summary; forwards parsed entries, or the error
+            // of a failed file, to the writer
+            let pb_handle = thread::spawn(move || {
+                let mut summary = IngestSummary::default();
+                for (mut file, result) in receiver_pb.iter() {
+                    summary.record(&file, &result);
+                    pb.set_message(file.url.clone());
                     pb.inc(1);
+                    let entries = match result {
+                        Ok(snapshot) => {
+                            file.rows_count = snapshot.entries.len() as i32;
+                            Ok(snapshot.entries)
+                        }
+                        Err(e) => Err(e.to_string()),
+                    };
+                    sender_entries.send((file, entries)).unwrap();
                 }
+                summary
             });
 
-            // dedicated writer thread
+            // dedicated writer thread; checkpoints the builder together with
+            // the files it has absorbed since the previous checkpoint
             let path2 = path.clone();
             let handle = thread::spawn(move || {
-                let mut trie = RoasTrieMut::new();
-                for entries in receiver_entries.iter() {
+                let mut trie = trie;
+                let mut pending: Vec<RoaFile> = Vec::new();
+                for (mut file, entries) in receiver_entries.iter() {
+                    // failed files are queued in the archive for `update` to
+                    // retry, and kept out of the ledger so a resume retries them
+                    let entries = match entries {
+                        Ok(entries) => entries,
+                        Err(e) => {
+                            trie.record_failure(&file, &e);
+                            continue;
+                        }
+                    };
                     trie.process_entries(&entries, true);
+                    trie.record_feed_tals(&file.tal, &entries);
+                    trie.mark_ingested(&file.tal, file.file_date);
+                    trie.record_observation(&file.tal, file.file_date, entries.len() as u64);
+                    file.processed = true;
+                    pending.push(file);
+                    if checkpoint_every > 0 && pending.len() >= checkpoint_every {
+                        match checkpoint.save(&mut trie, &pending) {
+                            Ok(()) => pending.clear(),
+                            Err(e) => warn!("failed to write checkpoint: {}", e),
+                        }
+                    }
                 }
+                // files arrive out of order, so anomalies are checked once
+                // the whole calendar is known
+                let flagged = trie.check_anomalies(|_, _| true);
                 trie.dump(path2.as_str()).unwrap();
+                if let Err(e) = checkpoint.clear() {
+                    warn!("failed to remove checkpoint files: {}", e);
+                }
                 info!(
                     "bootstrap finished: {} prefixes written to {}",
                     trie.len(),
                     path2
                 );
+                (trie.failed_files().to_vec(), flagged)
             });
 
-            all_files.par_chunks(chunks).for_each_with(
-                (sender_pb, sender_entries),
-                |(s_pb, s_entries), files| {
+            all_files
+                .par_chunks(chunks)
+                .for_each_with(sender_pb, |s_pb, files| {
                     for file in files {
-                        let url: &str = file.url.as_str();
-                        match parse_roas_csv(url) {
-                            Ok(roas) => {
-                                let count = roas.len() as i32;
-                                s_entries.send(roas).unwrap();
-                                s_pb.send((url.to_owned(), count)).unwrap();
-                            }
-                            Err(e) => {
-                                warn!("failed to parse {}: {}", url, e);
-                                s_pb.send((url.to_owned(), 0)).unwrap();
-                            }
-                        }
+                        let result = source.read_snapshot_with(file, mode);
+                        s_pb.send((file.clone(), result)).unwrap();
                     }
-                },
-            );
+                });
 
-            handle.join().unwrap();
+            let mut summary = pb_handle.join().unwrap();
+            let (failed_files, flagged) = handle.join().unwrap();
+            summary.count_retries(&failed_files);
+            summary.anomalies.extend(flagged);
+            anomalies.write_report(&summary.anomalies);
+            info!("rebuild summary: {}", summary);
         }
 
-        Opts::Update { tal, until } => {
+        Opts::Update {
+            tal,
+            until,
+            source,
+            anomalies,
+            strict,
+            gap_policy,
+        } => {
             check_bootstrap_and_download(&path, opts.bootstrap);
             ensure_data_available(&path);
 
-            if is_rkyv_path(&path) {
-                let mut trie = RoasTrieMut::load(&path).unwrap();
-                trie.update(tal, until).unwrap();
+            let source = source.build(tal);
+            let mode = parse_mode(strict);
+            let gap_policy = gap_policy.load();
+            let summary = if is_rkyv_path(&path) {
+                let mut trie = RoasTrieMut::load(&path)
+                    .unwrap()
+                    .with_anomaly_config(anomalies.config());
+                let summary = trie.update(source.as_ref(), until, mode).unwrap();
+                trie.fill_gaps(&gap_policy);
                 trie.dump(&path).unwrap();
+                summary
             } else {
                 let mut trie = wayback_rpki::legacy::LegacyRoasTrie::load(&path).unwrap();
-                trie.update(tal, until).unwrap();
+                let summary = trie.update(source.as_ref(), until, mode).unwrap();
+                trie.fill_gaps(&gap_policy);
                 trie.dump(&path).unwrap();
-            }
+                summary
+            };
+            anomalies.write_report(&summary.anomalies);
+            info!("update summary: {}", summary);
         }
 
         Opts::Search {
@@ -332,28 +775,225 @@ fn main() {
             max_len,
             date,
             current,
+            from,
+            until,
+            range,
+            first_seen_from,
+            first_seen_until,
+            last_seen_from,
+            last_seen_until,
+            match_mode,
             exact,
+            tal,
+            observed_only,
+            sort,
         } => {
             check_bootstrap_and_download(&path, opts.bootstrap);
             ensure_data_available(&path);
+            let match_mode =
+                match_mode.unwrap_or_else(|| MatchMode::from_exact(exact.unwrap_or(true)));
+            let window = DateRangeFilter {
+                from,
+                until,
+                mode: range.unwrap_or_default(),
+                first_seen_from,
+                first_seen_until,
+                last_seen_from,
+                last_seen_until,
+            };
+            if window.check().is_err() {
+                error!("--range within requires --from");
+                exit(1);
+            }
+
+            let query = SearchQuery {
+                prefix,
+                origin: asn,
+                max_len,
+                date,
+                current,
+                window,
+                match_mode,
+                tal,
+                observed_only,
+            };
 
             let results: Vec<RoasLookupEntryTabled> = if is_rkyv_path(&path) {
                 let trie = RoasTrie::open(&path).unwrap();
-                trie.search(prefix, asn, max_len, date, current, exact.unwrap_or(true))
-                    .into_iter()
-                    .map(|e| e.into())
-                    .collect()
+                let mut records: Vec<RoaRecordRef> = trie.search_iter(&query).collect();
+                sort.unwrap_or_default().sort(&mut records);
+                records.iter().map(|r| r.to_entry().into()).collect()
             } else {
                 let trie = wayback_rpki::legacy::LegacyRoasTrie::load(&path).unwrap();
-                trie.search(prefix, asn, max_len, date, current, exact.unwrap_or(true))
-                    .into_iter()
-                    .map(|e| e.into())
-                    .collect()
+                let mut entries = trie.search(&query);
+                sort.unwrap_or_default().sort(&mut entries);
+                entries.into_iter().map(|e| e.into()).collect()
             };
             println!("{}", Table::new(results).with(Style::markdown()));
         }
 
-        Opts::Fix { input, output } => {
+        Opts::Coverage {
+            tal,
+            from,
+            until,
+            days,
+        } => {
+            check_bootstrap_and_download(&path, opts.bootstrap);
+            ensure_data_available(&path);
+            if !is_rkyv_path(&path) {
+                error!(
+                    "v1 archives do not record an observation calendar: {}",
+                    path
+                );
+                exit(1);
+            }
+
+            let trie = RoasTrie::open(&path).unwrap();
+            let coverage = summarize_coverage(&trie.observations(), tal.as_deref(), from, until);
+            let rows: Vec<TalCoverageTabled> = coverage.iter().map(|c| c.into()).collect();
+            println!("{}", Table::new(rows).with(Style::markdown()));
+            for c in &coverage {
+                for (from, to) in c.missing_ranges() {
+                    println!("{}: missing {} .. {}", c.tal, from, to);
+                }
+            }
+            for (held_tal, date) in trie.held_out_days() {
+                if tal.as_ref().map_or(true, |t| *t == held_tal)
+                    && from.map_or(true, |f| date >= f)
+                    && until.map_or(true, |u| date <= u)
+                {
+                    println!("{}: held out {}", held_tal, date);
+                }
+            }
+            if days {
+                let days: Vec<Observation> = coverage
+                    .iter()
+                    .flat_map(|c| {
+                        c.days.iter().map(|(date, rows)| Observation {
+                            tal: c.tal.clone(),
+                            date: *date,
+                            rows: *rows,
+                        })
+                    })
+                    .collect();
+                println!("{}", Table::new(days).with(Style::markdown()));
+            }
+        }
+
+        Opts::Gaps { command } => match command {
+            GapsOpts::Investigate {
+                date,
+                from,
+                until,
+                tal,
+                transport,
+                source,
+                drop_ratio,
+                report,
+            } => {
+                let from = date.or(from).expect("clap requires --date or --from");
+                let until = date.or(until).unwrap_or(from);
+                let source = source.build(tal);
+
+                let (trie, archive_name, temp_archive) = match &transport {
+                    Some(transport) => {
+                        let archive_path = std::env::temp_dir()
+                            .join(format!("wayback-rpki-gaps-{}.rkyv", std::process::id()));
+                        let archive_path = archive_path.to_string_lossy().to_string();
+                        let opened = RoasTrieMut::from_jsonl(transport)
+                            .and_then(|mut t| t.dump(&archive_path))
+                            .and_then(|_| RoasTrie::open(&archive_path));
+                        match opened {
+                            Ok(trie) => (trie, transport.clone(), Some(archive_path)),
+                            Err(e) => {
+                                error!("failed to load transport {}: {}", transport, e);
+                                exit(1);
+                            }
+                        }
+                    }
+                    None => {
+                        check_bootstrap_and_download(&path, opts.bootstrap);
+                        ensure_data_available(&path);
+                        if !is_rkyv_path(&path) {
+                            error!("gap investigation requires a v2 .rkyv archive: {}", path);
+                            exit(1);
+                        }
+                        (RoasTrie::open(&path).unwrap(), path.clone(), None)
+                    }
+                };
+
+                let result = investigate_gaps(
+                    &trie,
+                    &archive_name,
+                    source.as_ref(),
+                    from,
+                    until,
+                    drop_ratio,
+                    report.as_deref(),
+                );
+                if let Some(temp_archive) = temp_archive {
+                    let _ = std::fs::remove_file(temp_archive);
+                }
+                if let Err(e) = result {
+                    error!("gap investigation failed: {}", e);
+                    exit(1);
+                }
+            }
+        },
+
+        Opts::Cache { command } => match command {
+            CacheOpts::Populate {
+                cache_dir,
+                tal,
+                from,
+                until,
+            } => {
+                let cache = RoaCache::new(&cache_dir);
+                let files = RipeArchive::new(RIPE_RPKI_ROOT, tal).list_all_snapshots(from, until);
+                let missing: Vec<&RoaFile> = files
+                    .iter()
+                    .filter(|f| !cache.contains(&f.tal, f.file_date))
+                    .collect();
+                info!(
+                    "{} of {} roa files not cached yet, downloading into {}",
+                    missing.len(),
+                    files.len(),
+                    cache_dir
+                );
+                let failed = missing
+                    .par_iter()
+                    .filter(|file| match cache.fetch(file) {
+                        Ok(_) => false,
+                        Err(e) => {
+                            warn!("{}", e);
+                            true
+                        }
+                    })
+                    .count();
+                info!(
+                    "cache populated: {} file(s) downloaded, {} failed",
+                    missing.len() - failed,
+                    failed
+                );
+            }
+            CacheOpts::Prune {
+                cache_dir,
+                before,
+                tal,
+            } => {
+                if let Err(e) = RoaCache::new(&cache_dir).prune(before, tal.as_deref()) {
+                    error!("failed to prune cache {}: {}", cache_dir, e);
+                    exit(1);
+                }
+            }
+        },
+
+        Opts::Fix {
+            input,
+            output,
+            gap_policy,
+        } => {
+            let policy = gap_policy.load();
             let output = output.unwrap_or_else(|| input.clone());
             if input == output {
                 // In-place: write to a temp file (preserving extension for oneio
@@ -371,7 +1011,7 @@ fn main() {
                     suffix
                 ));
                 let tmp_str = tmp_path.to_str().expect("temporary path is valid UTF-8");
-                if let Err(e) = fix_jsonl_transport(&input, tmp_str) {
+                if let Err(e) = fix_jsonl_transport(&input, tmp_str, &policy) {
                     error!("failed to generate fixed JSONL transport: {e}");
                     let _ = std::fs::remove_file(tmp_str);
                     exit(1);
@@ -383,7 +1023,7 @@ fn main() {
                 }
                 info!("fixed JSONL transport written in-place to {output}");
             } else {
-                if let Err(e) = fix_jsonl_transport(&input, &output) {
+                if let Err(e) = fix_jsonl_transport(&input, &output, &policy) {
                     error!("failed to generate fixed JSONL transport: {e}");
                     exit(1);
                 }
@@ -392,10 +1032,18 @@ fn main() {
         }
 
         Opts::Convert { from } => {
-            let legacy = wayback_rpki::legacy::LegacyRoasTrie::load(&from).unwrap();
-            let mut builder = legacy.to_builder();
-            builder.dump(&path).unwrap();
-            info!("converted legacy archive {} -> {}", from, path);
+            let builder = if is_rkyv_path(&from) {
+                RoasTrieMut::load(&from)
+            } else {
+                wayback_rpki::legacy::LegacyRoasTrie::load(&from).map(|t| t.to_builder())
+            };
+            match builder.and_then(|mut b| b.dump(&path)) {
+                Ok(()) => info!("converted archive {} -> {}", from, path),
+                Err(e) => {
+                    error!("failed to convert {}: {}", from, e);
+                    exit(1);
+                }
+            }
         }
 
         Opts::Export { output } => match export_archive(&path, &output) {
@@ -411,7 +1059,9 @@ fn main() {
             host,
             port,
             update_interval,
+            gap_policy,
         } => {
+            let gap_policy = gap_policy.load();
             let mut backup_destinations = vec![backup_to];
             if let Ok(p) = std::env::var("WAYBACK_BACKUP_TO") {
                 backup_destinations.push(Some(p));
@@ -431,12 +1081,19 @@ fn main() {
 
             let backend = if is_rkyv_path(&path) {
                 info!("opening v2 rkyv archive (mmap mode): {}", path);
-                TrieBackend::V2(RoasTrie::open(&path).unwrap())
+                RoasTrie::open(&path).map(TrieBackend::V2)
             } else {
                 info!("opening v1 legacy archive (in-memory mode): {}", path);
-                TrieBackend::V1(wayback_rpki::legacy::LegacyRoasTrie::load(&path).unwrap())
+                wayback_rpki::legacy::LegacyRoasTrie::load(&path).map(TrieBackend::V1)
             };
-            let trie_lock: Arc<RwLock<TrieBackend>> = Arc::new(RwLock::new(backend));
+            let backend = match backend {
+                Ok(backend) => backend,
+                Err(e) => {
+                    error!("failed to open {}: {}", path, e);
+                    exit(1);
+                }
+            };
+            let trie_lock: SharedTrie = Arc::new(RwLock::new(Arc::new(backend)));
             let timer_lock = trie_lock.clone();
             let serve_path = path.clone();
 
@@ -456,8 +1113,9 @@ fn main() {
                             RoasTrieMut::load(&rkyv_path)
                                 .map_err(|e| e.to_string())
                                 .and_then(|mut t| {
-                                    t.update(None, None).map_err(|e| e.to_string())?;
-                                    t.fill_gaps();
+                                    t.update(&RipeArchive::default(), None, ParseMode::default())
+                                        .map_err(|e| e.to_string())?;
+                                    t.fill_gaps(&gap_policy);
                                     t.dump(&rkyv_path).map_err(|e| e.to_string())?;
                                     Ok(())
                                 })
@@ -465,8 +1123,9 @@ fn main() {
                             wayback_rpki::legacy::LegacyRoasTrie::load(&rkyv_path)
                                 .map_err(|e| e.to_string())
                                 .and_then(|mut t| {
-                                    t.update(None, None).map_err(|e| e.to_string())?;
-                                    t.fill_gaps();
+                                    t.update(&RipeArchive::default(), None, ParseMode::default())
+                                        .map_err(|e| e.to_string())?;
+                                    t.fill_gaps(&gap_policy);
                                     t.dump(&rkyv_path).map_err(|e| e.to_string())?;
                                     Ok(())
                                 })
@@ -525,7 +1184,7 @@ fn main() {
                         };
                         if let Some(b) = new_backend {
                             let mut write_lock = timer_lock.write().await;
-                            *write_lock = b;
+                            *write_lock = Arc::new(b);
                             drop(write_lock);
                             info!("serving trie swapped");
                         }
@@ -713,6 +1372,24 @@ mod tests {
         ))
     }
 
+    #[test]
+    fn cli_definition_is_valid() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn update_rejects_tal_filter_on_vrp_dir() {
+        let args = |tal: &[&'static str]| {
+            ["wayback-rpki", "roas.rkyv", "update", "--vrp-dir", "vrps"]
+                .into_iter()
+                .chain(tal.iter().copied())
+                .collect::<Vec<_>>()
+        };
+        assert!(Cli::try_parse_from(args(&["--tal", "ripencc"])).is_err());
+        assert!(Cli::try_parse_from(args(&[])).is_ok());
+    }
+
     #[test]
     fn jsonl_round_trip_preserves_data() {
         // Build a small trie via direct record insertion, export to JSONL.gz,
@@ -752,15 +1429,27 @@ mod tests {
 
         // Compare results
         let prefix: IpNet = "1.1.1.0/24".parse().unwrap();
-        let orig = trie.search(Some(prefix), None, None, None, None, true);
-        let new = trie2.search(Some(prefix), None, None, None, None, true);
+        let orig = trie.search(&SearchQuery {
+            prefix: Some(prefix),
+            ..Default::default()
+        });
+        let new = trie2.search(&SearchQuery {
+            prefix: Some(prefix),
+            ..Default::default()
+        });
         assert_eq!(orig.len(), 1);
         assert_eq!(orig, new);
         assert_eq!(orig[0].origin, 13335);
 
         let prefix2: IpNet = "8.8.8.0/24".parse().unwrap();
-        let orig2 = trie.search(Some(prefix2), None, None, None, None, true);
-        let new2 = trie2.search(Some(prefix2), None, None, None, None, true);
+        let orig2 = trie.search(&SearchQuery {
+            prefix: Some(prefix2),
+            ..Default::default()
+        });
+        let new2 = trie2.search(&SearchQuery {
+            prefix: Some(prefix2),
+            ..Default::default()
+        });
         assert_eq!(orig2, new2);
         assert_eq!(orig2[0].origin, 15169);
 
@@ -822,11 +1511,19 @@ mod tests {
             r#"{"p":"1.1.1.0/24","m":24,"o":13335,"r":[[1687478400,1687478400],[1687651200,1687651200]]}"#,
         )?;
 
-        fix_jsonl_transport(input.to_str().unwrap(), output.to_str().unwrap())?;
+        fix_jsonl_transport(
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+            &GapPolicy::builtin(),
+        )?;
 
         let contents = std::fs::read_to_string(&output)?;
-        let record: serde_json::Value = serde_json::from_str(contents.trim())?;
+        let lines: Vec<&str> = contents.lines().collect();
+        let record: serde_json::Value = serde_json::from_str(lines[0])?;
         assert_eq!(record["r"], serde_json::json!([[1687478400, 1687651200]]));
+        // the applied policy is recorded after the records
+        let policy: serde_json::Value = serde_json::from_str(lines[1])?;
+        assert_eq!(policy["g"], GapPolicy::builtin().hash());
 
         let _ = std::fs::remove_file(input);
         let _ = std::fs::remove_file(output);
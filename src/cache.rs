@@ -0,0 +1,276 @@
+//! Persistent on-disk cache of downloaded RIPE `roas.csv.xz` files.
+//!
+//! The cache mirrors the archive layout, `<cache>/<tal>.tal/YYYY/MM/DD/roas.csv.xz`,
+//! so a populated cache can also be crawled directly as a local mirror when
+//! rebuilding without network access.
+
+use crate::{__list_local_dirs, is_remote_url, local_path, RoaFile};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+const ROAS_FILE_NAME: &str = "roas.csv.xz";
+
+/// First bytes of an xz stream.
+const XZ_HEADER_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+/// Last bytes of an xz stream.
+const XZ_FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+
+/// A directory of cached ROA files keyed by TAL and date.
+#[derive(Debug, Clone)]
+pub struct RoaCache {
+    dir: PathBuf,
+}
+
+/// Files and bytes removed by [`RoaCache::prune`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PruneSummary {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl RoaCache {
+    pub fn new(dir: &str) -> Self {
+        RoaCache {
+            dir: PathBuf::from(local_path(dir)),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Location of the cached file for `tal` on `date`, whether or not it exists.
+    pub fn path_for(&self, tal: &str, date: NaiveDate) -> PathBuf {
+        self.dir
+            .join(format!("{}.tal", tal))
+            .join(date.format("%Y/%m/%d").to_string())
+            .join(ROAS_FILE_NAME)
+    }
+
+    pub fn contains(&self, tal: &str, date: NaiveDate) -> bool {
+        self.path_for(tal, date).is_file()
+    }
+
+    /// Return a local path holding `file`, downloading it into the cache first
+    /// if it is remote and not cached yet. Local files are returned as-is.
+    ///
+    /// Downloads go to a temporary `.part` file that is renamed into place
+    /// only if it holds a complete xz stream, so an interrupted download never
+    /// leaves a truncated cache entry behind. A cached file that is not a
+    /// complete xz stream is evicted and downloaded again.
+    pub fn fetch(&self, file: &RoaFile) -> Result<PathBuf> {
+        if !is_remote_url(&file.url) {
+            return Ok(PathBuf::from(local_path(&file.url)));
+        }
+        let path = self.path_for(&file.tal, file.file_date);
+        if path.is_file() {
+            if is_complete_xz(&path) {
+                debug!("cache hit: {}", path.display());
+                return Ok(path);
+            }
+            warn!("evicting incomplete cached file {}", path.display());
+            self.evict(&file.tal, file.file_date)?;
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let part = path.with_extension(format!("xz.{}.part", std::process::id()));
+        let part_str = part
+            .to_str()
+            .ok_or_else(|| anyhow!("cache path is not valid UTF-8: {}", part.display()))?;
+        if let Err(e) = oneio::download(&file.url, part_str) {
+            let _ = std::fs::remove_file(&part);
+            return Err(anyhow!("failed to download {}: {}", file.url, e));
+        }
+        if !is_complete_xz(&part) {
+            let _ = std::fs::remove_file(&part);
+            return Err(anyhow!(
+                "failed to download {}: not a complete xz file",
+                file.url
+            ));
+        }
+        std::fs::rename(&part, &path)?;
+        debug!("cached {} → {}", file.url, path.display());
+        Ok(path)
+    }
+
+    /// Remove the cached file for `tal` on `date`, e.g. after it failed to
+    /// parse, so that the next [`RoaCache::fetch`] downloads it again.
+    /// Returns whether there was a file to remove.
+    pub fn evict(&self, tal: &str, date: NaiveDate) -> Result<bool> {
+        match std::fs::remove_file(self.path_for(tal, date)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Remove leftover partial downloads, and cached files dated before
+    /// `before` when given, optionally only for one `tal`. Directories left
+    /// empty are removed as well.
+    pub fn prune(&self, before: Option<NaiveDate>, tal: Option<&str>) -> Result<PruneSummary> {
+        let mut summary = PruneSummary::default();
+        let read_dir = match std::fs::read_dir(&self.dir) {
+            Ok(r) => r,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(summary),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in read_dir.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let entry_tal = match name.strip_suffix(".tal") {
+                Some(t) => t.to_string(),
+                None => continue,
+            };
+            if tal.is_some_and(|t| t != entry_tal) {
+                continue;
+            }
+            let tal_dir = entry.path().to_string_lossy().to_string();
+            for year in __list_local_dirs(&tal_dir, 4) {
+                let year_dir = format!("{}/{}", tal_dir, year);
+                for month in __list_local_dirs(&year_dir, 2) {
+                    let month_dir = format!("{}/{}", year_dir, month);
+                    for day in __list_local_dirs(&month_dir, 2) {
+                        let day_dir = format!("{}/{}", month_dir, day);
+                        let date = NaiveDate::parse_from_str(
+                            &format!("{}-{}-{}", year, month, day),
+                            "%Y-%m-%d",
+                        )
+                        .ok();
+                        let expired = before.is_some_and(|b| date.is_some_and(|d| d < b));
+                        prune_day(Path::new(&day_dir), expired, &mut summary)?;
+                        let _ = std::fs::remove_dir(&day_dir);
+                    }
+                    let _ = std::fs::remove_dir(&month_dir);
+                }
+                let _ = std::fs::remove_dir(&year_dir);
+            }
+        }
+
+        info!(
+            "pruned {} file(s), {:.1} MB from {}",
+            summary.files,
+            summary.bytes as f64 / 1024.0 / 1024.0,
+            self.dir.display()
+        );
+        Ok(summary)
+    }
+}
+
+/// Whether `path` starts and ends like an xz stream. Catches truncated and
+/// non-xz files (e.g. an HTML error page) without decompressing them.
+fn is_complete_xz(path: &Path) -> bool {
+    let check = || -> std::io::Result<bool> {
+        let mut f = std::fs::File::open(path)?;
+        let mut header = [0u8; 6];
+        f.read_exact(&mut header)?;
+        let mut footer = [0u8; 2];
+        f.seek(SeekFrom::End(-2))?;
+        f.read_exact(&mut footer)?;
+        Ok(header == XZ_HEADER_MAGIC && footer == XZ_FOOTER_MAGIC)
+    };
+    check().unwrap_or(false)
+}
+
+/// Remove partial downloads in a day directory, and the cached file itself
+/// when `expired`.
+fn prune_day(dir: &Path, expired: bool, summary: &mut PruneSummary) -> Result<()> {
+    for entry in std::fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.ends_with(".part") || (expired && name == ROAS_FILE_NAME) {
+            summary.bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            std::fs::remove_file(entry.path())?;
+            summary.files += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_removes_old_and_partial_files() {
+        let dir = std::env::temp_dir().join(format!("wayback-rpki-cache-{}", std::process::id()));
+        let cache = RoaCache::new(dir.to_str().unwrap());
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        for (tal, d) in [("ripencc", 1), ("ripencc", 2), ("apnic", 1)] {
+            let path = cache.path_for(tal, day(d));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, b"cached").unwrap();
+        }
+        let part = cache
+            .path_for("ripencc", day(2))
+            .with_extension("xz.1.part");
+        std::fs::write(&part, b"partial").unwrap();
+
+        // local files are never copied into the cache
+        let local = RoaFile {
+            url: "/mirror/ripencc.tal/2024/01/03/roas.csv.xz".to_string(),
+            tal: "ripencc".to_string(),
+            file_date: day(3),
+            rows_count: 0,
+            processed: false,
+        };
+        assert_eq!(cache.fetch(&local).unwrap(), PathBuf::from(&local.url));
+        assert!(!cache.contains("ripencc", day(3)));
+
+        let summary = cache.prune(Some(day(2)), Some("ripencc")).unwrap();
+        assert_eq!(summary.files, 2);
+        assert!(!cache.contains("ripencc", day(1)));
+        assert!(!dir.join("ripencc.tal/2024/01/01").exists());
+        assert!(cache.contains("ripencc", day(2)));
+        assert!(!part.exists());
+        assert!(cache.contains("apnic", day(1)));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_fetch_evicts_incomplete_cached_file() {
+        let dir =
+            std::env::temp_dir().join(format!("wayback-rpki-cache-corrupt-{}", std::process::id()));
+        let cache = RoaCache::new(dir.to_str().unwrap());
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        // a local port nothing listens on, so the download fails without DNS
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let file = RoaFile {
+            url: format!(
+                "http://127.0.0.1:{}/ripencc.tal/2024/01/01/roas.csv.xz",
+                port
+            ),
+            tal: "ripencc".to_string(),
+            file_date: date,
+            rows_count: 0,
+            processed: false,
+        };
+        // a download truncated by an older version: the header is intact,
+        // but the stream does not end in the footer magic
+        let path = cache.path_for("ripencc", date);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, XZ_HEADER_MAGIC).unwrap();
+
+        // the planted file is not served; fetching downloads it again
+        let err = cache.fetch(&file).unwrap_err().to_string();
+        assert!(err.starts_with("failed to download"), "{}", err);
+        assert!(!cache.contains("ripencc", date));
+
+        let mut complete = XZ_HEADER_MAGIC.to_vec();
+        complete.extend_from_slice(&XZ_FOOTER_MAGIC);
+        std::fs::write(&path, complete).unwrap();
+        assert_eq!(cache.fetch(&file).unwrap(), path);
+        assert!(cache.evict("ripencc", date).unwrap());
+        assert!(!cache.evict("ripencc", date).unwrap());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
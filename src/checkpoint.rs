@@ -0,0 +1,209 @@
+//! Checkpoints for resumable `rebuild` runs.
+//!
+//! A checkpoint is the builder state dumped next to the target archive as
+//! `<path>.checkpoint`, plus a ledger `<path>.ledger.jsonl` with one line per
+//! [`RoaFile`] whose entries the checkpoint contains. The builder is always
+//! dumped before the ledger is appended, so the ledger never lists a file the
+//! checkpoint is missing; at worst a few files are processed twice on resume,
+//! which is harmless because adding a known date to a record is a no-op.
+
+use crate::{RoaFile, RoasTrieMut};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// One ledger line: a processed snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LedgerEntry {
+    tal: String,
+    /// `YYYY-MM-DD`
+    date: String,
+    url: String,
+    rows: i32,
+}
+
+/// Checkpoint files belonging to the archive at `path`.
+pub struct RebuildCheckpoint {
+    checkpoint_path: String,
+    ledger_path: String,
+}
+
+impl RebuildCheckpoint {
+    pub fn new(path: &str) -> Self {
+        RebuildCheckpoint {
+            checkpoint_path: format!("{}.checkpoint", path),
+            ledger_path: format!("{}.ledger.jsonl", path),
+        }
+    }
+
+    pub fn checkpoint_path(&self) -> &str {
+        &self.checkpoint_path
+    }
+
+    pub fn ledger_path(&self) -> &str {
+        &self.ledger_path
+    }
+
+    /// Whether a checkpoint from an earlier run is present.
+    pub fn exists(&self) -> bool {
+        Path::new(&self.checkpoint_path).is_file()
+    }
+
+    /// Load the checkpointed builder and the `(tal, date)` keys of the files
+    /// it already contains.
+    pub fn load(&self) -> Result<(RoasTrieMut, HashSet<(String, NaiveDate)>)> {
+        let builder = RoasTrieMut::load(&self.checkpoint_path)?;
+        let mut processed = HashSet::new();
+        if Path::new(&self.ledger_path).is_file() {
+            let reader = std::io::BufReader::new(std::fs::File::open(&self.ledger_path)?);
+            let lines = reader.lines().collect::<std::io::Result<Vec<String>>>()?;
+            for (idx, line) in lines.iter().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let parsed = serde_json::from_str::<LedgerEntry>(line)
+                    .map_err(|e| e.to_string())
+                    .and_then(|entry| {
+                        NaiveDate::parse_from_str(&entry.date, "%Y-%m-%d")
+                            .map(|date| (entry.tal, date))
+                            .map_err(|e| e.to_string())
+                    });
+                match parsed {
+                    Ok(key) => {
+                        processed.insert(key);
+                    }
+                    // a torn last line from a crash mid-append: that file is
+                    // simply processed again
+                    Err(e) if idx + 1 == lines.len() => {
+                        warn!("ignoring incomplete last ledger line: {}", e);
+                    }
+                    Err(e) => {
+                        return Err(anyhow!(
+                            "invalid ledger line {} in {}: {}",
+                            idx + 1,
+                            self.ledger_path,
+                            e
+                        ));
+                    }
+                }
+            }
+        }
+        info!(
+            "resuming from checkpoint {} with {} processed file(s)",
+            self.checkpoint_path,
+            processed.len()
+        );
+        Ok((builder, processed))
+    }
+
+    /// Dump `builder` as the new checkpoint, then append `files` to the ledger.
+    pub fn save(&self, builder: &mut RoasTrieMut, files: &[RoaFile]) -> Result<()> {
+        builder.dump(&self.checkpoint_path)?;
+
+        let mut ledger = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.ledger_path)?;
+        let mut buf = Vec::new();
+        for file in files {
+            let entry = LedgerEntry {
+                tal: file.tal.clone(),
+                date: file.file_date.to_string(),
+                url: file.url.clone(),
+                rows: file.rows_count,
+            };
+            serde_json::to_writer(&mut buf, &entry)?;
+            buf.push(b'\n');
+        }
+        ledger.write_all(&buf)?;
+        ledger.sync_all()?;
+        info!(
+            "checkpoint written: {} ({} more file(s) in ledger)",
+            self.checkpoint_path,
+            files.len()
+        );
+        Ok(())
+    }
+
+    /// Remove the checkpoint and ledger, e.g. after the final archive is written.
+    pub fn clear(&self) -> Result<()> {
+        for path in [&self.checkpoint_path, &self.ledger_path] {
+            match std::fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RoasTrie, SearchQuery};
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "wayback-rpki-checkpoint-{}.rkyv",
+            std::process::id()
+        ));
+        let checkpoint = RebuildCheckpoint::new(path.to_str().unwrap());
+        checkpoint.clear().unwrap();
+        assert!(!checkpoint.exists());
+
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let file = |d| RoaFile {
+            url: format!("ripencc.tal/2024/01/{:02}/roas.csv.xz", d),
+            tal: "ripencc".to_string(),
+            file_date: day(d),
+            rows_count: 1,
+            processed: true,
+        };
+        let mut builder = RoasTrieMut::new();
+        let entries = |d| {
+            vec![crate::RoaEntry {
+                tal: "ripencc".to_string(),
+                prefix: "1.1.1.0/24".parse().unwrap(),
+                max_len: 24,
+                asn: 13335,
+                date: day(d),
+                object: None,
+                expires: None,
+            }]
+        };
+        builder.process_entries(&entries(1), true);
+        checkpoint.save(&mut builder, &[file(1)]).unwrap();
+        builder.process_entries(&entries(2), true);
+        checkpoint.save(&mut builder, &[file(2)]).unwrap();
+
+        // a crash mid-append leaves a torn last line behind
+        let mut ledger = std::fs::OpenOptions::new()
+            .append(true)
+            .open(checkpoint.ledger_path())
+            .unwrap();
+        ledger.write_all(b"{\"tal\":\"ripe").unwrap();
+
+        let (mut resumed, processed) = checkpoint.load().unwrap();
+        assert_eq!(processed.len(), 2);
+        assert!(processed.contains(&("ripencc".to_string(), day(2))));
+
+        // re-processing a day already in the checkpoint changes nothing
+        resumed.process_entries(&entries(2), true);
+        resumed.process_entries(&entries(3), true);
+        resumed.dump(path.to_str().unwrap()).unwrap();
+        let trie = RoasTrie::open(path.to_str().unwrap()).unwrap();
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results[0].dates_ranges, vec![(day(1), day(3))]);
+
+        checkpoint.clear().unwrap();
+        assert!(!checkpoint.exists());
+        assert!(!Path::new(checkpoint.ledger_path()).exists());
+        let _ = std::fs::remove_file(path);
+    }
+}
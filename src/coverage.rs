@@ -0,0 +1,164 @@
+//! Source observation calendar: the days on which each feed (TAL) was
+//! actually ingested, and how many rows each of those snapshots had.
+//!
+//! Record date ranges only imply which days were seen; the calendar records
+//! them explicitly, so a missing or empty day can be told apart from a ROA
+//! that was withdrawn and later reauthorized.
+
+use chrono::NaiveDate;
+use tabled::Tabled;
+
+/// One ingested snapshot: feed `tal` observed on `date` with `rows` ROA rows.
+#[derive(Debug, Clone, PartialEq, Eq, Tabled)]
+pub struct Observation {
+    pub tal: String,
+    pub date: NaiveDate,
+    pub rows: u64,
+}
+
+/// Observed days of one feed within a date window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TalCoverage {
+    pub tal: String,
+    /// Observed days with their row counts, in date order
+    pub days: Vec<(NaiveDate, u64)>,
+}
+
+impl TalCoverage {
+    pub fn first(&self) -> Option<NaiveDate> {
+        self.days.first().map(|(d, _)| *d)
+    }
+
+    pub fn last(&self) -> Option<NaiveDate> {
+        self.days.last().map(|(d, _)| *d)
+    }
+
+    /// Days between the first and last observation that were never
+    /// observed, as inclusive ranges.
+    pub fn missing_ranges(&self) -> Vec<(NaiveDate, NaiveDate)> {
+        self.days
+            .windows(2)
+            .filter_map(|w| {
+                let (prev, next) = (w[0].0, w[1].0);
+                let from = prev.succ_opt()?;
+                (from < next).then(|| (from, next.pred_opt().unwrap()))
+            })
+            .collect()
+    }
+
+    /// Number of days between the first and last observation that were
+    /// never observed.
+    pub fn missing_days(&self) -> i64 {
+        self.missing_ranges()
+            .iter()
+            .map(|(from, to)| (*to - *from).num_days() + 1)
+            .sum()
+    }
+
+    /// Observed days whose snapshot had no rows at all.
+    pub fn empty_days(&self) -> Vec<NaiveDate> {
+        self.days
+            .iter()
+            .filter(|(_, rows)| *rows == 0)
+            .map(|(d, _)| *d)
+            .collect()
+    }
+}
+
+/// Group `observations` by feed, keeping the days within `from..=until`.
+/// Feeds are returned in name order, each with its days in date order.
+pub fn summarize_coverage(
+    observations: &[Observation],
+    tal: Option<&str>,
+    from: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> Vec<TalCoverage> {
+    let mut by_tal: std::collections::BTreeMap<&str, Vec<(NaiveDate, u64)>> = Default::default();
+    for o in observations {
+        if tal.is_some_and(|t| t != o.tal)
+            || from.is_some_and(|f| o.date < f)
+            || until.is_some_and(|u| o.date > u)
+        {
+            continue;
+        }
+        by_tal.entry(&o.tal).or_default().push((o.date, o.rows));
+    }
+    by_tal
+        .into_iter()
+        .map(|(tal, mut days)| {
+            days.sort_unstable();
+            days.dedup_by_key(|(d, _)| *d);
+            TalCoverage {
+                tal: tal.to_string(),
+                days,
+            }
+        })
+        .collect()
+}
+
+/// One table row per feed for the `coverage` command.
+#[derive(Debug, Clone, Tabled)]
+pub struct TalCoverageTabled {
+    pub tal: String,
+    pub first: String,
+    pub last: String,
+    pub observed: usize,
+    pub missing: i64,
+    pub empty: usize,
+    pub min_rows: u64,
+    pub max_rows: u64,
+}
+
+impl From<&TalCoverage> for TalCoverageTabled {
+    fn from(c: &TalCoverage) -> Self {
+        let fmt = |d: Option<NaiveDate>| d.map(|d| d.to_string()).unwrap_or_default();
+        TalCoverageTabled {
+            tal: c.tal.clone(),
+            first: fmt(c.first()),
+            last: fmt(c.last()),
+            observed: c.days.len(),
+            missing: c.missing_days(),
+            empty: c.empty_days().len(),
+            min_rows: c.days.iter().map(|(_, r)| *r).min().unwrap_or(0),
+            max_rows: c.days.iter().map(|(_, r)| *r).max().unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize_coverage_finds_missing_and_empty_days() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let obs = |tal: &str, d, rows| Observation {
+            tal: tal.to_string(),
+            date: day(d),
+            rows,
+        };
+        let observations = vec![
+            obs("ripencc", 5, 10),
+            obs("ripencc", 1, 10),
+            obs("ripencc", 2, 0),
+            obs("ripencc", 8, 12),
+            obs("apnic", 1, 7),
+        ];
+
+        let coverage = summarize_coverage(&observations, None, None, None);
+        assert_eq!(coverage.len(), 2);
+        assert_eq!(coverage[0].tal, "apnic");
+        let ripe = &coverage[1];
+        assert_eq!((ripe.first(), ripe.last()), (Some(day(1)), Some(day(8))));
+        assert_eq!(
+            ripe.missing_ranges(),
+            vec![(day(3), day(4)), (day(6), day(7))]
+        );
+        assert_eq!(ripe.missing_days(), 4);
+        assert_eq!(ripe.empty_days(), vec![day(2)]);
+
+        let window = summarize_coverage(&observations, Some("ripencc"), Some(day(2)), Some(day(5)));
+        assert_eq!(window.len(), 1);
+        assert_eq!(window[0].days, vec![(day(2), 0), (day(5), 10)]);
+    }
+}
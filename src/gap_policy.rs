@@ -0,0 +1,216 @@
+//! Known-gap policy: the date intervals `fill_gaps` bridges by interpolation.
+//!
+//! The policy is data rather than code, so a newly investigated gap can be
+//! added without a release. It is read from a TOML or JSON file with one
+//! entry per interval (see `src/known_gaps.toml`, the built-in default), and
+//! each entry may be limited to one TAL and carry the classification and
+//! notes of the investigation that justified it.
+
+use crate::{normalize_tal, GapClassification};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The policy shipped with this release.
+const BUILTIN_POLICY: &str = include_str!("known_gaps.toml");
+
+/// One interval of the known-gap policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownGap {
+    /// First missing day
+    pub start: NaiveDate,
+    /// Last missing day, inclusive
+    pub end: NaiveDate,
+    /// Only bridge records of this TAL; `None` applies to every TAL
+    pub tal: Option<String>,
+    pub classification: Option<GapClassification>,
+    pub notes: Option<String>,
+}
+
+impl KnownGap {
+    /// Whether the gap applies to a record of `tal` (empty when unknown).
+    /// TAL-specific gaps never apply to records of unknown provenance.
+    pub fn applies_to(&self, tal: &str) -> bool {
+        match &self.tal {
+            None => true,
+            Some(gap_tal) => gap_tal == tal,
+        }
+    }
+}
+
+/// On-disk form of a [`KnownGap`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KnownGapFile {
+    start: String,
+    end: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tal: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    classification: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GapPolicyFile {
+    #[serde(default)]
+    gap: Vec<KnownGapFile>,
+}
+
+/// The known-gap policy applied by `fill_gaps`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GapPolicy {
+    pub gaps: Vec<KnownGap>,
+}
+
+impl Default for GapPolicy {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl GapPolicy {
+    /// The policy compiled into this release.
+    pub fn builtin() -> Self {
+        Self::from_toml(BUILTIN_POLICY).expect("built-in gap policy is valid")
+    }
+
+    /// Load a policy file; `.json` files are read as JSON, anything else as
+    /// TOML. `path` may be any location `oneio` can read.
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = oneio::read_to_string_lossy(path)
+            .map_err(|e| anyhow!("failed to read gap policy {}: {}", path, e))?;
+        let policy = if path.ends_with(".json") {
+            Self::from_json(&contents)
+        } else {
+            Self::from_toml(&contents)
+        };
+        policy.map_err(|e| anyhow!("invalid gap policy {}: {}", path, e))
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self> {
+        Self::from_file(toml::from_str(s)?)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self> {
+        Self::from_file(serde_json::from_str(s)?)
+    }
+
+    fn from_file(file: GapPolicyFile) -> Result<Self> {
+        let gaps = file
+            .gap
+            .into_iter()
+            .enumerate()
+            .map(|(idx, g)| {
+                let date = |s: &str| {
+                    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                        .map_err(|_| anyhow!("gap {}: invalid date '{}'", idx + 1, s))
+                };
+                let (start, end) = (date(&g.start)?, date(&g.end)?);
+                if start > end {
+                    return Err(anyhow!(
+                        "gap {}: start {} is after end {}",
+                        idx + 1,
+                        start,
+                        end
+                    ));
+                }
+                let classification = g
+                    .classification
+                    .map(|c| {
+                        GapClassification::parse(&c).ok_or_else(|| {
+                            anyhow!("gap {}: unknown classification '{}'", idx + 1, c)
+                        })
+                    })
+                    .transpose()?;
+                Ok(KnownGap {
+                    start,
+                    end,
+                    tal: g.tal.as_deref().map(normalize_tal),
+                    classification,
+                    notes: g.notes,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(GapPolicy { gaps })
+    }
+
+    /// SHA-256 of the policy's content, as hex. The hash does not depend on
+    /// the file format or formatting, so the same policy written as TOML or
+    /// JSON hashes the same.
+    pub fn hash(&self) -> String {
+        let file = GapPolicyFile {
+            gap: self
+                .gaps
+                .iter()
+                .map(|g| KnownGapFile {
+                    start: g.start.to_string(),
+                    end: g.end.to_string(),
+                    tal: g.tal.clone(),
+                    classification: g.classification.map(|c| c.as_str().to_string()),
+                    notes: g.notes.clone(),
+                })
+                .collect(),
+        };
+        let canonical = serde_json::to_vec(&file).expect("gap policy serializes");
+        Sha256::digest(&canonical)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_policy() {
+        let policy = GapPolicy::builtin();
+        assert_eq!(policy.gaps.len(), 76);
+        assert_eq!(
+            policy.gaps[0].start,
+            NaiveDate::from_ymd_opt(2016, 1, 27).unwrap()
+        );
+        assert!(policy.gaps.iter().all(|g| g.tal.is_none()));
+    }
+
+    #[test]
+    fn test_toml_and_json_policies_hash_the_same() {
+        let toml = r#"
+            [[gap]]
+            start = "2024-01-02"
+            end = "2024-01-03"
+            tal = "RIPE"
+            classification = "raw_source_discontinuity"
+            notes = "truncated snapshots"
+        "#;
+        let json = r#"{"gap": [{"start": "2024-01-02", "end": "2024-01-03", "tal": "ripencc",
+            "classification": "raw_source_discontinuity", "notes": "truncated snapshots"}]}"#;
+        let from_toml = GapPolicy::from_toml(toml).unwrap();
+        let from_json = GapPolicy::from_json(json).unwrap();
+        assert_eq!(from_toml, from_json);
+        assert_eq!(from_toml.hash(), from_json.hash());
+        assert_ne!(from_toml.hash(), GapPolicy::builtin().hash());
+
+        let gap = &from_toml.gaps[0];
+        assert_eq!(gap.tal.as_deref(), Some("ripencc"));
+        assert_eq!(
+            gap.classification,
+            Some(GapClassification::RawSourceDiscontinuity)
+        );
+        assert!(gap.applies_to("ripencc"));
+        assert!(!gap.applies_to("apnic"));
+        assert!(!gap.applies_to(""));
+    }
+
+    #[test]
+    fn test_invalid_policies_are_rejected() {
+        let inverted = "[[gap]]\nstart = \"2024-01-03\"\nend = \"2024-01-02\"";
+        assert!(GapPolicy::from_toml(inverted).is_err());
+        let unknown =
+            "[[gap]]\nstart = \"2024-01-02\"\nend = \"2024-01-02\"\nclassification = \"x\"";
+        assert!(GapPolicy::from_toml(unknown).is_err());
+    }
+}
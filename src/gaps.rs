@@ -0,0 +1,622 @@
+//! Historical gap investigation, automating the procedure in DEVELOPMENT.md.
+//!
+//! For a candidate date `D`, the raw snapshots of every feed (TAL) on `D-1`,
+//! `D` and `D+1` are compared with the records an archive holds for those
+//! days. Distinct `(prefix, maxLength, ASN)` record counts and distinct prefix
+//! counts are reported per TAL and for the union of all TALs, and the date is
+//! classified before it is added to (or left out of) the known-gap policy.
+//! Archives whose records do not name the source's feeds (JSONL transports
+//! without TALs, or a VRP directory read as a single feed) are classified
+//! from the union alone.
+
+use crate::{ParseMode, RoaSource, RoasTrie};
+use anyhow::Result;
+use chrono::{NaiveDate, SecondsFormat};
+use ipnet::IpNet;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use tabled::Tabled;
+use tracing::{info, warn};
+
+/// A distinct `(prefix, maxLength, ASN)` record.
+type RecordKey = (IpNet, u8, u32);
+
+/// Name of the pseudo-TAL holding the union of all TALs in a report.
+pub const UNION_TAL: &str = "union";
+
+/// Raw and archived counts of one TAL (or the union) on one day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GapDayCounts {
+    pub tal: String,
+    pub date: NaiveDate,
+    /// Distinct records in the raw snapshot; `None` if there is no snapshot
+    pub raw_records: Option<usize>,
+    pub raw_prefixes: Option<usize>,
+    /// Distinct records the archive holds for the day
+    pub archive_records: usize,
+    pub archive_prefixes: usize,
+    /// Records in the raw snapshot but not in the archive
+    pub raw_only: usize,
+    /// Records in the archive but not in the raw snapshot
+    pub archive_only: usize,
+}
+
+/// How one TAL's archived state on `D` relates to its raw snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TalGapStatus {
+    /// The archive matches a complete raw snapshot.
+    Consistent,
+    /// The archive differs from an available, complete raw snapshot.
+    ProductHistoryOmission,
+    /// The archive matches a reduced, empty or absent raw snapshot.
+    RawSourceDiscontinuity,
+    /// The raw snapshot is reduced or absent, and the archive holds more
+    /// than it: the day was bridged by synthetic interpolation.
+    Interpolated,
+}
+
+impl TalGapStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TalGapStatus::Consistent => "consistent",
+            TalGapStatus::ProductHistoryOmission => "product_history_omission",
+            TalGapStatus::RawSourceDiscontinuity => "raw_source_discontinuity",
+            TalGapStatus::Interpolated => "interpolated",
+        }
+    }
+}
+
+/// Classification of a candidate date across all TALs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapClassification {
+    NoGap,
+    ProductHistoryOmission,
+    RawSourceDiscontinuity,
+    /// Different TALs show both conditions on the same date.
+    Both,
+}
+
+impl GapClassification {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GapClassification::NoGap => "no_gap",
+            GapClassification::ProductHistoryOmission => "product_history_omission",
+            GapClassification::RawSourceDiscontinuity => "raw_source_discontinuity",
+            GapClassification::Both => "both",
+        }
+    }
+
+    /// Inverse of [`GapClassification::as_str`].
+    pub fn parse(s: &str) -> Option<Self> {
+        [
+            GapClassification::NoGap,
+            GapClassification::ProductHistoryOmission,
+            GapClassification::RawSourceDiscontinuity,
+            GapClassification::Both,
+        ]
+        .into_iter()
+        .find(|c| c.as_str() == s)
+    }
+}
+
+/// Result of investigating one candidate date.
+#[derive(Debug, Clone)]
+pub struct GapInvestigation {
+    pub date: NaiveDate,
+    /// Counts for `D-1`, `D` and `D+1`, per TAL in name order, then the union
+    pub counts: Vec<GapDayCounts>,
+    /// Status of each TAL on `D`
+    pub statuses: Vec<(String, TalGapStatus)>,
+    pub classification: GapClassification,
+}
+
+impl GapInvestigation {
+    /// Whether the archive's state on `D` is interpolated for some TAL, and
+    /// must not be described as an observed ROA state.
+    pub fn synthetic_interpolation(&self) -> bool {
+        self.statuses
+            .iter()
+            .any(|(_, s)| *s == TalGapStatus::Interpolated)
+    }
+}
+
+/// Compares raw snapshots of `source` with `trie`, caching the per-day
+/// record sets shared by consecutive candidate dates.
+pub struct GapInvestigator<'a> {
+    trie: &'a RoasTrie,
+    source: &'a dyn RoaSource,
+    tals: Vec<String>,
+    /// Fraction of its neighbors' record count below which a raw snapshot
+    /// counts as reduced
+    drop_ratio: f64,
+    raw: HashMap<(String, NaiveDate), Option<HashSet<RecordKey>>>,
+    archive: HashMap<NaiveDate, HashMap<String, HashSet<RecordKey>>>,
+}
+
+impl<'a> GapInvestigator<'a> {
+    pub fn new(trie: &'a RoasTrie, source: &'a dyn RoaSource, drop_ratio: f64) -> Self {
+        let mut tals = source.tals();
+        tals.sort();
+        tals.dedup();
+        GapInvestigator {
+            trie,
+            source,
+            tals,
+            drop_ratio,
+            raw: HashMap::new(),
+            archive: HashMap::new(),
+        }
+    }
+
+    /// Investigate every date in `from..=until`.
+    pub fn investigate_range(
+        &mut self,
+        from: NaiveDate,
+        until: NaiveDate,
+    ) -> Vec<GapInvestigation> {
+        let mut results = Vec::new();
+        let mut date = from;
+        while date <= until {
+            results.push(self.investigate(date));
+            // D-1 is not needed again once D moves on
+            let stale = date.pred_opt().unwrap();
+            self.raw.retain(|(_, d), _| *d > stale);
+            self.archive.retain(|d, _| *d > stale);
+            date = date.succ_opt().unwrap();
+        }
+        results
+    }
+
+    /// Investigate a single candidate date.
+    pub fn investigate(&mut self, date: NaiveDate) -> GapInvestigation {
+        info!("investigating {}", date);
+        let days = [date.pred_opt().unwrap(), date, date.succ_opt().unwrap()];
+        for day in days {
+            for tal in self.tals.clone() {
+                self.load_raw(&tal, day);
+            }
+            self.load_archive(day);
+        }
+
+        let mut counts = Vec::new();
+        let mut statuses = Vec::new();
+        let attributed = self.archive_attributed(&days);
+        if !attributed {
+            info!("archive records do not name the source's feeds; comparing the union");
+        }
+        for tal in self.tals.iter().filter(|_| attributed) {
+            for day in days {
+                counts.push(day_counts(
+                    tal,
+                    day,
+                    self.raw[&(tal.clone(), day)].as_ref(),
+                    self.archive[&day].get(tal),
+                ));
+            }
+            let tal_counts = &counts[counts.len() - 3..];
+            statuses.push((tal.clone(), self.tal_status(tal_counts)));
+        }
+        for day in days {
+            let raw_union = self
+                .tals
+                .iter()
+                .filter_map(|tal| self.raw[&(tal.clone(), day)].as_ref())
+                .fold(None, |acc: Option<HashSet<RecordKey>>, set| {
+                    let mut acc = acc.unwrap_or_default();
+                    acc.extend(set.iter().copied());
+                    Some(acc)
+                });
+            let archive_union: HashSet<RecordKey> = self.archive[&day]
+                .values()
+                .flat_map(|set| set.iter().copied())
+                .collect();
+            counts.push(day_counts(
+                UNION_TAL,
+                day,
+                raw_union.as_ref(),
+                Some(&archive_union),
+            ));
+        }
+        if !attributed {
+            let union_counts = &counts[counts.len() - 3..];
+            statuses.push((UNION_TAL.to_string(), self.tal_status(union_counts)));
+        }
+
+        let classification = classify(statuses.iter().map(|(_, s)| *s));
+        GapInvestigation {
+            date,
+            counts,
+            statuses,
+            classification,
+        }
+    }
+
+    /// Whether every archived record of `days` belongs to one of the
+    /// source's feeds, so that they can be compared TAL by TAL.
+    fn archive_attributed(&self, days: &[NaiveDate]) -> bool {
+        days.iter()
+            .flat_map(|day| self.archive[day].keys())
+            .all(|tal| self.tals.contains(tal))
+    }
+
+    /// Status of one TAL from its counts on `D-1`, `D` and `D+1`.
+    fn tal_status(&self, counts: &[GapDayCounts]) -> TalGapStatus {
+        let (prev, day, next) = (&counts[0], &counts[1], &counts[2]);
+        let neighbors = [prev.raw_records, next.raw_records];
+        let baseline = neighbors.iter().flatten().max().copied();
+        let reduced = match (day.raw_records, baseline) {
+            // nothing published around the date either, e.g. before the TAL existed
+            (None, None) => false,
+            (None, Some(_)) => true,
+            (Some(0), _) => true,
+            (Some(rows), Some(b)) => (rows as f64) < b as f64 * self.drop_ratio,
+            (Some(_), None) => false,
+        };
+
+        if day.raw_only > 0 || (!reduced && day.archive_only > 0) {
+            TalGapStatus::ProductHistoryOmission
+        } else if !reduced {
+            TalGapStatus::Consistent
+        } else if day.archive_only == 0 {
+            TalGapStatus::RawSourceDiscontinuity
+        } else {
+            TalGapStatus::Interpolated
+        }
+    }
+
+    fn load_raw(&mut self, tal: &str, day: NaiveDate) {
+        let key = (tal.to_string(), day);
+        if self.raw.contains_key(&key) {
+            return;
+        }
+        let files = self.source.list_snapshots(tal, Some(day), Some(day));
+        let records = files.first().and_then(|file| {
+            match self.source.read_snapshot_with(file, ParseMode::Lenient) {
+                Ok(snapshot) => Some(
+                    snapshot
+                        .entries
+                        .iter()
+                        .map(|e| (e.prefix, e.max_len as u8, e.asn))
+                        .collect::<HashSet<RecordKey>>(),
+                ),
+                Err(e) => {
+                    warn!("failed to read {}: {}; treating it as absent", file.url, e);
+                    None
+                }
+            }
+        });
+        self.raw.insert(key, records);
+    }
+
+    fn load_archive(&mut self, day: NaiveDate) {
+        if self.archive.contains_key(&day) {
+            return;
+        }
+        let mut by_tal: HashMap<String, HashSet<RecordKey>> = HashMap::new();
+        for (tal, prefix, max_len, origin) in self.trie.records_on(day) {
+            by_tal
+                .entry(tal)
+                .or_default()
+                .insert((prefix, max_len, origin));
+        }
+        self.archive.insert(day, by_tal);
+    }
+}
+
+fn day_counts(
+    tal: &str,
+    date: NaiveDate,
+    raw: Option<&HashSet<RecordKey>>,
+    archive: Option<&HashSet<RecordKey>>,
+) -> GapDayCounts {
+    let empty = HashSet::new();
+    let archive = archive.unwrap_or(&empty);
+    let prefixes = |set: &HashSet<RecordKey>| {
+        set.iter()
+            .map(|(p, _, _)| *p)
+            .collect::<BTreeSet<_>>()
+            .len()
+    };
+    GapDayCounts {
+        tal: tal.to_string(),
+        date,
+        raw_records: raw.map(|r| r.len()),
+        raw_prefixes: raw.map(prefixes),
+        archive_records: archive.len(),
+        archive_prefixes: prefixes(archive),
+        raw_only: raw.map_or(0, |r| r.difference(archive).count()),
+        archive_only: archive.difference(raw.unwrap_or(&empty)).count(),
+    }
+}
+
+/// Combine the TAL statuses of a date; an interpolated day is a raw-source
+/// discontinuity that has already been bridged.
+pub fn classify(statuses: impl Iterator<Item = TalGapStatus>) -> GapClassification {
+    let (mut omission, mut discontinuity) = (false, false);
+    for status in statuses {
+        match status {
+            TalGapStatus::ProductHistoryOmission => omission = true,
+            TalGapStatus::RawSourceDiscontinuity | TalGapStatus::Interpolated => {
+                discontinuity = true
+            }
+            TalGapStatus::Consistent => {}
+        }
+    }
+    match (omission, discontinuity) {
+        (true, true) => GapClassification::Both,
+        (true, false) => GapClassification::ProductHistoryOmission,
+        (false, true) => GapClassification::RawSourceDiscontinuity,
+        (false, false) => GapClassification::NoGap,
+    }
+}
+
+/// One table row of the `gaps investigate` output.
+#[derive(Debug, Clone, Tabled)]
+pub struct GapDayCountsTabled {
+    pub tal: String,
+    pub date: String,
+    pub raw_records: String,
+    pub raw_prefixes: String,
+    pub archive_records: usize,
+    pub archive_prefixes: usize,
+    pub raw_only: usize,
+    pub archive_only: usize,
+}
+
+impl From<&GapDayCounts> for GapDayCountsTabled {
+    fn from(c: &GapDayCounts) -> Self {
+        let fmt = |v: Option<usize>| v.map_or_else(|| "-".to_string(), |v| v.to_string());
+        GapDayCountsTabled {
+            tal: c.tal.clone(),
+            date: c.date.to_string(),
+            raw_records: fmt(c.raw_records),
+            raw_prefixes: fmt(c.raw_prefixes),
+            archive_records: c.archive_records,
+            archive_prefixes: c.archive_prefixes,
+            raw_only: c.raw_only,
+            archive_only: c.archive_only,
+        }
+    }
+}
+
+/// Write `investigations` as a JSON report to `path`, for attaching to a
+/// known-gap policy change. `archive` and `source` name what was compared.
+pub fn write_gap_report(
+    path: &str,
+    archive: &str,
+    source: &str,
+    drop_ratio: f64,
+    investigations: &[GapInvestigation],
+) -> Result<()> {
+    let dates = investigations
+        .iter()
+        .map(|inv| {
+            let mut tals: BTreeMap<&str, Vec<serde_json::Value>> = BTreeMap::new();
+            for c in &inv.counts {
+                tals.entry(c.tal.as_str()).or_default().push(json!({
+                    "date": c.date.to_string(),
+                    "raw_records": c.raw_records,
+                    "raw_prefixes": c.raw_prefixes,
+                    "archive_records": c.archive_records,
+                    "archive_prefixes": c.archive_prefixes,
+                    "raw_only": c.raw_only,
+                    "archive_only": c.archive_only,
+                }));
+            }
+            json!({
+                "date": inv.date.to_string(),
+                "classification": inv.classification.as_str(),
+                "synthetic_interpolation": inv.synthetic_interpolation(),
+                "statuses": inv
+                    .statuses
+                    .iter()
+                    .map(|(tal, s)| (tal.clone(), s.as_str()))
+                    .collect::<BTreeMap<_, _>>(),
+                "counts": tals,
+            })
+        })
+        .collect::<Vec<_>>();
+    let report = json!({
+        "generated_at": chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        "archive": archive,
+        "source": source,
+        "drop_ratio": drop_ratio,
+        "dates": dates,
+    });
+    let mut writer = oneio::get_writer(path)?;
+    serde_json::to_writer_pretty(&mut writer, &report)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roas_trie::tests::dump_and_open;
+    use crate::roas_trie::{date_to_ts, JsonlRecord};
+    use crate::{RoaEntry, RoaFile, RoasTrieMut};
+
+    /// Snapshots of three TALs for 2024-01-01..03: `ripencc` loses half of
+    /// its ROAs on the 2nd, and `arin` has no file that day.
+    struct GapSource;
+
+    const TALS: [&str; 3] = ["apnic", "arin", "ripencc"];
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, d).unwrap()
+    }
+
+    fn entries(tal: &str, d: u32) -> Vec<RoaEntry> {
+        let count = match (tal, d) {
+            ("ripencc", 2) => 2,
+            _ => 4,
+        };
+        (0..count)
+            .map(|i| RoaEntry {
+                tal: tal.to_string(),
+                prefix: format!(
+                    "10.{}.{}.0/24",
+                    TALS.iter().position(|t| *t == tal).unwrap(),
+                    i
+                )
+                .parse()
+                .unwrap(),
+                max_len: 24,
+                asn: 64500,
+                date: day(d),
+                object: None,
+                expires: None,
+            })
+            .collect()
+    }
+
+    impl RoaSource for GapSource {
+        fn name(&self) -> String {
+            "gaps".to_string()
+        }
+
+        fn tals(&self) -> Vec<String> {
+            TALS.iter().map(|t| t.to_string()).collect()
+        }
+
+        fn list_snapshots(
+            &self,
+            tal: &str,
+            from: Option<NaiveDate>,
+            _until: Option<NaiveDate>,
+        ) -> Vec<RoaFile> {
+            let date = from.unwrap();
+            if tal == "arin" && date == day(2) {
+                return Vec::new();
+            }
+            vec![RoaFile {
+                url: format!("gaps://{}/{}", tal, date),
+                tal: tal.to_string(),
+                file_date: date,
+                rows_count: 0,
+                processed: false,
+            }]
+        }
+
+        fn read_snapshot(&self, file: &RoaFile) -> Result<Vec<RoaEntry>> {
+            Ok(entries(
+                &file.tal,
+                file.file_date.format("%d").to_string().parse()?,
+            ))
+        }
+    }
+
+    #[test]
+    fn test_investigate_classifies_each_tal() {
+        // the archive took ripencc's reduced snapshot as-is, bridged arin's
+        // missing day and lost apnic's complete one
+        let mut builder = RoasTrieMut::new();
+        for d in 1..=3 {
+            for tal in TALS {
+                if !(tal == "apnic" && d == 2) {
+                    builder.process_entries(&entries(tal, d), true);
+                }
+            }
+        }
+        let trie = dump_and_open(&mut builder, "gaps");
+
+        let source = GapSource;
+        let mut investigator = GapInvestigator::new(&trie, &source, 0.75);
+        let results = investigator.investigate_range(day(2), day(2));
+        let inv = &results[0];
+        assert_eq!(
+            inv.statuses,
+            vec![
+                ("apnic".to_string(), TalGapStatus::ProductHistoryOmission),
+                ("arin".to_string(), TalGapStatus::Interpolated),
+                ("ripencc".to_string(), TalGapStatus::RawSourceDiscontinuity),
+            ]
+        );
+        assert_eq!(inv.classification, GapClassification::Both);
+        assert!(inv.synthetic_interpolation());
+
+        let union_day = inv
+            .counts
+            .iter()
+            .find(|c| c.tal == UNION_TAL && c.date == day(2))
+            .unwrap();
+        assert_eq!(union_day.raw_records, Some(6));
+        assert_eq!(union_day.archive_records, 6);
+        assert_eq!((union_day.raw_only, union_day.archive_only), (4, 4));
+    }
+
+    #[test]
+    fn test_investigate_compares_union_of_transport_without_tals() {
+        // a JSONL transport whose records carry no TAL: apnic's records are
+        // missing on the 2nd, the other TALs match their raw snapshots
+        let path = std::env::temp_dir().join(format!(
+            "wayback-rpki-test-gaps-transport-{}.jsonl",
+            std::process::id()
+        ));
+        let mut lines = Vec::new();
+        for tal in TALS {
+            let mut by_record: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+            for d in 1..=3 {
+                if (tal == "apnic" && d == 2) || (tal == "arin" && d == 2) {
+                    continue;
+                }
+                for e in entries(tal, d) {
+                    by_record.entry(e.prefix.to_string()).or_default().push(d);
+                }
+            }
+            for (prefix, days) in by_record {
+                let record = JsonlRecord {
+                    p: prefix,
+                    m: 24,
+                    o: 64500,
+                    t: String::new(),
+                    r: days
+                        .iter()
+                        .map(|d| (date_to_ts(day(*d)), date_to_ts(day(*d))))
+                        .collect(),
+                    i: Vec::new(),
+                    u: Vec::new(),
+                    n: None,
+                    x: None,
+                };
+                lines.push(serde_json::to_string(&record).unwrap());
+            }
+        }
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let mut builder = RoasTrieMut::from_jsonl(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        let trie = dump_and_open(&mut builder, "gaps-transport");
+
+        let source = GapSource;
+        let mut investigator = GapInvestigator::new(&trie, &source, 0.75);
+        let inv = &investigator.investigate_range(day(2), day(2))[0];
+        assert_eq!(
+            inv.statuses,
+            vec![(UNION_TAL.to_string(), TalGapStatus::ProductHistoryOmission)]
+        );
+        assert!(inv.counts.iter().all(|c| c.tal == UNION_TAL));
+        let union_day = inv.counts.iter().find(|c| c.date == day(2)).unwrap();
+        assert_eq!(union_day.raw_records, Some(6));
+        assert_eq!(union_day.archive_records, 2);
+        assert_eq!((union_day.raw_only, union_day.archive_only), (4, 0));
+    }
+
+    #[test]
+    fn test_classify_combines_tal_statuses() {
+        use TalGapStatus::*;
+        assert_eq!(
+            classify([Consistent, Consistent].into_iter()),
+            GapClassification::NoGap
+        );
+        assert_eq!(
+            classify([Consistent, RawSourceDiscontinuity].into_iter()),
+            GapClassification::RawSourceDiscontinuity
+        );
+        assert_eq!(
+            classify([ProductHistoryOmission, Interpolated].into_iter()),
+            GapClassification::Both
+        );
+    }
+}
@@ -4,10 +4,10 @@
 //! in-memory implementation, preserving v1 behavior. `convert` uses
 //! [`LegacyRoasTrie::load`] followed by conversion to the v2 builder.
 
-use crate::roas_trie::KNOWN_GAPS_STR;
 use crate::{
-    crawl_tal_after, get_tal_urls, parse_roas_csv, RoaEntry, RoaFile, RoaRecordMut,
-    RoasLookupEntry, RoasTrieMut, RpkiValidation,
+    parse_roas_csv, CoveringVrp, DateRangeFilter, GapPolicy, IngestSummary, MatchMode, ParseMode,
+    RoaEntry, RoaRecordMut, RoaSource, RoasLookupEntry, RoasTrieMut, SearchQuery, ValidationReport,
+    VrpMatch,
 };
 use anyhow::Result;
 use bincode::{Decode, Encode};
@@ -168,23 +168,20 @@ impl LegacyRoasTrie {
         Ok(roas_trie)
     }
 
-    pub fn fill_gaps(&mut self) {
+    /// Fill the gaps of `policy`. v1 records carry no TAL, so gaps limited
+    /// to one TAL are skipped.
+    pub fn fill_gaps(&mut self, policy: &GapPolicy) {
         info!("filling known gaps...");
         const ONE_DAY_SECONDS: i64 = 86400;
 
-        for (start, end) in KNOWN_GAPS_STR.iter() {
-            let start_ts = chrono::NaiveDate::parse_from_str(start, "%Y-%m-%d")
-                .unwrap()
-                .and_hms_opt(0, 0, 0)
-                .unwrap()
-                .and_utc()
-                .timestamp();
-            let end_ts = chrono::NaiveDate::parse_from_str(end, "%Y-%m-%d")
-                .unwrap()
+        for gap in policy.gaps.iter().filter(|g| g.applies_to("")) {
+            let start_ts = gap
+                .start
                 .and_hms_opt(0, 0, 0)
                 .unwrap()
                 .and_utc()
                 .timestamp();
+            let end_ts = gap.end.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
 
             // vector of timestamps from start_ts to end_ts
             let mut dates = Vec::new();
@@ -298,32 +295,79 @@ impl LegacyRoasTrie {
         }
     }
 
-    pub fn validate(&self, prefix: &IpNet, origin: u32, date_ts: i64) -> RpkiValidation {
-        let mut is_valid = RpkiValidation::Unknown;
-        'outer: for matched in self.trie.matches(prefix) {
-            for entry in matched.1.values() {
-                if entry.origin == origin
-                    && entry.max_len >= prefix.prefix_len()
-                    && entry.contains_date(date_ts)
-                {
-                    is_valid = RpkiValidation::Valid;
-                    break 'outer;
-                }
+    /// Entries covering `prefix`, shortest first, found by looking up each of
+    /// its ancestors.
+    fn covering(&self, prefix: &IpNet) -> Vec<(IpNet, &RoasTrieMap)> {
+        (0..=prefix.prefix_len())
+            .filter_map(|len| {
+                let ancestor = IpNet::new(prefix.network(), len).ok()?.trunc();
+                self.trie.exact_match(ancestor).map(|map| (ancestor, map))
+            })
+            .collect()
+    }
+
+    /// Entries of `prefix`, its supernets and its subnets.
+    fn related(&self, prefix: &IpNet) -> Vec<(IpNet, &RoasTrieMap)> {
+        self.trie
+            .matches(prefix)
+            .into_iter()
+            .filter(|(p, _)| p.contains(prefix) || prefix.contains(p))
+            .collect()
+    }
+
+    /// Entries of `prefix` and its subnets.
+    fn covered(&self, prefix: &IpNet) -> Vec<(IpNet, &RoasTrieMap)> {
+        self.trie
+            .matches(prefix)
+            .into_iter()
+            .filter(|(p, _)| prefix.contains(p))
+            .collect()
+    }
+
+    /// RPKI validation for a prefix/origin at a given date, considering only
+    /// the ROAs covering the prefix and active on the date (RFC 6811). v1
+    /// archives do not flag interpolated days, so `strict` has no effect, and
+    /// do not record TALs, so the reported VRPs have none.
+    pub fn validate(
+        &self,
+        prefix: &IpNet,
+        origin: u32,
+        date_ts: i64,
+        _strict: bool,
+    ) -> ValidationReport {
+        let mut vrps = Vec::new();
+        for (covering, map) in self.covering(prefix) {
+            for entry in map.values().filter(|e| e.contains_date(date_ts)) {
+                vrps.push(CoveringVrp {
+                    prefix: covering,
+                    max_len: entry.max_len,
+                    origin: entry.origin,
+                    tal: String::new(),
+                    status: VrpMatch::classify(
+                        entry.origin,
+                        entry.max_len,
+                        origin,
+                        prefix.prefix_len(),
+                    ),
+                });
             }
-            is_valid = RpkiValidation::Invalid;
         }
-
-        is_valid
+        ValidationReport::from_vrps(vrps)
     }
 
     pub fn lookup_prefix(&self, prefix: &IpNet) -> Vec<RoasLookupEntry> {
         let mut entries = Vec::new();
-        for (prefix, map) in self.trie.matches(prefix) {
+        for (prefix, map) in self.related(prefix) {
             for entry in map.values() {
                 entries.push(RoasLookupEntry {
                     prefix,
                     origin: entry.origin,
                     max_len: entry.max_len,
+                    tal: String::new(),
+                    objects: Vec::new(),
+                    object_count: 0,
+                    expires: None,
+                    interpolated_ranges: Vec::new(),
                     dates_ranges: entry
                         .dates_compressed
                         .iter()
@@ -346,25 +390,38 @@ impl LegacyRoasTrie {
         entries
     }
 
-    pub fn search(
-        &self,
-        prefix: Option<IpNet>,
-        origin: Option<u32>,
-        max_len: Option<u8>,
-        date: Option<NaiveDate>,
-        current: Option<bool>,
-        exact: bool,
-    ) -> Vec<RoasLookupEntry> {
+    /// Search ROAs matching `query`. v1 archives do not flag interpolated
+    /// days, so `observed_only` has no effect.
+    pub fn search(&self, query: &SearchQuery) -> Vec<RoasLookupEntry> {
+        let SearchQuery {
+            prefix,
+            origin,
+            max_len,
+            date,
+            current,
+            window,
+            match_mode,
+            ref tal,
+            observed_only: _,
+        } = *query;
         let mut entries = Vec::new();
 
-        // prefix filter: exact match by default (fixes issue #9),
-        // falls back to matches() when exact=false for supernet/subnet inclusion
+        // v1 archives carry no TAL provenance, so a TAL filter never matches
+        if tal.is_some() {
+            return entries;
+        }
+
+        // prefix filter: exact match by default (fixes issue #9)
         let iter: Vec<(IpNet, &RoasTrieMap)> = match prefix {
-            Some(prefix) if exact => match self.trie.exact_match(prefix) {
-                Some(map) => vec![(prefix, map)],
-                None => vec![],
+            Some(prefix) => match match_mode {
+                MatchMode::Exact => match self.trie.exact_match(prefix) {
+                    Some(map) => vec![(prefix, map)],
+                    None => vec![],
+                },
+                MatchMode::Covering => self.covering(&prefix),
+                MatchMode::CoveredBy => self.covered(&prefix),
+                MatchMode::Overlapping => self.related(&prefix),
             },
-            Some(prefix) => self.trie.matches(&prefix),
             None => self.trie.iter().collect(),
         };
 
@@ -420,10 +477,15 @@ impl LegacyRoasTrie {
                     continue;
                 }
 
-                entries.push(RoasLookupEntry {
+                let lookup = RoasLookupEntry {
                     prefix,
                     origin: entry.origin,
                     max_len: entry.max_len,
+                    tal: String::new(),
+                    objects: Vec::new(),
+                    object_count: 0,
+                    expires: None,
+                    interpolated_ranges: Vec::new(),
                     dates_ranges: entry
                         .dates_compressed
                         .iter()
@@ -440,43 +502,56 @@ impl LegacyRoasTrie {
                             )
                         })
                         .collect(),
-                });
+                };
+                if window != DateRangeFilter::default()
+                    && !window.matches(&lookup.dates_ranges, self.get_latest_date())
+                {
+                    continue;
+                }
+                entries.push(lookup);
             }
         }
         entries
     }
 
-    pub fn update(&mut self, tal: Option<String>, until: Option<NaiveDate>) -> Result<()> {
-        info!("updating trie... tal: {:?}, until: {:?}", &tal, &until);
-        let mut all_files = get_tal_urls(tal)
-            .into_iter()
-            .flat_map(|tal_url| {
-                crawl_tal_after(
-                    tal_url.as_str(),
-                    Some(self.get_latest_date() + chrono::Duration::days(1)),
-                    until,
-                )
-            })
-            .collect::<Vec<RoaFile>>();
+    /// Incremental update from the day after the global latest date. The v1
+    /// format does not track feeds separately: a run limited to some TALs
+    /// (`update --tal`) advances the date for all of them, so the others'
+    /// missing days are not fetched by later runs. Convert to the current
+    /// format for per-TAL catch-up.
+    pub fn update(
+        &mut self,
+        source: &dyn RoaSource,
+        until: Option<NaiveDate>,
+        mode: ParseMode,
+    ) -> Result<IngestSummary> {
+        info!(
+            "updating trie... source: {}, until: {:?}",
+            source.name(),
+            &until
+        );
+        let mut summary = IngestSummary::default();
+        let all_files = source.list_all_snapshots(
+            Some(self.get_latest_date() + chrono::Duration::days(1)),
+            until,
+        );
 
         if all_files.is_empty() {
             info!("trie is up to date. No new files found.");
-            return Ok(());
+            return Ok(summary);
         }
 
-        // sort by date
-        all_files.sort_by_key(|a| a.file_date);
-
         for file in all_files {
             info!("processing {}", file.url.as_str());
-            let url: &str = file.url.as_str();
-            if let Ok(roas) = parse_roas_csv(url) {
-                self.process_entries(&roas, false);
+            let result = source.read_snapshot_with(&file, mode);
+            summary.record(&file, &result);
+            if let Ok(snapshot) = result {
+                self.process_entries(&snapshot.entries, false);
             }
         }
 
-        info!("updating trie... done");
-        Ok(())
+        info!("updating trie... done: {}", summary);
+        Ok(summary)
     }
 
     pub fn replace(&mut self, other: LegacyRoasTrie) {
@@ -527,8 +602,15 @@ impl LegacyRoasTrie {
                 let record = RoaRecordMut {
                     origin: *origin,
                     max_len: *max_len,
+                    tal: String::new(),
+                    objects: Vec::new(),
+                    object_count: 0,
+                    expires: None,
+                    seen_objects: HashSet::new(),
+                    objects_dropped: false,
                     dates: HashSet::new(),
                     ranges,
+                    interpolated: Vec::new(),
                 };
                 builder.insert_record(prefix, record);
             }
@@ -541,7 +623,7 @@ impl LegacyRoasTrie {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::RoaEntry;
+    use crate::{RoaEntry, RpkiValidation};
     use chrono::NaiveDate;
     use ipnet::IpNet;
     use std::net::Ipv4Addr;
@@ -554,6 +636,8 @@ mod tests {
             max_len,
             asn,
             date,
+            object: None,
+            expires: None,
         }
     }
 
@@ -579,7 +663,10 @@ mod tests {
         let prefix = IpNet::V4(ipnet::Ipv4Net::new(Ipv4Addr::new(1, 1, 1, 0), 24).unwrap());
 
         // exact=true should return only the /24 ROA
-        let results = trie.search(Some(prefix), None, None, None, None, true);
+        let results = trie.search(&SearchQuery {
+            prefix: Some(prefix),
+            ..Default::default()
+        });
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].prefix.to_string(), "1.1.1.0/24");
         assert_eq!(results[0].origin, 13335);
@@ -590,21 +677,97 @@ mod tests {
         let trie = build_test_trie();
         let prefix = IpNet::V4(ipnet::Ipv4Net::new(Ipv4Addr::new(1, 1, 1, 0), 24).unwrap());
 
-        // exact=false should use matches(), which includes the supernet and subnet
-        let results = trie.search(Some(prefix), None, None, None, None, false);
+        // exact=false includes the supernet and subnet
+        let results = trie.search(&SearchQuery {
+            prefix: Some(prefix),
+            match_mode: MatchMode::Overlapping,
+            ..Default::default()
+        });
         let prefixes: Vec<String> = results.iter().map(|r| r.prefix.to_string()).collect();
         assert!(prefixes.contains(&"1.0.0.0/8".to_string()));
         assert!(prefixes.contains(&"1.1.1.0/24".to_string()));
         assert!(prefixes.contains(&"1.1.1.0/25".to_string()));
     }
 
+    #[test]
+    fn test_validate_considers_only_covering_roas() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let mut trie = LegacyRoasTrie::new();
+        trie.process_entries(
+            &vec![
+                make_entry("10.0.0.0/8", 1, 8, date),
+                make_entry("10.1.0.0/24", 2, 24, date),
+                make_entry("10.2.0.0/16", 3, 16, date),
+            ],
+            true,
+        );
+        trie.compress_dates();
+
+        let ts = date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+        let route = IpNet::from_str("10.1.0.0/16").unwrap();
+        let report = trie.validate(&route, 1, ts, false);
+        assert_eq!(report.result, RpkiValidation::Invalid);
+        assert_eq!(report.vrps.len(), 1);
+        assert_eq!(report.vrps[0].status, VrpMatch::MaxLengthExceeded);
+        let report = trie.validate(&route, 2, ts, false);
+        assert_eq!(report.result, RpkiValidation::Invalid);
+        assert_eq!(report.vrps[0].status, VrpMatch::OriginMismatch);
+        assert_eq!(
+            trie.validate(&route, 3, ts, false).result,
+            RpkiValidation::Invalid
+        );
+        let route = IpNet::from_str("10.1.0.0/24").unwrap();
+        let report = trie.validate(&route, 2, ts, false);
+        assert_eq!(report.result, RpkiValidation::Valid);
+        let statuses: Vec<VrpMatch> = report.vrps.iter().map(|v| v.status).collect();
+        assert_eq!(statuses, vec![VrpMatch::OriginMismatch, VrpMatch::Matched]);
+
+        let results = trie.search(&SearchQuery {
+            prefix: Some(route),
+            match_mode: MatchMode::Overlapping,
+            ..Default::default()
+        });
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_search_covering_and_covered_by() {
+        let trie = build_test_trie();
+        let prefix = IpNet::from_str("1.1.1.0/24").unwrap();
+
+        let matched = |mode| -> Vec<String> {
+            let mut prefixes: Vec<String> = trie
+                .search(&SearchQuery {
+                    prefix: Some(prefix),
+                    match_mode: mode,
+                    ..Default::default()
+                })
+                .iter()
+                .map(|r| r.prefix.to_string())
+                .collect();
+            prefixes.sort();
+            prefixes
+        };
+        assert_eq!(
+            matched(MatchMode::Covering),
+            vec!["1.0.0.0/8", "1.1.1.0/24"]
+        );
+        assert_eq!(
+            matched(MatchMode::CoveredBy),
+            vec!["1.1.1.0/24", "1.1.1.0/25"]
+        );
+    }
+
     #[test]
     fn test_search_exact_no_match_returns_empty() {
         let trie = build_test_trie();
         // prefix not in trie
         let prefix = IpNet::V4(ipnet::Ipv4Net::new(Ipv4Addr::new(2, 2, 2, 0), 24).unwrap());
 
-        let results = trie.search(Some(prefix), None, None, None, None, true);
+        let results = trie.search(&SearchQuery {
+            prefix: Some(prefix),
+            ..Default::default()
+        });
         assert!(results.is_empty());
     }
 
@@ -613,7 +776,7 @@ mod tests {
         let trie = build_test_trie();
 
         // No prefix filter → return all entries regardless of exact flag
-        let results = trie.search(None, None, None, None, None, true);
+        let results = trie.search(&SearchQuery::default());
         assert_eq!(results.len(), 3);
     }
 }
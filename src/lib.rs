@@ -1,8 +1,16 @@
 #![allow(clippy::nonminimal_bool)]
 
+mod anomaly;
 pub mod api;
+mod cache;
+mod checkpoint;
+mod coverage;
+mod gap_policy;
+mod gaps;
 pub mod legacy;
 mod roas_trie;
+mod source;
+mod vrp;
 
 use anyhow::{anyhow, Result};
 use chrono::{Datelike, NaiveDate};
@@ -10,11 +18,20 @@ use ipnet::IpNet;
 use rayon::prelude::*;
 use regex::Regex;
 use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use std::str::FromStr;
 use tracing::{debug, info, warn};
 
+pub use anomaly::*;
 pub use api::*;
+pub use cache::*;
+pub use checkpoint::*;
+pub use coverage::*;
+pub use gap_policy::*;
+pub use gaps::*;
 pub use roas_trie::*;
+pub use source::*;
+pub use vrp::*;
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct RoaEntry {
@@ -23,9 +40,127 @@ pub struct RoaEntry {
     max_len: i32,
     asn: u32,
     date: NaiveDate,
+    /// The ROA object the entry was read from, when the input names it.
+    object: Option<RoaObject>,
+    /// When the validator stops accepting the ROA unless its chain is
+    /// renewed, as a UTC Unix timestamp, when the input reports it.
+    expires: Option<i64>,
 }
 
-#[derive(Debug)]
+impl RoaEntry {
+    /// One ROA row of the `date` snapshot of `tal`. Custom [`RoaSource`]s
+    /// build their entries with it:
+    ///
+    /// ```
+    /// use anyhow::Result;
+    /// use chrono::NaiveDate;
+    /// use wayback_rpki::{ParseMode, RoaEntry, RoaFile, RoaSource, RoasTrieMut};
+    ///
+    /// struct OneRoa;
+    ///
+    /// impl RoaSource for OneRoa {
+    ///     fn name(&self) -> String {
+    ///         "one-roa".to_string()
+    ///     }
+    ///
+    ///     fn tals(&self) -> Vec<String> {
+    ///         vec!["example".to_string()]
+    ///     }
+    ///
+    ///     fn list_snapshots(
+    ///         &self,
+    ///         tal: &str,
+    ///         from: Option<NaiveDate>,
+    ///         _until: Option<NaiveDate>,
+    ///     ) -> Vec<RoaFile> {
+    ///         let file_date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+    ///         if from.is_some_and(|from| from > file_date) {
+    ///             return Vec::new();
+    ///         }
+    ///         vec![RoaFile {
+    ///             url: "memory://one-roa".to_string(),
+    ///             tal: tal.to_string(),
+    ///             file_date,
+    ///             rows_count: 1,
+    ///             processed: false,
+    ///         }]
+    ///     }
+    ///
+    ///     fn read_snapshot(&self, file: &RoaFile) -> Result<Vec<RoaEntry>> {
+    ///         let prefix = "192.0.2.0/24".parse()?;
+    ///         Ok(vec![RoaEntry::new(&file.tal, prefix, 24, 64496, file.file_date, None)])
+    ///     }
+    /// }
+    ///
+    /// let mut builder = RoasTrieMut::new();
+    /// let summary = builder.update(&OneRoa, None, ParseMode::Strict)?;
+    /// assert_eq!(summary.files, 1);
+    ///
+    /// let entry = &OneRoa.read_snapshot(&OneRoa.list_snapshots("example", None, None)[0])?[0];
+    /// assert_eq!((entry.tal(), entry.asn(), entry.max_len()), ("example", 64496, 24));
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn new(
+        tal: &str,
+        prefix: IpNet,
+        max_len: i32,
+        asn: u32,
+        date: NaiveDate,
+        object: Option<RoaObject>,
+    ) -> Self {
+        RoaEntry {
+            tal: tal.to_string(),
+            prefix,
+            max_len,
+            asn,
+            date,
+            object,
+            expires: None,
+        }
+    }
+
+    /// Set the expiry the validator reported for the entry.
+    pub fn with_expires(mut self, expires: Option<i64>) -> Self {
+        self.expires = expires;
+        self
+    }
+
+    pub fn tal(&self) -> &str {
+        &self.tal
+    }
+
+    pub fn prefix(&self) -> IpNet {
+        self.prefix
+    }
+
+    pub fn max_len(&self) -> i32 {
+        self.max_len
+    }
+
+    pub fn asn(&self) -> u32 {
+        self.asn
+    }
+
+    pub fn date(&self) -> NaiveDate {
+        self.date
+    }
+
+    /// The ROA object the entry was read from, when the input names it.
+    pub fn object(&self) -> Option<&RoaObject> {
+        self.object.as_ref()
+    }
+
+    /// The validator-reported expiry, when the input has one.
+    pub fn expires(&self) -> Option<i64> {
+        self.expires
+    }
+}
+
+/// Root of the RIPE NCC RPKI archive. A local mirror of this tree (plain
+/// directory or `file://` URL) can be used anywhere a root is accepted.
+pub const RIPE_RPKI_ROOT: &str = "https://ftp.ripe.net/rpki";
+
+#[derive(Debug, Clone)]
 pub struct RoaFile {
     pub url: String,
     pub tal: String,
@@ -34,7 +169,43 @@ pub struct RoaFile {
     pub processed: bool,
 }
 
+/// Whether a URL points to a remote archive rather than a local mirror.
+fn is_remote_url(url: &str) -> bool {
+    ["http://", "https://", "ftp://", "s3://", "r2://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+/// Strip an optional `file://` scheme, leaving a plain filesystem path.
+fn local_path(url: &str) -> &str {
+    url.strip_prefix("file://").unwrap_or(url)
+}
+
+/// List the names of sub-directories of a local mirror directory that consist
+/// of exactly `width` digits, sorted ascending.
+fn __list_local_dirs(dir: &str, width: usize) -> Vec<String> {
+    let read_dir = match std::fs::read_dir(local_path(dir)) {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("failed to list local directory {}: {}", dir, e);
+            return Vec::new();
+        }
+    };
+    let mut names: Vec<String> = read_dir
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name.len() == width && name.chars().all(|c| c.is_ascii_digit()))
+        .collect();
+    names.sort();
+    names
+}
+
 fn __crawl_years(tal_url: &str) -> Vec<String> {
+    if !is_remote_url(tal_url) {
+        return __list_local_dirs(tal_url, 4);
+    }
+
     let year_pattern: Regex = Regex::new(r#"<a href=".*">\s*(\d\d\d\d)/</a>.*"#).unwrap();
 
     // get all years
@@ -54,6 +225,10 @@ fn __crawl_years(tal_url: &str) -> Vec<String> {
 }
 
 fn __crawl_months_days(months_days_url: &str) -> Vec<String> {
+    if !is_remote_url(months_days_url) {
+        return __list_local_dirs(months_days_url, 2);
+    }
+
     let month_day_pattern: Regex = Regex::new(r#"<a href=".*">\s*(\d\d)/</a>.*"#).unwrap();
 
     let body = match oneio::read_to_string_lossy(months_days_url) {
@@ -101,17 +276,34 @@ fn check_date(
     from_match && until_match
 }
 
+/// Extract the TAL name from a TAL directory URL or path, e.g.
+/// `https://ftp.ripe.net/rpki/ripencc.tal` or `/mirror/rpki/ripencc.tal/` → `ripencc`.
+fn tal_from_url(tal_url: &str) -> String {
+    let dir = tal_url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    dir.strip_suffix(".tal").unwrap_or(dir).to_owned()
+}
+
 /// Crawl and return all RIPE ROA file metadata after a given date
 ///
 /// The ROA files URLs has the following format:
 /// https://ftp.ripe.net/ripe/rpki/ripencc.tal/2022/08/28/roas.csv.xz
+///
+/// `tal_url` may also be a local mirror directory (plain path or `file://` URL)
+/// with the same `<tal>.tal/YYYY/MM/DD/roas.csv.xz` layout; in that case the
+/// filesystem is walked instead of HTML listings, and only days that actually
+/// contain a `roas.csv.xz` file are returned.
 pub fn crawl_tal_after(
     tal_url: &str,
     from: Option<NaiveDate>,
     until: Option<NaiveDate>,
 ) -> Vec<RoaFile> {
-    let fields: Vec<&str> = tal_url.split('/').collect();
-    let tal = fields[4].split('.').collect::<Vec<&str>>()[0].to_owned();
+    let tal_url = tal_url.trim_end_matches('/');
+    let tal = tal_from_url(tal_url);
+    let is_local = !is_remote_url(tal_url);
 
     // get all years
     let years: Vec<i32> = __crawl_years(tal_url)
@@ -154,8 +346,10 @@ pub fn crawl_tal_after(
                         .collect();
 
                     days.into_iter()
-                        .map(|day| {
-                            let url = format!("{}/{:02}/roas.csv.xz", month_url, day);
+                        .map(|day| (day, format!("{}/{:02}/roas.csv.xz", month_url, day)))
+                        // a local mirror may hold partially-synced days
+                        .filter(|(_, url)| !is_local || Path::new(local_path(url)).is_file())
+                        .map(|(day, url)| {
                             let file_date = NaiveDate::from_ymd_opt(*year, *month, day).unwrap();
                             RoaFile {
                                 tal: tal.clone(),
@@ -174,66 +368,253 @@ pub fn crawl_tal_after(
         .collect::<Vec<RoaFile>>()
 }
 
-/// Parse a RIPE ROA CSV file and return a set of ROA entries.
-pub fn parse_roas_csv(csv_url: &str) -> Result<Vec<RoaEntry>> {
-    // parse csv url for auxiliary fields
-    let fields: Vec<&str> = csv_url.split('/').collect();
+/// Derive `(tal, date)` from the trailing `<tal>.tal/YYYY/MM/DD/roas.csv.xz`
+/// components of a ROA file URL or local path.
+fn parse_roa_file_path(csv_url: &str) -> Result<(String, NaiveDate)> {
+    let fields: Vec<&str> = csv_url.rsplit('/').take(5).collect();
+    if fields.len() < 5 {
+        return Err(anyhow!(
+            "ROA file path does not match <tal>.tal/YYYY/MM/DD/roas.csv.xz: {}",
+            csv_url
+        ));
+    }
 
-    let tal = fields[4].split('.').collect::<Vec<&str>>()[0].to_owned();
-    let year = fields[5].parse::<i32>()?;
-    let month = fields[6].parse::<u32>()?;
-    let day = fields[7].parse::<u32>()?;
-    let date = NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    let tal = tal_from_url(fields[4]);
+    let year = fields[3].parse::<i32>()?;
+    let month = fields[2].parse::<u32>()?;
+    let day = fields[1].parse::<u32>()?;
+    let date = NaiveDate::from_ymd_opt(year, month, day)
+        .ok_or_else(|| anyhow!("invalid date in ROA file path: {}", csv_url))?;
+    Ok((tal, date))
+}
 
-    let mut roas = HashSet::new();
+/// How a ROA CSV parser treats malformed rows. The default is
+/// [`ParseMode::Lenient`], as in `rebuild`, `update` and `serve`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail the whole file on its first malformed row.
+    Strict,
+    /// Skip malformed rows, reporting them in [`ParsedSnapshot::skipped`].
+    #[default]
+    Lenient,
+}
+
+/// What went wrong while parsing a ROA CSV file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoaCsvErrorKind {
+    /// The file path does not follow `<tal>.tal/YYYY/MM/DD/roas.csv.xz`.
+    InvalidPath,
+    /// The file could not be opened or read (e.g. truncated download).
+    Io(String),
+    /// The first line is not the expected `URI,ASN,...` header.
+    MissingHeader,
+    /// The row has fewer columns than required.
+    MissingColumn,
+    InvalidAsn(String),
+    InvalidPrefix(String),
+    InvalidMaxLength(String),
+    /// A VRP in a JSON export does not decode, with the decoder's message.
+    InvalidRecord(String),
+}
+
+/// A ROA CSV parse error with its location. `line` and `column` are 1-based;
+/// `line` is 0 for errors that concern the whole file. For VRP JSON exports,
+/// `line` is the VRP's position in the `roas` array.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoaCsvError {
+    pub file: String,
+    pub line: usize,
+    pub column: Option<usize>,
+    pub kind: RoaCsvErrorKind,
+}
+
+impl std::fmt::Display for RoaCsvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file)?;
+        if self.line > 0 {
+            write!(f, ":{}", self.line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+        match &self.kind {
+            RoaCsvErrorKind::InvalidPath => {
+                write!(f, ": path does not match <tal>.tal/YYYY/MM/DD/roas.csv.xz")
+            }
+            RoaCsvErrorKind::Io(e) => write!(f, ": read error: {}", e),
+            RoaCsvErrorKind::MissingHeader => write!(f, ": missing CSV header"),
+            RoaCsvErrorKind::MissingColumn => write!(f, ": missing column"),
+            RoaCsvErrorKind::InvalidAsn(v) => write!(f, ": invalid ASN '{}'", v),
+            RoaCsvErrorKind::InvalidPrefix(v) => write!(f, ": invalid prefix '{}'", v),
+            RoaCsvErrorKind::InvalidMaxLength(v) => write!(f, ": invalid max length '{}'", v),
+            RoaCsvErrorKind::InvalidRecord(e) => write!(f, ": invalid record: {}", e),
+        }
+    }
+}
 
-    let mut file_ok = false;
+impl std::error::Error for RoaCsvError {}
 
-    for line in oneio::read_lines_lossy(csv_url)? {
-        let line = line.unwrap();
+/// Entries of one snapshot, plus the rows skipped in [`ParseMode::Lenient`].
+#[derive(Debug, Default)]
+pub struct ParsedSnapshot {
+    pub entries: Vec<RoaEntry>,
+    pub skipped: Vec<RoaCsvError>,
+}
+
+/// Parse a RIPE ROA CSV file and return a set of ROA entries, failing on the
+/// first malformed row. See [`parse_roas_csv_with`].
+///
+/// `csv_url` may be a remote URL, a local path, or a `file://` URL, as long as
+/// it ends with `<tal>.tal/YYYY/MM/DD/roas.csv.xz`.
+pub fn parse_roas_csv(csv_url: &str) -> Result<Vec<RoaEntry>> {
+    Ok(parse_roas_csv_with(csv_url, ParseMode::Strict)?.entries)
+}
+
+/// Parse a RIPE ROA CSV file. In [`ParseMode::Lenient`], malformed rows are
+/// skipped and returned alongside the entries; file-level errors (bad path,
+/// unreadable file, missing header) fail in either mode.
+pub fn parse_roas_csv_with(
+    csv_url: &str,
+    mode: ParseMode,
+) -> std::result::Result<ParsedSnapshot, RoaCsvError> {
+    let file_error = |line: usize, kind: RoaCsvErrorKind| RoaCsvError {
+        file: csv_url.to_string(),
+        line,
+        column: None,
+        kind,
+    };
 
-        if line.starts_with("URI") {
-            file_ok = true;
+    // parse csv url for auxiliary fields
+    let (tal, date) =
+        parse_roa_file_path(csv_url).map_err(|_| file_error(0, RoaCsvErrorKind::InvalidPath))?;
+
+    let lines = oneio::read_lines_lossy(local_path(csv_url))
+        .map_err(|e| file_error(0, RoaCsvErrorKind::Io(e.to_string())))?;
+
+    let mut roas = HashSet::new();
+    let mut skipped = Vec::new();
+    for (idx, line) in lines.enumerate() {
+        let line_no = idx + 1;
+        let line = line.map_err(|e| file_error(line_no, RoaCsvErrorKind::Io(e.to_string())))?;
+
+        if idx == 0 {
+            if !line.starts_with("URI") {
+                return Err(file_error(line_no, RoaCsvErrorKind::MissingHeader));
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
             continue;
         }
 
-        if !file_ok {
-            return Err(anyhow!("file format incorrect!"));
+        match parse_roas_csv_row(&line, &tal, date) {
+            Ok(entry) => {
+                roas.insert(entry);
+            }
+            Err((column, kind)) => {
+                let error = RoaCsvError {
+                    file: csv_url.to_string(),
+                    line: line_no,
+                    column: Some(column),
+                    kind,
+                };
+                match mode {
+                    ParseMode::Strict => return Err(error),
+                    ParseMode::Lenient => skipped.push(error),
+                }
+            }
         }
+    }
 
-        let fields = line.split(',').collect::<Vec<&str>>();
-        let asn = fields[1].trim_start_matches("AS").parse::<u32>().unwrap();
-        let prefix = IpNet::from_str(fields[2].to_owned().as_str()).unwrap();
-        let max_len = match fields[3].to_owned().parse::<i32>() {
-            Ok(l) => l,
-            Err(_e) => prefix.prefix_len() as i32,
-        };
+    Ok(ParsedSnapshot {
+        entries: roas.into_iter().collect(),
+        skipped,
+    })
+}
 
-        let entry = RoaEntry {
-            prefix,
-            asn,
-            max_len,
-            tal: tal.to_owned(),
-            date,
-        };
+/// Parse one `URI,ASN,IP Prefix,Max Length,Not Before,Not After` row. Errors
+/// carry the 1-based column they were found in.
+fn parse_roas_csv_row(
+    line: &str,
+    tal: &str,
+    date: NaiveDate,
+) -> std::result::Result<RoaEntry, (usize, RoaCsvErrorKind)> {
+    let fields = line.split(',').collect::<Vec<&str>>();
+    let field = |idx: usize| {
+        fields
+            .get(idx)
+            .map(|f| f.trim())
+            .ok_or((idx + 1, RoaCsvErrorKind::MissingColumn))
+    };
 
-        roas.insert(entry);
-    }
+    let asn_str = field(1)?;
+    let asn = asn_str
+        .trim_start_matches("AS")
+        .parse::<u32>()
+        .map_err(|_| (2, RoaCsvErrorKind::InvalidAsn(asn_str.to_string())))?;
+    let prefix_str = field(2)?;
+    let prefix = IpNet::from_str(prefix_str)
+        .map_err(|_| (3, RoaCsvErrorKind::InvalidPrefix(prefix_str.to_string())))?;
+    let max_len = match field(3).unwrap_or_default() {
+        "" => prefix.prefix_len() as i32,
+        s => match s.parse::<u8>() {
+            Ok(l) if l >= prefix.prefix_len() && l <= prefix.max_prefix_len() => l as i32,
+            _ => return Err((4, RoaCsvErrorKind::InvalidMaxLength(s.to_string()))),
+        },
+    };
+
+    let object = match fields[0] {
+        "" => None,
+        uri => Some(RoaObject {
+            uri: uri.to_string(),
+            not_before: fields.get(4).and_then(|t| parse_validity_time(t)),
+            not_after: fields.get(5).and_then(|t| parse_validity_time(t)),
+        }),
+    };
+
+    Ok(RoaEntry {
+        prefix,
+        asn,
+        max_len,
+        tal: tal.to_owned(),
+        date,
+        object,
+        expires: None,
+    })
+}
 
-    Ok(roas.into_iter().collect::<Vec<RoaEntry>>())
+/// Parse a certificate validity timestamp as written by the RIPE archive
+/// (`2024-01-29 19:11:54`) or by validators (RFC 3339, with or without offset)
+/// into a UTC Unix timestamp.
+pub(crate) fn parse_validity_time(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Some(dt.timestamp());
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|fmt| chrono::NaiveDateTime::parse_from_str(s, fmt).ok())
+        .map(|dt| dt.and_utc().timestamp())
 }
 
 pub fn get_tal_urls(tal: Option<String>) -> Vec<String> {
+    get_tal_urls_from(RIPE_RPKI_ROOT, tal)
+}
+
+/// Like [`get_tal_urls`], but rooted at `root` instead of `ftp.ripe.net`.
+/// `root` can be a local mirror directory or `file://` URL with the same layout.
+pub fn get_tal_urls_from(root: &str, tal: Option<String>) -> Vec<String> {
+    let root = root.trim_end_matches('/');
     let tal_map = HashMap::from([
-        ("afrinic", "https://ftp.ripe.net/rpki/afrinic.tal"),
-        ("lacnic", "https://ftp.ripe.net/rpki/lacnic.tal"),
-        ("apnic", "https://ftp.ripe.net/rpki/apnic.tal"),
-        ("ripencc", "https://ftp.ripe.net/rpki/ripencc.tal"),
-        ("arin", "https://ftp.ripe.net/rpki/arin.tal"),
+        ("afrinic", format!("{}/afrinic.tal", root)),
+        ("lacnic", format!("{}/lacnic.tal", root)),
+        ("apnic", format!("{}/apnic.tal", root)),
+        ("ripencc", format!("{}/ripencc.tal", root)),
+        ("arin", format!("{}/arin.tal", root)),
     ]);
 
     match tal {
-        None => tal_map.values().map(|url| url.to_string()).collect(),
+        None => tal_map.into_values().collect(),
         Some(tal) => {
             let url = tal_map
                 .get(tal.as_str())
@@ -247,8 +628,34 @@ pub fn get_tal_urls(tal: Option<String>) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
+
+    const CSV_HEADER: &str = "URI,ASN,IP Prefix,Max Length,Not Before,Not After";
+
+    /// Create a local mirror with `<tal>.tal/YYYY/MM/DD/roas.csv.xz` files.
+    fn build_local_mirror(name: &str, files: &[(&str, &str, &[&str])]) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "wayback-rpki-mirror-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        for (tal, date, rows) in files {
+            let path = root
+                .join(format!("{}.tal", tal))
+                .join(date.replace('-', "/"))
+                .join("roas.csv.xz");
+            let mut writer = oneio::get_writer(path.to_str().unwrap()).unwrap();
+            writeln!(writer, "{}", CSV_HEADER).unwrap();
+            for row in rows.iter() {
+                writeln!(writer, "{}", row).unwrap();
+            }
+        }
+        root
+    }
 
     #[test]
+    #[ignore = "fetches ftp.ripe.net; run with --ignored"]
     fn test_parse() {
         let roas =
             parse_roas_csv("https://ftp.ripe.net/rpki/ripencc.tal/2022/01/15/roas.csv.xz").unwrap();
@@ -258,6 +665,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "fetches ftp.ripe.net; run with --ignored"]
     fn test_crawl_after() {
         let after_date = NaiveDate::from_ymd_opt(2023, 3, 31).unwrap();
         let roa_files = crawl_tal_after(
@@ -270,6 +678,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "fetches ftp.ripe.net; run with --ignored"]
     fn test_crawl_after_bootstrap() {
         let roa_files = crawl_tal_after("https://ftp.ripe.net/rpki/ripencc.tal", None, None);
         assert!(!roa_files.is_empty());
@@ -280,6 +689,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "fetches ftp.ripe.net; run with --ignored"]
     fn test_missing_prefix() {
         let roas =
             parse_roas_csv("https://ftp.ripe.net/rpki/ripencc.tal/2024/06/02/roas.csv.xz").unwrap();
@@ -289,4 +699,117 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_crawl_local_mirror() {
+        let root = build_local_mirror(
+            "crawl",
+            &[
+                ("ripencc", "2024-01-30", &[]),
+                ("ripencc", "2024-01-31", &[]),
+                ("ripencc", "2024-02-01", &[]),
+            ],
+        );
+        // a day directory without a roas.csv.xz file is skipped
+        std::fs::create_dir_all(root.join("ripencc.tal/2024/02/02")).unwrap();
+
+        let tal_url = format!("{}/ripencc.tal", root.to_str().unwrap());
+        let mut roa_files =
+            crawl_tal_after(tal_url.as_str(), NaiveDate::from_ymd_opt(2024, 1, 31), None);
+        roa_files.sort_by_key(|f| f.file_date);
+        let dates: Vec<NaiveDate> = roa_files.iter().map(|f| f.file_date).collect();
+        assert_eq!(
+            dates,
+            vec![
+                NaiveDate::from_ymd_opt(2024, 1, 31).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 2, 1).unwrap(),
+            ]
+        );
+        assert!(roa_files.iter().all(|f| f.tal == "ripencc"));
+
+        // file:// roots behave the same as plain paths
+        let file_url = format!("file://{}", tal_url);
+        assert_eq!(crawl_tal_after(file_url.as_str(), None, None).len(), 3);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_parse_local_mirror() {
+        let root = build_local_mirror(
+            "parse",
+            &[(
+                "apnic",
+                "2022-01-15",
+                &[
+                    "rsync://example.net/a.roa,AS13335,1.1.1.0/24,24,2022-01-01 00:00:00,2023-01-01 00:00:00",
+                    "rsync://example.net/b.roa,AS13335,2606:4700::/32,,2022-01-01 00:00:00,2023-01-01 00:00:00",
+                ],
+            )],
+        );
+
+        let urls = get_tal_urls_from(
+            format!("file://{}", root.to_str().unwrap()).as_str(),
+            Some("apnic".to_string()),
+        );
+        let roa_files = crawl_tal_after(urls[0].as_str(), None, None);
+        assert_eq!(roa_files.len(), 1);
+
+        let mut roas = parse_roas_csv(roa_files[0].url.as_str()).unwrap();
+        roas.sort_by_key(|r| r.prefix);
+        assert_eq!(roas.len(), 2);
+        assert_eq!(roas[0].tal, "apnic");
+        assert_eq!(roas[0].date, NaiveDate::from_ymd_opt(2022, 1, 15).unwrap());
+        assert_eq!(roas[0].asn, 13335);
+        assert_eq!(roas[0].max_len, 24);
+        // empty max length falls back to the prefix length
+        assert_eq!(roas[1].max_len, 32);
+
+        let object = roas[0].object.as_ref().unwrap();
+        assert_eq!(object.uri, "rsync://example.net/a.roa");
+        assert_eq!(object.not_before, Some(1640995200));
+        assert_eq!(object.not_after, Some(1672531200));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_parse_malformed_rows() {
+        let root = build_local_mirror(
+            "malformed",
+            &[(
+                "ripencc",
+                "2022-01-15",
+                &[
+                    "rsync://example.net/a.roa,AS13335,1.1.1.0/24,24,,",
+                    "rsync://example.net/b.roa,ASX,1.1.2.0/24,24,,",
+                    "rsync://example.net/c.roa,AS13335,1.1.3.0/33,24,,",
+                    "rsync://example.net/d.roa,AS13335,1.1.4.0/24,16,,",
+                    "rsync://example.net/e.roa,AS13335",
+                ],
+            )],
+        );
+        let url = format!(
+            "{}/ripencc.tal/2022/01/15/roas.csv.xz",
+            root.to_str().unwrap()
+        );
+
+        let err = parse_roas_csv_with(&url, ParseMode::Strict).unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(err.column, Some(2));
+        assert_eq!(err.kind, RoaCsvErrorKind::InvalidAsn("ASX".to_string()));
+        assert!(parse_roas_csv(&url).is_err());
+
+        let parsed = parse_roas_csv_with(&url, ParseMode::Lenient).unwrap();
+        assert_eq!(parsed.entries.len(), 1);
+        let locations: Vec<(usize, Option<usize>)> =
+            parsed.skipped.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(
+            locations,
+            vec![(3, Some(2)), (4, Some(3)), (5, Some(4)), (6, Some(3))]
+        );
+        assert_eq!(parsed.skipped[3].kind, RoaCsvErrorKind::MissingColumn);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
 }
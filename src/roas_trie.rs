@@ -1,113 +1,123 @@
-use crate::{crawl_tal_after, get_tal_urls, parse_roas_csv, RoaEntry, RoaFile};
+use crate::{
+    detect_anomalies, normalize_tal, parse_roas_csv, Anomaly, AnomalyConfig, GapPolicy,
+    IngestSummary, Observation, ParseMode, RoaEntry, RoaFile, RoaSource,
+};
 use anyhow::{anyhow, Result};
 use chrono::NaiveDate;
-use ipnet::IpNet;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use prefix_trie::joint::JointPrefixMap;
 use prefix_trie::{AsView, TrieView};
 use rkyv::{Archive, Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::io::Read;
+use std::net::{Ipv4Addr, Ipv6Addr};
 use tabled::Tabled;
 use tracing::{info, warn};
 
-/// On-disk format version. v2: rkyv archive of [`RoasTrieData`].
-pub const FORMAT_VERSION: u32 = 2;
+/// On-disk format version of the rkyv archive. 2: records without
+/// provenance (1.1.0 and earlier); 3: [`RoasTrieData`] with TALs, ROA objects,
+/// interpolated days, the observation calendar, the retry queue and the ASN
+/// index. [`RoasTrieMut::load`] migrates format-2 archives.
+pub const FORMAT_VERSION: u32 = 3;
 
 /// Default remote bootstrap URL. Platform-agnostic JSONL.gz transport format;
 /// the client streams it through a builder and dumps a local `.rkyv` for mmap serving.
 pub const REMOTE_BOOTSTRAP_URL: &str = "https://spaces.bgpkit.org/broker/roas_trie.jsonl.gz";
 
-pub(crate) const KNOWN_GAPS_STR: [(&str, &str); 76] = [
-    ("2016-01-27", "2016-01-29"),
-    ("2018-12-28", "2019-01-02"),
-    ("2019-10-22", "2019-10-22"),
-    ("2019-11-24", "2019-11-24"),
-    ("2020-02-24", "2020-02-24"),
-    ("2020-04-06", "2020-04-06"),
-    ("2020-08-03", "2020-08-03"),
-    ("2021-01-04", "2021-01-04"),
-    ("2021-02-28", "2021-02-28"),
-    ("2021-04-22", "2021-04-22"),
-    ("2021-04-25", "2021-04-27"),
-    ("2021-07-15", "2021-07-15"),
-    ("2021-07-19", "2021-07-19"),
-    ("2021-07-23", "2021-07-23"),
-    ("2021-07-31", "2021-07-31"),
-    ("2021-08-06", "2021-08-09"),
-    ("2021-08-10", "2021-08-10"),
-    ("2021-08-11", "2021-08-11"),
-    ("2021-08-13", "2021-08-14"),
-    ("2021-08-16", "2021-08-16"),
-    ("2021-08-20", "2021-08-23"),
-    ("2021-08-26", "2021-08-28"),
-    ("2021-09-03", "2021-09-03"),
-    ("2021-09-04", "2021-09-05"),
-    ("2021-09-06", "2021-09-07"),
-    ("2021-09-08", "2021-09-09"),
-    ("2021-09-10", "2021-09-25"),
-    ("2021-09-26", "2021-09-26"),
-    ("2021-09-27", "2021-09-28"),
-    ("2021-11-12", "2021-11-12"),
-    ("2021-12-03", "2021-12-10"),
-    ("2021-12-13", "2021-12-15"),
-    ("2022-01-03", "2022-01-06"),
-    ("2022-01-08", "2022-01-14"),
-    ("2022-01-15", "2022-01-15"),
-    ("2022-01-16", "2022-01-18"),
-    ("2022-01-19", "2022-01-19"),
-    ("2022-01-20", "2022-01-22"),
-    ("2022-01-24", "2022-01-24"),
-    ("2022-01-25", "2022-02-01"),
-    ("2022-02-02", "2022-02-03"),
-    ("2022-02-04", "2022-02-04"),
-    ("2022-02-05", "2022-02-12"),
-    ("2022-02-13", "2022-02-15"),
-    ("2022-02-16", "2022-02-17"),
-    ("2022-06-14", "2022-06-14"),
-    ("2022-08-27", "2022-08-27"),
-    ("2022-09-06", "2022-09-06"),
-    ("2022-09-08", "2022-09-08"),
-    ("2022-09-10", "2022-09-10"),
-    ("2022-09-14", "2022-09-14"),
-    ("2022-09-17", "2022-09-17"),
-    ("2022-09-20", "2022-09-22"),
-    ("2022-09-24", "2022-09-24"),
-    ("2022-09-26", "2022-09-26"),
-    ("2022-09-30", "2022-09-30"),
-    ("2022-10-03", "2022-10-03"),
-    ("2022-10-05", "2022-10-05"),
-    ("2022-10-07", "2022-10-07"),
-    ("2022-10-10", "2022-10-10"),
-    ("2022-10-14", "2022-10-14"),
-    ("2022-10-16", "2022-10-16"),
-    ("2022-10-19", "2022-10-20"),
-    ("2022-10-22", "2022-10-23"),
-    ("2022-10-26", "2022-10-26"),
-    ("2023-06-24", "2023-06-24"),
-    ("2023-07-14", "2023-07-17"),
-    ("2023-08-04", "2023-08-04"),
-    ("2026-03-27", "2026-04-03"),
-    ("2026-04-04", "2026-04-04"),
-    ("2026-04-05", "2026-04-10"),
-    ("2026-04-24", "2026-04-24"),
-    ("2026-04-30", "2026-05-03"),
-    ("2026-05-28", "2026-05-28"),
-    ("2026-06-13", "2026-06-13"),
-    ("2026-06-28", "2026-06-28"),
-];
-
 const ONE_DAY_SECONDS: i64 = 86400;
 
-/// A single ROA record in the serialized archive: one (max_len, origin) pair
-/// with its compressed date ranges.
+/// Delay before the first retry of a failed snapshot; doubles with every
+/// further failed attempt up to [`RETRY_BACKOFF_MAX_SECONDS`].
+const RETRY_BACKOFF_SECONDS: i64 = 3600;
+const RETRY_BACKOFF_MAX_SECONDS: i64 = 7 * ONE_DAY_SECONDS;
+/// Failed attempts after which a snapshot is abandoned: about 18 days of
+/// retries with the backoff above.
+pub const RETRY_MAX_ATTEMPTS: u32 = 10;
+
+/// A snapshot that could not be read during an update, kept in the archive
+/// so later updates can retry it.
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct FailedFile {
+    pub url: String,
+    pub tal: String,
+    /// Snapshot date, as a UTC midnight timestamp
+    pub date: i64,
+    /// Failed attempts so far
+    pub attempts: u32,
+    /// Unix timestamp of the latest attempt
+    pub last_attempt: i64,
+    /// Error of the latest attempt
+    pub error: String,
+}
+
+impl FailedFile {
+    /// Whether the file has failed [`RETRY_MAX_ATTEMPTS`] times. Abandoned
+    /// files stay in the archive to be reported, but are no longer retried.
+    pub fn abandoned(&self) -> bool {
+        self.attempts >= RETRY_MAX_ATTEMPTS
+    }
+
+    /// Earliest Unix timestamp at which the file is retried.
+    pub fn next_attempt(&self) -> i64 {
+        let backoff = RETRY_BACKOFF_SECONDS
+            .saturating_mul(1i64 << self.attempts.saturating_sub(1).min(20))
+            .min(RETRY_BACKOFF_MAX_SECONDS);
+        self.last_attempt + backoff
+    }
+
+    fn roa_file(&self) -> RoaFile {
+        RoaFile {
+            url: self.url.clone(),
+            tal: self.tal.clone(),
+            file_date: ts_to_date(self.date),
+            rows_count: 0,
+            processed: false,
+        }
+    }
+}
+
+/// A signed ROA object a record was published in: its repository URI and the
+/// validity window of its EE certificate, as UTC Unix timestamps.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct RoaObject {
+    pub uri: String,
+    pub not_before: Option<i64>,
+    pub not_after: Option<i64>,
+}
+
+/// Most ROA objects kept per record: the first one seen and the latest ones.
+/// A ROA reissued every few weeks would otherwise collect hundreds.
+pub const MAX_RECORD_OBJECTS: usize = 8;
+
+/// A single ROA record in the serialized archive: one (max_len, origin, tal)
+/// triple with its compressed date ranges.
 #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
 pub struct RoaRecord {
     /// ROA max length
     pub max_len: u8,
     /// ROA origin ASN
     pub origin: u32,
+    /// Trust anchor (TAL) that published the ROA, e.g. `ripencc`; empty when
+    /// unknown (legacy v1 data, or inputs without a trust-anchor column).
+    pub tal: String,
     /// Compressed date ranges, each tuple is (start_ts, end_ts), UTC day granularity.
     pub dates: Vec<(i64, i64)>,
+    /// The days of `dates` that were interpolated rather than observed (bridged
+    /// by `fill_gaps` or carried over a held-out day), as compressed ranges.
+    pub interpolated: Vec<(i64, i64)>,
+    /// ROA objects the record was seen in, in first-seen order: the first
+    /// one and the latest, at most [`MAX_RECORD_OBJECTS`]; empty when the
+    /// inputs did not name them.
+    pub objects: Vec<RoaObject>,
+    /// Distinct ROA objects the record was seen in, including those no
+    /// longer kept in `objects`. Once objects have been dropped, objects
+    /// first seen after the archive is reloaded are not counted, so this is
+    /// a lower bound.
+    pub object_count: u32,
+    /// Latest expiry a validator reported for the ROA (rpki-client
+    /// `expires`: the earliest expiry along its certificate chain), as a UTC
+    /// Unix timestamp; `None` when the inputs did not report one.
+    pub expires: Option<i64>,
 }
 
 /// Mutable per-ROA record used while building or updating the trie.
@@ -115,11 +125,24 @@ pub struct RoaRecord {
 pub struct RoaRecordMut {
     pub max_len: u8,
     pub origin: u32,
+    pub tal: String,
     /// Uncompressed dates collected during bootstrap.
     #[allow(dead_code)]
     pub(crate) dates: HashSet<i64>,
     /// Compressed date ranges.
     pub(crate) ranges: Vec<(i64, i64)>,
+    /// Interpolated days, as compressed ranges; a subset of the record's days.
+    pub(crate) interpolated: Vec<(i64, i64)>,
+    pub objects: Vec<RoaObject>,
+    pub object_count: u32,
+    /// The distinct objects seen while building, for deduplication; seeded
+    /// from `objects` on first use.
+    pub(crate) seen_objects: HashSet<RoaObject>,
+    /// Whether objects were dropped from `objects` before the record was
+    /// loaded. `seen_objects` cannot tell those from new ones, so
+    /// `object_count` no longer grows.
+    pub(crate) objects_dropped: bool,
+    pub expires: Option<i64>,
 }
 
 impl RoaRecordMut {
@@ -129,30 +152,100 @@ impl RoaRecordMut {
         RoaRecordMut {
             max_len,
             origin,
+            tal: String::new(),
             dates: HashSet::new(),
             ranges: Vec::new(),
+            interpolated: Vec::new(),
+            objects: Vec::new(),
+            object_count: 0,
+            seen_objects: HashSet::new(),
+            objects_dropped: false,
+            expires: None,
         }
     }
 
-    fn new(date_ts: i64, max_len: u8, origin: u32, bootstrap: bool) -> Self {
+    fn new(date_ts: i64, max_len: u8, origin: u32, tal: &str, bootstrap: bool) -> Self {
         if bootstrap {
             RoaRecordMut {
                 max_len,
                 origin,
+                tal: tal.to_string(),
                 dates: HashSet::from([date_ts]),
                 ranges: Vec::new(),
+                interpolated: Vec::new(),
+                objects: Vec::new(),
+                object_count: 0,
+                seen_objects: HashSet::new(),
+                objects_dropped: false,
+                expires: None,
             }
         } else {
             RoaRecordMut {
                 max_len,
                 origin,
+                tal: tal.to_string(),
                 dates: HashSet::new(),
                 ranges: vec![(date_ts, date_ts)],
+                interpolated: Vec::new(),
+                objects: Vec::new(),
+                object_count: 0,
+                seen_objects: HashSet::new(),
+                objects_dropped: false,
+                expires: None,
             }
         }
     }
 
+    /// Record that the ROA was seen in `object`. Beyond
+    /// [`MAX_RECORD_OBJECTS`], the oldest object after the first is dropped.
+    fn push_object(&mut self, object: &RoaObject) {
+        if self.seen_objects.is_empty() {
+            self.seen_objects = self.objects.iter().cloned().collect();
+        }
+        if !self.seen_objects.insert(object.clone()) {
+            return;
+        }
+        if !self.objects_dropped {
+            self.object_count += 1;
+        }
+        if self.objects.len() >= MAX_RECORD_OBJECTS {
+            self.objects.remove(1);
+        }
+        self.objects.push(object.clone());
+    }
+
+    /// Keep the latest of the known expiry and `expires`.
+    fn push_expires(&mut self, expires: Option<i64>) {
+        self.expires = self.expires.max(expires);
+    }
+
+    fn contains_day(&self, date_ts: i64) -> bool {
+        self.dates.contains(&date_ts)
+            || self
+                .ranges
+                .iter()
+                .any(|(start, end)| *start <= date_ts && date_ts <= *end)
+    }
+
+    /// Remove a single day, splitting the range that contains it.
+    fn remove_day(&mut self, date_ts: i64) {
+        self.dates.remove(&date_ts);
+        remove_range_day(&mut self.ranges, date_ts);
+        remove_range_day(&mut self.interpolated, date_ts);
+    }
+
+    /// Add the interpolated days `start_ts..=end_ts`.
+    fn push_interpolated(&mut self, start_ts: i64, end_ts: i64) {
+        self.dates
+            .extend((start_ts..=end_ts).step_by(ONE_DAY_SECONDS as usize));
+        add_range(&mut self.interpolated, start_ts, end_ts);
+    }
+
+    /// Add an observed day; an interpolated day becomes observed.
     fn push_date(&mut self, date_ts: i64, bootstrap: bool) {
+        if !self.interpolated.is_empty() {
+            remove_range_day(&mut self.interpolated, date_ts);
+        }
         if bootstrap {
             self.dates.insert(date_ts);
             return;
@@ -183,6 +276,9 @@ impl RoaRecordMut {
             }
         }
         all.sort_unstable();
+        // a day may be present both as a pending date and in a range, e.g.
+        // when a resumed rebuild re-processes a checkpointed file
+        all.dedup();
 
         let mut compressed: Vec<(i64, i64)> = Vec::new();
         if all.is_empty() {
@@ -205,14 +301,493 @@ impl RoaRecordMut {
     }
 }
 
+/// Secondary index entry: the prefixes holding at least one record of
+/// `origin`, each as (network address, prefix length), in trie order.
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize)]
+pub struct AsnIndexEntry {
+    pub origin: u32,
+    pub v4: Vec<(u32, u8)>,
+    pub v6: Vec<(u128, u8)>,
+}
+
 /// The serialized archive: header metadata plus the prefix trie.
 #[derive(Archive, Serialize, Deserialize)]
 pub struct RoasTrieData {
     pub format_version: u32,
     pub latest_date: i64,
+    /// Latest ingested snapshot date per source feed (TAL), sorted by name.
+    pub tal_latest: Vec<(String, i64)>,
+    /// Snapshots that failed and are queued for retry.
+    pub failed_files: Vec<FailedFile>,
+    /// Observation calendar: per feed (TAL), sorted by name, the ingested
+    /// snapshot days as (date_ts, rows_count), sorted by date.
+    pub observations: Vec<(String, Vec<(i64, u64)>)>,
+    /// Days held out as source anomalies, per feed (TAL), as date_ts.
+    pub held_out: Vec<(String, Vec<i64>)>,
+    /// TALs of the records published by feeds that are not a single TAL
+    /// (e.g. a VRP export directory), sorted by feed name.
+    pub feed_tals: Vec<(String, Vec<String>)>,
+    /// [`GapPolicy::hash`] of the known-gap policy last applied by `fill_gaps`.
+    pub gap_policy_hash: Option<String>,
     pub ipv4_count: u64,
     pub ipv6_count: u64,
     pub trie: JointPrefixMap<IpNet, Vec<RoaRecord>>,
+    /// Origin → prefix index, sorted by origin; built by `dump`.
+    pub asn_index: Vec<AsnIndexEntry>,
+    /// Identifies the dump that wrote the archive; differs between dumps
+    /// even when `latest_date` does not.
+    pub build_id: u64,
+}
+
+/// A record of a format-2 archive.
+#[derive(Archive, Serialize, Deserialize)]
+struct RoaRecordV2 {
+    max_len: u8,
+    origin: u32,
+    dates: Vec<(i64, i64)>,
+}
+
+/// The layout of a format-2 archive, read only to migrate it.
+#[derive(Archive, Serialize, Deserialize)]
+struct RoasTrieDataV2 {
+    format_version: u32,
+    latest_date: i64,
+    ipv4_count: u64,
+    ipv6_count: u64,
+    trie: JointPrefixMap<IpNet, Vec<RoaRecordV2>>,
+}
+
+/// The error for bytes that are not a current archive, naming the fix when
+/// they are a format-2 archive.
+fn archive_error(bytes: &[u8], e: impl std::fmt::Display) -> anyhow::Error {
+    let is_v2 = rkyv::access::<ArchivedRoasTrieDataV2, rkyv::rancor::Error>(bytes)
+        .is_ok_and(|data| data.format_version == 2);
+    if is_v2 {
+        anyhow!(
+            "trie archive is in format 2 (expected {}): convert it with \
+             `wayback-rpki convert --from <archive>`, or remove it to bootstrap from the \
+             JSONL transport again",
+            FORMAT_VERSION
+        )
+    } else {
+        anyhow!(
+            "trie archive validation failed: {} (archives written by an older \
+             version must be regenerated from a JSONL transport)",
+            e
+        )
+    }
+}
+
+/// How a search relates stored prefixes to the query prefix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchMode {
+    /// The query prefix only
+    #[default]
+    Exact,
+    /// The query prefix and its supernets: the ROAs that cover it
+    Covering,
+    /// The query prefix and its subnets: the ROAs inside it
+    CoveredBy,
+    /// The query prefix, its supernets and its subnets
+    Overlapping,
+}
+
+impl MatchMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchMode::Exact => "exact",
+            MatchMode::Covering => "covering",
+            MatchMode::CoveredBy => "covered_by",
+            MatchMode::Overlapping => "overlapping",
+        }
+    }
+
+    /// Inverse of [`MatchMode::as_str`].
+    pub fn parse(s: &str) -> Option<Self> {
+        [
+            MatchMode::Exact,
+            MatchMode::Covering,
+            MatchMode::CoveredBy,
+            MatchMode::Overlapping,
+        ]
+        .into_iter()
+        .find(|m| m.as_str() == s)
+    }
+
+    /// The mode of the former `exact` flag: `false` meant supernets and
+    /// subnets.
+    pub fn from_exact(exact: bool) -> Self {
+        match exact {
+            true => MatchMode::Exact,
+            false => MatchMode::Overlapping,
+        }
+    }
+}
+
+impl std::str::FromStr for MatchMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        MatchMode::parse(s).ok_or_else(|| {
+            anyhow!(
+                "unknown match mode '{}' (expected exact, covering, covered_by or overlapping)",
+                s
+            )
+        })
+    }
+}
+
+/// How a record's date ranges must relate to a `from`/`until` window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RangeMode {
+    /// Active on at least one day of the window
+    #[default]
+    Overlap,
+    /// Active on every day of the window
+    Within,
+}
+
+impl RangeMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RangeMode::Overlap => "overlap",
+            RangeMode::Within => "within",
+        }
+    }
+
+    /// Inverse of [`RangeMode::as_str`].
+    pub fn parse(s: &str) -> Option<Self> {
+        [RangeMode::Overlap, RangeMode::Within]
+            .into_iter()
+            .find(|m| m.as_str() == s)
+    }
+}
+
+impl std::str::FromStr for RangeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        RangeMode::parse(s)
+            .ok_or_else(|| anyhow!("unknown range mode '{}' (expected overlap or within)", s))
+    }
+}
+
+/// Filters of a search; the default query matches every record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Stored prefixes related to this one by `match_mode`
+    pub prefix: Option<IpNet>,
+    /// ROA origin ASN
+    pub origin: Option<u32>,
+    /// ROA max length
+    pub max_len: Option<u8>,
+    /// Active on this day
+    pub date: Option<NaiveDate>,
+    /// Active (`true`) or no longer active (`false`) on the archive's latest
+    /// day; takes precedence over `date`
+    pub current: Option<bool>,
+    /// The `from`/`until` and first/last-seen filters
+    pub window: DateRangeFilter,
+    /// How stored prefixes relate to `prefix`; no effect without a prefix
+    pub match_mode: MatchMode,
+    /// Trust anchor, in any spelling understood by [`normalize_tal`]
+    pub tal: Option<String>,
+    /// Treat interpolated days as absent: they neither match the
+    /// `date`/`current` filters nor appear in the returned date ranges
+    pub observed_only: bool,
+}
+
+/// Date-window filters of a search. All bounds are inclusive, and an unset
+/// bound is open; the default filter matches every record.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateRangeFilter {
+    /// First day of the window
+    pub from: Option<NaiveDate>,
+    /// Last day of the window; the archive's latest date when unset
+    pub until: Option<NaiveDate>,
+    /// How the window is matched; a `Within` window needs a `from` day
+    pub mode: RangeMode,
+    /// First seen on or after this day
+    pub first_seen_from: Option<NaiveDate>,
+    /// First seen on or before this day
+    pub first_seen_until: Option<NaiveDate>,
+    /// Last seen on or after this day
+    pub last_seen_from: Option<NaiveDate>,
+    /// Last seen on or before this day
+    pub last_seen_until: Option<NaiveDate>,
+}
+
+impl DateRangeFilter {
+    /// Reject a filter that cannot match anything: a `Within` window has no
+    /// default start, so it needs a `from` day.
+    pub fn check(&self) -> Result<(), &'static str> {
+        if self.mode == RangeMode::Within && self.from.is_none() {
+            return Err("range=within requires from");
+        }
+        Ok(())
+    }
+
+    /// Whether a record with the given (sorted) date ranges passes the
+    /// filter; `latest` closes a window without `until`. A filter that fails
+    /// [`DateRangeFilter::check`] matches nothing.
+    pub fn matches(&self, ranges: &[(NaiveDate, NaiveDate)], latest: NaiveDate) -> bool {
+        let between = |d: NaiveDate, lo: Option<NaiveDate>, hi: Option<NaiveDate>| {
+            lo.map_or(true, |lo| d >= lo) && hi.map_or(true, |hi| d <= hi)
+        };
+        if self.first_seen_from.is_some() || self.first_seen_until.is_some() {
+            match ranges.first() {
+                Some((first, _))
+                    if between(*first, self.first_seen_from, self.first_seen_until) => {}
+                _ => return false,
+            }
+        }
+        if self.last_seen_from.is_some() || self.last_seen_until.is_some() {
+            match ranges.last() {
+                Some((_, last)) if between(*last, self.last_seen_from, self.last_seen_until) => {}
+                _ => return false,
+            }
+        }
+        if self.from.is_none() && self.until.is_none() {
+            return true;
+        }
+        let until = self.until.unwrap_or(latest);
+        match self.mode {
+            RangeMode::Overlap => ranges
+                .iter()
+                .any(|(start, end)| *start <= until && self.from.map_or(true, |from| *end >= from)),
+            RangeMode::Within => self.from.is_some_and(|from| {
+                ranges
+                    .iter()
+                    .any(|(start, end)| *start <= from && *end >= until)
+            }),
+        }
+    }
+}
+
+/// Order of search results. `Prefix` is the archive's trie order; the other
+/// keys sort stably, so ties keep that order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Prefix,
+    Origin,
+    FirstSeen,
+    LastSeen,
+}
+
+impl SortKey {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortKey::Prefix => "prefix",
+            SortKey::Origin => "origin",
+            SortKey::FirstSeen => "first_seen",
+            SortKey::LastSeen => "last_seen",
+        }
+    }
+
+    /// Inverse of [`SortKey::as_str`].
+    pub fn parse(s: &str) -> Option<Self> {
+        [
+            SortKey::Prefix,
+            SortKey::Origin,
+            SortKey::FirstSeen,
+            SortKey::LastSeen,
+        ]
+        .into_iter()
+        .find(|k| k.as_str() == s)
+    }
+
+    /// Sort search results, which must be in trie order, by this key.
+    pub fn sort<T: SortableRecord>(&self, records: &mut [T]) {
+        match self {
+            SortKey::Prefix => {}
+            SortKey::Origin => records.sort_by_key(|r| r.origin()),
+            SortKey::FirstSeen => records.sort_by_cached_key(|r| r.first_seen()),
+            SortKey::LastSeen => records.sort_by_cached_key(|r| r.last_seen()),
+        }
+    }
+
+    /// The value `record` is sorted by; days without a date sort first.
+    fn value<T: SortableRecord>(&self, record: &T) -> i64 {
+        match self {
+            SortKey::Prefix => 0,
+            SortKey::Origin => record.origin() as i64,
+            SortKey::FirstSeen => record.first_seen().map_or(i64::MIN, date_to_ts),
+            SortKey::LastSeen => record.last_seen().map_or(i64::MIN, date_to_ts),
+        }
+    }
+
+    /// Where `record` sits in results sorted by this key.
+    pub fn position<T: SortableRecord>(&self, record: &T) -> SearchPosition {
+        SearchPosition {
+            value: self.value(record),
+            prefix: record.prefix(),
+            origin: record.origin(),
+            max_len: record.max_len(),
+            tal: record.tal().to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        SortKey::parse(s).ok_or_else(|| {
+            anyhow!(
+                "unknown sort key '{}' (expected prefix, origin, first_seen or last_seen)",
+                s
+            )
+        })
+    }
+}
+
+/// A search result that [`SortKey::sort`] can order.
+pub trait SortableRecord {
+    fn prefix(&self) -> IpNet;
+    fn origin(&self) -> u32;
+    fn max_len(&self) -> u8;
+    fn tal(&self) -> &str;
+    fn first_seen(&self) -> Option<NaiveDate>;
+    fn last_seen(&self) -> Option<NaiveDate>;
+}
+
+impl SortableRecord for RoasLookupEntry {
+    fn prefix(&self) -> IpNet {
+        self.prefix
+    }
+
+    fn origin(&self) -> u32 {
+        self.origin
+    }
+
+    fn max_len(&self) -> u8 {
+        self.max_len
+    }
+
+    fn tal(&self) -> &str {
+        &self.tal
+    }
+
+    fn first_seen(&self) -> Option<NaiveDate> {
+        self.dates_ranges.first().map(|range| range.0)
+    }
+
+    fn last_seen(&self) -> Option<NaiveDate> {
+        self.dates_ranges.last().map(|range| range.1)
+    }
+}
+
+impl SortableRecord for RoaRecordRef<'_> {
+    fn prefix(&self) -> IpNet {
+        RoaRecordRef::prefix(self)
+    }
+
+    fn origin(&self) -> u32 {
+        RoaRecordRef::origin(self)
+    }
+
+    fn max_len(&self) -> u8 {
+        RoaRecordRef::max_len(self)
+    }
+
+    fn tal(&self) -> &str {
+        RoaRecordRef::tal(self)
+    }
+
+    fn first_seen(&self) -> Option<NaiveDate> {
+        RoaRecordRef::first_seen(self)
+    }
+
+    fn last_seen(&self) -> Option<NaiveDate> {
+        RoaRecordRef::last_seen(self)
+    }
+}
+
+/// Place of a search result in its sort order: the [`SortKey`] value, then
+/// trie order (prefix, then origin, max length and TAL within a prefix),
+/// which breaks ties. Results compare in the order they are returned.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SearchPosition {
+    pub value: i64,
+    pub prefix: IpNet,
+    pub origin: u32,
+    pub max_len: u8,
+    pub tal: String,
+}
+
+impl SearchPosition {
+    /// Whether a record at (`prefix`, `origin`, `max_len`, `tal`) in trie
+    /// order comes at or before this position; `value` is ignored.
+    fn reaches(&self, prefix: IpNet, origin: u32, max_len: u8, tal: &str) -> bool {
+        (prefix, origin, max_len, tal)
+            <= (self.prefix, self.origin, self.max_len, self.tal.as_str())
+    }
+}
+
+/// Position in a paginated search: the last result of the previous page. A
+/// cursor is bound to one archive build and one query, so a page is never
+/// read from a different result list than the one it continues.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchCursor {
+    /// [`RoasTrie::build_id`] of the archive the cursor was issued for
+    pub build_id: u64,
+    /// Caller-defined fingerprint of the query and its sort order
+    pub query: u64,
+    /// Number of the next page
+    pub page: usize,
+    /// The last result before the next page
+    pub last: SearchPosition,
+}
+
+impl SearchCursor {
+    /// The opaque string form of the cursor: hex digits only, so it can be
+    /// passed in a URL as-is.
+    pub fn encode(&self) -> String {
+        let last = format!(
+            "{} {} {} {} {}",
+            self.last.value, self.last.prefix, self.last.origin, self.last.max_len, self.last.tal
+        );
+        let mut out = format!(
+            "{:016x}{:016x}{:016x}",
+            self.build_id, self.query, self.page
+        );
+        for byte in last.bytes() {
+            out.push_str(&format!("{:02x}", byte));
+        }
+        out
+    }
+
+    /// Inverse of [`SearchCursor::encode`].
+    pub fn decode(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("invalid cursor '{}'", s);
+        if s.len() <= 48 || s.len() % 2 != 0 || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let field = |range: std::ops::Range<usize>| {
+            u64::from_str_radix(&s[range], 16).map_err(|_| invalid())
+        };
+        let bytes = (48..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| invalid()))
+            .collect::<Result<Vec<u8>>>()?;
+        let last = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let mut fields = last.splitn(5, ' ');
+        let mut next = || fields.next().ok_or_else(invalid);
+        let last = SearchPosition {
+            value: next()?.parse().map_err(|_| invalid())?,
+            prefix: next()?.parse().map_err(|_| invalid())?,
+            origin: next()?.parse().map_err(|_| invalid())?,
+            max_len: next()?.parse().map_err(|_| invalid())?,
+            tal: next()?.to_string(),
+        };
+        Ok(SearchCursor {
+            build_id: field(0..16)?,
+            query: field(16..32)?,
+            page: field(32..48)? as usize,
+            last,
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -220,7 +795,61 @@ pub struct RoasLookupEntry {
     pub prefix: IpNet,
     pub origin: u32,
     pub max_len: u8,
+    /// Trust anchor that published the ROA; empty when unknown.
+    pub tal: String,
     pub dates_ranges: Vec<(NaiveDate, NaiveDate)>,
+    /// The days of `dates_ranges` that were interpolated rather than observed.
+    pub interpolated_ranges: Vec<(NaiveDate, NaiveDate)>,
+    /// ROA objects the record was seen in (see [`RoaRecord::objects`]);
+    /// empty when unknown.
+    pub objects: Vec<RoaObject>,
+    /// Distinct ROA objects the record was seen in, kept or not.
+    pub object_count: u32,
+    /// Latest validator-reported expiry (see [`RoaRecord::expires`]).
+    pub expires: Option<i64>,
+}
+
+/// Why a ROA record is no longer published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoaEnd {
+    /// Its last day is the expiry of its latest object (or the day before).
+    Expired,
+    /// It disappeared while its latest object was still valid.
+    Revoked,
+}
+
+impl RoaEnd {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoaEnd::Expired => "expired",
+            RoaEnd::Revoked => "revoked",
+        }
+    }
+}
+
+impl RoasLookupEntry {
+    /// The day the record was last seen.
+    pub fn last_seen(&self) -> Option<NaiveDate> {
+        self.dates_ranges.last().map(|(_, end)| *end)
+    }
+
+    /// Why the record ended, comparing its last day with the latest expiry
+    /// known for it (its objects' `not_after`, or `expires`). `None` while
+    /// the record is still seen on `latest`, or when no expiry is known.
+    pub fn end(&self, latest: NaiveDate) -> Option<RoaEnd> {
+        let last_seen = self.last_seen().filter(|d| *d < latest)?;
+        let expiry = self
+            .objects
+            .iter()
+            .filter_map(|o| o.not_after)
+            .chain(self.expires)
+            .max()?;
+        if ts_to_date(expiry) <= last_seen + chrono::Duration::days(1) {
+            Some(RoaEnd::Expired)
+        } else {
+            Some(RoaEnd::Revoked)
+        }
+    }
 }
 
 #[derive(Debug, Clone, Tabled)]
@@ -228,21 +857,27 @@ pub struct RoasLookupEntryTabled {
     pub origin: u32,
     pub prefix: String,
     pub max_len: u8,
+    pub tal: String,
     pub dates_ranges: String,
+    pub interpolated_ranges: String,
 }
 
 impl From<RoasLookupEntry> for RoasLookupEntryTabled {
     fn from(entry: RoasLookupEntry) -> Self {
+        let fmt = |ranges: &[(NaiveDate, NaiveDate)]| {
+            ranges
+                .iter()
+                .map(|(start, end)| format!("({},{})", start, end))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
         RoasLookupEntryTabled {
             origin: entry.origin,
             prefix: entry.prefix.to_string(),
             max_len: entry.max_len,
-            dates_ranges: entry
-                .dates_ranges
-                .iter()
-                .map(|(start, end)| format!("({},{})", start, end))
-                .collect::<Vec<String>>()
-                .join(", "),
+            tal: entry.tal,
+            dates_ranges: fmt(&entry.dates_ranges),
+            interpolated_ranges: fmt(&entry.interpolated_ranges),
         }
     }
 }
@@ -264,6 +899,72 @@ impl std::fmt::Display for RpkiValidation {
     }
 }
 
+/// How a VRP covering a route relates to it (RFC 6811).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VrpMatch {
+    /// Same origin, and the route is no longer than the max length
+    Matched,
+    /// The origin differs from the route's; always the case for AS0 ROAs
+    OriginMismatch,
+    /// Same origin, but the route is longer than the max length
+    MaxLengthExceeded,
+}
+
+impl VrpMatch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VrpMatch::Matched => "matched",
+            VrpMatch::OriginMismatch => "origin_mismatch",
+            VrpMatch::MaxLengthExceeded => "max_length_exceeded",
+        }
+    }
+
+    /// Classify a covering VRP against a route of `origin` and `prefix_len`.
+    pub fn classify(vrp_origin: u32, vrp_max_len: u8, origin: u32, prefix_len: u8) -> Self {
+        if vrp_origin != origin || vrp_origin == 0 {
+            VrpMatch::OriginMismatch
+        } else if prefix_len > vrp_max_len {
+            VrpMatch::MaxLengthExceeded
+        } else {
+            VrpMatch::Matched
+        }
+    }
+}
+
+/// A VRP covering a validated route, active on the validation date.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoveringVrp {
+    pub prefix: IpNet,
+    pub max_len: u8,
+    pub origin: u32,
+    /// Trust anchor that published the ROA; empty when unknown.
+    pub tal: String,
+    pub status: VrpMatch,
+}
+
+/// The validation state of a route and the covering VRPs that decided it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub result: RpkiValidation,
+    /// Covering VRPs active on the date, shortest prefix first
+    pub vrps: Vec<CoveringVrp>,
+}
+
+impl ValidationReport {
+    /// RFC 6811: `Valid` if any covering VRP matches, `Invalid` if some
+    /// cover the route but none matches, `Unknown` (NotFound) if none cover it.
+    pub fn from_vrps(vrps: Vec<CoveringVrp>) -> Self {
+        let result = if vrps.iter().any(|v| v.status == VrpMatch::Matched) {
+            RpkiValidation::Valid
+        } else if vrps.is_empty() {
+            RpkiValidation::Unknown
+        } else {
+            RpkiValidation::Invalid
+        };
+        ValidationReport { result, vrps }
+    }
+}
+
 pub(crate) fn ts_to_date(ts: i64) -> NaiveDate {
     chrono::DateTime::from_timestamp(ts, 0)
         .unwrap()
@@ -277,10 +978,70 @@ pub(crate) fn date_to_ts(date: NaiveDate) -> i64 {
         .timestamp()
 }
 
+/// Add the days `start..=end` to sorted, compressed `ranges`, merging
+/// overlapping and adjacent ranges.
+fn add_range(ranges: &mut Vec<(i64, i64)>, start: i64, end: i64) {
+    ranges.push((start, end));
+    ranges.sort_unstable();
+    let mut merged: Vec<(i64, i64)> = Vec::with_capacity(ranges.len());
+    for &(s, e) in ranges.iter() {
+        match merged.last_mut() {
+            Some(last) if s <= last.1 + ONE_DAY_SECONDS => last.1 = last.1.max(e),
+            _ => merged.push((s, e)),
+        }
+    }
+    *ranges = merged;
+}
+
+/// Remove a single day from compressed `ranges`, splitting the range that
+/// contains it.
+fn remove_range_day(ranges: &mut Vec<(i64, i64)>, date_ts: i64) {
+    if let Some(idx) = ranges
+        .iter()
+        .position(|(start, end)| *start <= date_ts && date_ts <= *end)
+    {
+        let (start, end) = ranges.remove(idx);
+        if date_ts < end {
+            ranges.insert(idx, (date_ts + ONE_DAY_SECONDS, end));
+        }
+        if start < date_ts {
+            ranges.insert(idx, (start, date_ts - ONE_DAY_SECONDS));
+        }
+    }
+}
+
+/// The days of sorted, compressed `ranges` that are not in `minus`.
+fn subtract_ranges(ranges: &[(i64, i64)], minus: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut out = Vec::new();
+    for &(start, end) in ranges {
+        let mut cur = start;
+        for &(m_start, m_end) in minus {
+            if m_end < cur || m_start > end {
+                continue;
+            }
+            if m_start > cur {
+                out.push((cur, m_start - ONE_DAY_SECONDS));
+            }
+            cur = m_end + ONE_DAY_SECONDS;
+        }
+        if cur <= end {
+            out.push((cur, end));
+        }
+    }
+    out
+}
+
 /// Builder/mutable trie for rebuild, update, and fix operations.
 pub struct RoasTrieMut {
     trie: JointPrefixMap<IpNet, Vec<RoaRecordMut>>,
     latest_date: i64,
+    tal_latest: BTreeMap<String, i64>,
+    failed_files: Vec<FailedFile>,
+    observations: BTreeMap<String, BTreeMap<i64, u64>>,
+    held_out: BTreeMap<String, BTreeSet<i64>>,
+    feed_tals: BTreeMap<String, BTreeSet<String>>,
+    gap_policy_hash: Option<String>,
+    anomaly_config: AnomalyConfig,
 }
 
 impl Default for RoasTrieMut {
@@ -294,16 +1055,32 @@ impl RoasTrieMut {
         RoasTrieMut {
             trie: JointPrefixMap::new(),
             latest_date: 0,
+            tal_latest: BTreeMap::new(),
+            failed_files: Vec::new(),
+            observations: BTreeMap::new(),
+            held_out: BTreeMap::new(),
+            feed_tals: BTreeMap::new(),
+            gap_policy_hash: None,
+            anomaly_config: AnomalyConfig::default(),
         }
     }
 
-    /// Load a v2 archive from disk into a mutable trie for updating.
+    /// Load a v2 archive from disk into a mutable trie for updating. A
+    /// format-2 archive is migrated; `dump` writes it in the current format.
     pub fn load(path: &str) -> Result<Self> {
         info!("loading trie from {} ...", path);
         let mut bytes = Vec::new();
         oneio::get_reader(path)?.read_to_end(&mut bytes)?;
-        let data: RoasTrieData = rkyv::from_bytes::<_, rkyv::rancor::Error>(&bytes)
-            .map_err(|e| anyhow!("failed to deserialize trie archive: {}", e))?;
+        let data: RoasTrieData = match rkyv::from_bytes::<_, rkyv::rancor::Error>(&bytes) {
+            Ok(data) => data,
+            Err(e) => match rkyv::from_bytes::<RoasTrieDataV2, rkyv::rancor::Error>(&bytes) {
+                Ok(old) if old.format_version == 2 => {
+                    info!("migrating format 2 archive {} ...", path);
+                    return Ok(Self::from_v2(old));
+                }
+                _ => return Err(anyhow!("failed to deserialize trie archive: {}", e)),
+            },
+        };
         if data.format_version != FORMAT_VERSION {
             return Err(anyhow!(
                 "unsupported trie format version {} (expected {})",
@@ -318,8 +1095,15 @@ impl RoasTrieMut {
                 .map(|r| RoaRecordMut {
                     max_len: r.max_len,
                     origin: r.origin,
+                    tal: r.tal.clone(),
                     dates: HashSet::new(),
                     ranges: r.dates.clone(),
+                    interpolated: r.interpolated.clone(),
+                    objects: r.objects.clone(),
+                    object_count: r.object_count,
+                    seen_objects: HashSet::new(),
+                    objects_dropped: r.object_count as usize > r.objects.len(),
+                    expires: r.expires,
                 })
                 .collect();
             trie.insert(prefix, recs);
@@ -332,9 +1116,43 @@ impl RoasTrieMut {
         Ok(RoasTrieMut {
             trie,
             latest_date: data.latest_date,
+            tal_latest: data.tal_latest.into_iter().collect(),
+            failed_files: data.failed_files,
+            observations: data
+                .observations
+                .into_iter()
+                .map(|(tal, days)| (tal, days.into_iter().collect()))
+                .collect(),
+            held_out: data
+                .held_out
+                .into_iter()
+                .map(|(tal, days)| (tal, days.into_iter().collect()))
+                .collect(),
+            feed_tals: data
+                .feed_tals
+                .into_iter()
+                .map(|(feed, tals)| (feed, tals.into_iter().collect()))
+                .collect(),
+            gap_policy_hash: data.gap_policy_hash,
+            anomaly_config: AnomalyConfig::default(),
         })
     }
 
+    /// Migrate a format-2 archive. Its records carry no TAL; each is adopted
+    /// by the first TAL that publishes the ROA again (see `process_entries`).
+    fn from_v2(data: RoasTrieDataV2) -> Self {
+        let mut trie = Self::new();
+        trie.latest_date = data.latest_date;
+        for (prefix, records) in data.trie.iter() {
+            for r in records {
+                let mut record = RoaRecordMut::new_legacy(r.max_len, r.origin);
+                record.ranges = r.dates.clone();
+                trie.insert_record(prefix, record);
+            }
+        }
+        trie
+    }
+
     /// Compress all in-flight dates and serialize the trie to `path` (atomically).
     pub fn dump(&mut self, path: &str) -> Result<()> {
         info!("compressing dates into date ranges...");
@@ -343,17 +1161,35 @@ impl RoasTrieMut {
         let mut v4_count: u64 = 0;
         let mut v6_count: u64 = 0;
         let mut out: JointPrefixMap<IpNet, Vec<RoaRecord>> = JointPrefixMap::new();
+        let mut asn_index: BTreeMap<u32, AsnIndexEntry> = BTreeMap::new();
         for (prefix, records) in self.trie.iter() {
             match prefix {
                 IpNet::V4(_) => v4_count += 1,
                 IpNet::V6(_) => v6_count += 1,
             }
+            let origins: BTreeSet<u32> = records.iter().map(|r| r.origin).collect();
+            for origin in origins {
+                let entry = asn_index.entry(origin).or_insert_with(|| AsnIndexEntry {
+                    origin,
+                    v4: Vec::new(),
+                    v6: Vec::new(),
+                });
+                match prefix {
+                    IpNet::V4(p) => entry.v4.push((p.addr().into(), p.prefix_len())),
+                    IpNet::V6(p) => entry.v6.push((p.addr().into(), p.prefix_len())),
+                }
+            }
             let recs: Vec<RoaRecord> = records
                 .iter()
                 .map(|r| RoaRecord {
                     max_len: r.max_len,
                     origin: r.origin,
+                    tal: r.tal.clone(),
                     dates: r.ranges.clone(),
+                    interpolated: r.interpolated.clone(),
+                    objects: r.objects.clone(),
+                    object_count: r.object_count,
+                    expires: r.expires,
                 })
                 .collect();
             out.insert(prefix, recs);
@@ -362,9 +1198,36 @@ impl RoasTrieMut {
         let data = RoasTrieData {
             format_version: FORMAT_VERSION,
             latest_date: self.latest_date,
+            tal_latest: self
+                .tal_latest
+                .iter()
+                .map(|(tal, ts)| (tal.clone(), *ts))
+                .collect(),
+            failed_files: self.failed_files.clone(),
+            observations: self
+                .observations
+                .iter()
+                .map(|(tal, days)| (tal.clone(), days.iter().map(|(d, r)| (*d, *r)).collect()))
+                .collect(),
+            held_out: self
+                .held_out
+                .iter()
+                .map(|(tal, days)| (tal.clone(), days.iter().copied().collect()))
+                .collect(),
+            feed_tals: self
+                .feed_tals
+                .iter()
+                .map(|(feed, tals)| (feed.clone(), tals.iter().cloned().collect()))
+                .collect(),
+            gap_policy_hash: self.gap_policy_hash.clone(),
             ipv4_count: v4_count,
             ipv6_count: v6_count,
             trie: out,
+            asn_index: asn_index.into_values().collect(),
+            build_id: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default(),
         };
         let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&data)
             .map_err(|e| anyhow!("failed to serialize trie: {}", e))?;
@@ -392,24 +1255,44 @@ impl RoasTrieMut {
             let prefix = entry.prefix;
             let max_len = entry.max_len as u8;
             let origin = entry.asn;
+            let tal = entry.tal.as_str();
             let date_ts = date_to_ts(entry.date);
 
-            match self.trie.get_mut(&prefix) {
-                Some(recs) => match recs
-                    .iter_mut()
-                    .find(|r| r.max_len == max_len && r.origin == origin)
-                {
-                    Some(r) => r.push_date(date_ts, bootstrap),
-                    None => recs.push(RoaRecordMut::new(date_ts, max_len, origin, bootstrap)),
-                },
+            let recs = match self.trie.get_mut(&prefix) {
+                Some(recs) => recs,
                 None => {
-                    self.trie.insert(
-                        prefix,
-                        vec![RoaRecordMut::new(date_ts, max_len, origin, bootstrap)],
-                    );
+                    self.trie.insert(prefix, Vec::new());
+                    self.trie.get_mut(&prefix).unwrap()
                 }
-            }
-
+            };
+            // a record without provenance (v1 or format-2 data, or a JSONL
+            // transport without `t`) is adopted by the first TAL that
+            // publishes the ROA again instead of being kept beside a new one
+            let idx = recs
+                .iter()
+                .position(|r| r.max_len == max_len && r.origin == origin && r.tal == tal)
+                .or_else(|| {
+                    let idx = recs.iter().position(|r| {
+                        r.max_len == max_len && r.origin == origin && r.tal.is_empty()
+                    })?;
+                    recs[idx].tal = tal.to_string();
+                    Some(idx)
+                });
+            let record = match idx {
+                Some(idx) => {
+                    recs[idx].push_date(date_ts, bootstrap);
+                    &mut recs[idx]
+                }
+                None => {
+                    recs.push(RoaRecordMut::new(date_ts, max_len, origin, tal, bootstrap));
+                    recs.last_mut().unwrap()
+                }
+            };
+            if let Some(object) = &entry.object {
+                record.push_object(object);
+            }
+            record.push_expires(entry.expires());
+
             if date_ts > self.latest_date {
                 self.latest_date = date_ts;
             }
@@ -428,72 +1311,336 @@ impl RoasTrieMut {
         }
     }
 
-    /// Incremental update: crawl new ROA files since `latest_date` and apply.
-    pub fn update(&mut self, tal: Option<String>, until: Option<NaiveDate>) -> Result<()> {
-        info!("updating trie... tal: {:?}, until: {:?}", &tal, &until);
-        let mut all_files = get_tal_urls(tal)
-            .into_iter()
-            .flat_map(|tal_url| {
-                crawl_tal_after(
-                    tal_url.as_str(),
-                    Some(self.get_latest_date() + chrono::Duration::days(1)),
-                    until,
-                )
+    /// Record that the snapshot of feed `tal` for `date` has been ingested,
+    /// raising that feed's high-water mark.
+    pub fn mark_ingested(&mut self, tal: &str, date: NaiveDate) {
+        let ts = date_to_ts(date);
+        let latest = self.tal_latest.entry(tal.to_string()).or_insert(ts);
+        *latest = (*latest).max(ts);
+    }
+
+    /// Latest ingested snapshot date of each feed.
+    pub fn tal_latest_dates(&self) -> Vec<(String, NaiveDate)> {
+        self.tal_latest
+            .iter()
+            .map(|(tal, ts)| (tal.clone(), ts_to_date(*ts)))
+            .collect()
+    }
+
+    /// Add the snapshot of feed `tal` for `date`, which had `rows` rows, to
+    /// the observation calendar.
+    pub fn record_observation(&mut self, tal: &str, date: NaiveDate, rows: u64) {
+        self.observations
+            .entry(tal.to_string())
+            .or_default()
+            .insert(date_to_ts(date), rows);
+    }
+
+    /// Remember the TALs of the `entries` a snapshot of `feed` published,
+    /// so that a held-out day of the feed reaches their records.
+    pub fn record_feed_tals(&mut self, feed: &str, entries: &[RoaEntry]) {
+        for entry in entries.iter().filter(|e| e.tal() != feed) {
+            if !self
+                .feed_tals
+                .get(feed)
+                .is_some_and(|t| t.contains(entry.tal()))
+            {
+                self.feed_tals
+                    .entry(feed.to_string())
+                    .or_default()
+                    .insert(entry.tal().to_string());
+            }
+        }
+    }
+
+    /// The observation calendar, by feed and then date.
+    pub fn observations(&self) -> Vec<Observation> {
+        self.observations
+            .iter()
+            .flat_map(|(tal, days)| {
+                days.iter().map(move |(ts, rows)| Observation {
+                    tal: tal.clone(),
+                    date: ts_to_date(*ts),
+                    rows: *rows,
+                })
             })
-            .collect::<Vec<RoaFile>>();
+            .collect()
+    }
+
+    /// Use `config` for the anomaly checks of `update` and `check_anomalies`.
+    pub fn with_anomaly_config(mut self, config: AnomalyConfig) -> Self {
+        self.anomaly_config = config;
+        self
+    }
+
+    /// Flag anomalous observed days selected by `days`, holding them out of
+    /// the trie when the anomaly config asks for it.
+    pub fn check_anomalies(&mut self, days: impl Fn(&str, NaiveDate) -> bool) -> Vec<Anomaly> {
+        let mut anomalies = detect_anomalies(&self.observations(), days, &self.anomaly_config);
+        for anomaly in anomalies.iter_mut() {
+            warn!(
+                "source anomaly: {} {} {} ({} row(s), previous day {})",
+                anomaly.tal,
+                anomaly.date,
+                anomaly.kind.as_str(),
+                anomaly.rows,
+                anomaly
+                    .baseline
+                    .map_or_else(|| "unknown".to_string(), |b| b.to_string())
+            );
+            if self.anomaly_config.hold_out {
+                self.hold_out_day(&anomaly.tal, anomaly.date);
+                anomaly.held_out = true;
+            }
+        }
+        anomalies
+    }
+
+    /// Replace the snapshot of feed `tal` on `date` with the previous day's
+    /// state: the day is removed from every record of the feed (those of
+    /// TAL `tal`, or of the TALs the feed published, see
+    /// `record_feed_tals`), then added back, flagged as interpolated, to the
+    /// records that were present the day before. The day is listed in
+    /// `held_out_days`.
+    pub fn hold_out_day(&mut self, tal: &str, date: NaiveDate) {
+        let date_ts = date_to_ts(date);
+        let feed_tals = self.feed_tals.get(tal).cloned().unwrap_or_default();
+        let mut emptied = Vec::new();
+        for (prefix, records) in self.trie.iter_mut() {
+            for r in records
+                .iter_mut()
+                .filter(|r| r.tal == tal || feed_tals.contains(&r.tal))
+            {
+                r.remove_day(date_ts);
+                if r.contains_day(date_ts - ONE_DAY_SECONDS) {
+                    r.push_interpolated(date_ts, date_ts);
+                }
+            }
+            records.retain(|r| !r.dates.is_empty() || !r.ranges.is_empty());
+            if records.is_empty() {
+                emptied.push(prefix);
+            }
+        }
+        for prefix in emptied {
+            self.trie.remove(&prefix);
+        }
+        self.held_out
+            .entry(tal.to_string())
+            .or_default()
+            .insert(date_ts);
+        info!("held out {} {}", tal, date);
+    }
+
+    /// Days held out as source anomalies, by feed and then date.
+    pub fn held_out_days(&self) -> Vec<(String, NaiveDate)> {
+        self.held_out
+            .iter()
+            .flat_map(|(tal, days)| days.iter().map(move |ts| (tal.clone(), ts_to_date(*ts))))
+            .collect()
+    }
+
+    /// Snapshots queued for retry, and those abandoned.
+    pub fn failed_files(&self) -> &[FailedFile] {
+        &self.failed_files
+    }
+
+    /// Queue `file` for retry, or count another failed attempt if it is
+    /// queued already; the file is abandoned after [`RETRY_MAX_ATTEMPTS`].
+    pub fn record_failure(&mut self, file: &RoaFile, error: &str) {
+        let date = date_to_ts(file.file_date);
+        let now = chrono::Utc::now().timestamp();
+        match self
+            .failed_files
+            .iter_mut()
+            .find(|f| f.tal == file.tal && f.date == date)
+        {
+            Some(failed) => {
+                failed.attempts += 1;
+                failed.last_attempt = now;
+                failed.error = error.to_string();
+                if failed.abandoned() {
+                    warn!(
+                        "giving up on {} after {} attempts: {}",
+                        failed.url, failed.attempts, failed.error
+                    );
+                }
+            }
+            None => self.failed_files.push(FailedFile {
+                url: file.url.clone(),
+                tal: file.tal.clone(),
+                date,
+                attempts: 1,
+                last_attempt: now,
+                error: error.to_string(),
+            }),
+        }
+    }
+
+    /// Incremental update: for every feed of `source`, list the snapshots
+    /// newer than that feed's high-water mark and apply them in date order.
+    /// Feeds without a mark (archives built before marks were recorded, or
+    /// new feeds) first get one at the global `latest_date`, for every feed
+    /// of [`RoaSource::all_tals`], so a run limited to some feeds does not
+    /// make the others skip their missing days. Malformed rows are handled
+    /// according to `mode`.
+    ///
+    /// Snapshots that fail are queued in the archive and retried by later
+    /// updates once their backoff has passed, until they are abandoned; a
+    /// recovered day is merged in out of order.
+    pub fn update(
+        &mut self,
+        source: &dyn RoaSource,
+        until: Option<NaiveDate>,
+        mode: ParseMode,
+    ) -> Result<IngestSummary> {
+        info!(
+            "updating trie... source: {}, until: {:?}",
+            source.name(),
+            &until
+        );
+        let mut summary = IngestSummary::default();
+        let latest_date = self.latest_date;
+        for tal in source.all_tals() {
+            self.tal_latest.entry(tal).or_insert(latest_date);
+        }
+        let tals = source.tals();
+        // days ingested by this run, checked for source anomalies at the end
+        let mut new_days = self.retry_failed(source, &tals, mode, &mut summary);
+
+        let mut all_files = Vec::new();
+        for tal in &tals {
+            let latest = self
+                .tal_latest
+                .get(tal)
+                .copied()
+                .unwrap_or(self.latest_date);
+            let from = ts_to_date(latest) + chrono::Duration::days(1);
+            info!("{}: fetching snapshots from {}", tal, from);
+            all_files.extend(source.list_snapshots(tal, Some(from), until));
+        }
+        all_files.sort_by_key(|f| f.file_date);
 
         if all_files.is_empty() {
             info!("trie is up to date. No new files found.");
-            return Ok(());
         }
 
-        all_files.sort_by_key(|a| a.file_date);
-
-        let mut failed = 0usize;
         for file in &all_files {
             info!("processing {}", file.url.as_str());
-            match parse_roas_csv(file.url.as_str()) {
-                Ok(roas) => self.process_entries(&roas, false),
-                Err(e) => {
-                    failed += 1;
-                    warn!("failed to process {}: {}", file.url, e);
+            let result = source.read_snapshot_with(file, mode);
+            summary.record(file, &result);
+            match result {
+                Ok(snapshot) => {
+                    self.process_entries(&snapshot.entries, false);
+                    self.record_feed_tals(&file.tal, &snapshot.entries);
+                    self.record_observation(
+                        &file.tal,
+                        file.file_date,
+                        snapshot.entries.len() as u64,
+                    );
+                    new_days.insert((file.tal.clone(), file.file_date));
                 }
+                Err(e) => self.record_failure(file, &e.to_string()),
             }
+            // a failed day is in the retry queue, so the feed moves past it
+            self.mark_ingested(&file.tal, file.file_date);
         }
-        if failed > 0 {
+
+        let anomalies =
+            self.check_anomalies(|tal, date| new_days.contains(&(tal.to_string(), date)));
+        summary.anomalies.extend(anomalies);
+
+        summary.count_retries(&self.failed_files);
+        for failed in &self.failed_files {
+            if failed.abandoned() {
+                warn!(
+                    "abandoned: {} {} ({} attempt(s)): {}",
+                    failed.tal,
+                    ts_to_date(failed.date),
+                    failed.attempts,
+                    failed.error
+                );
+                continue;
+            }
             warn!(
-                "update finished with {} failed file(s) out of {}",
-                failed,
-                all_files.len()
+                "pending retry: {} {} ({} attempt(s), next after {}): {}",
+                failed.tal,
+                ts_to_date(failed.date),
+                failed.attempts,
+                chrono::DateTime::from_timestamp(failed.next_attempt(), 0).unwrap(),
+                failed.error
             );
         }
-
-        info!("updating trie... done");
-        Ok(())
+        info!("updating trie... done: {}", summary);
+        Ok(summary)
     }
 
-    /// Fill known historical data gaps by interpolating adjacent date ranges.
-    pub fn fill_gaps(&mut self) {
-        info!("filling known gaps...");
-        for (start, end) in KNOWN_GAPS_STR.iter() {
-            let start_ts = date_to_ts(NaiveDate::parse_from_str(start, "%Y-%m-%d").unwrap());
-            let end_ts = date_to_ts(NaiveDate::parse_from_str(end, "%Y-%m-%d").unwrap());
-
-            let mut dates = Vec::new();
-            let mut d = start_ts;
-            while d <= end_ts {
-                dates.push(d);
-                d += ONE_DAY_SECONDS;
+    /// Retry the queued failures of `tals` whose backoff has passed, unless
+    /// they are abandoned. Entries
+    /// of a recovered snapshot are merged in bootstrap mode, since its day
+    /// usually lies before the records' latest ranges; `dump` compresses them.
+    /// Returns the recovered days.
+    fn retry_failed(
+        &mut self,
+        source: &dyn RoaSource,
+        tals: &[String],
+        mode: ParseMode,
+        summary: &mut IngestSummary,
+    ) -> HashSet<(String, NaiveDate)> {
+        let mut recovered = HashSet::new();
+        let now = chrono::Utc::now().timestamp();
+        let due: Vec<RoaFile> = self
+            .failed_files
+            .iter()
+            .filter(|f| tals.contains(&f.tal) && !f.abandoned() && f.next_attempt() <= now)
+            .map(|f| f.roa_file())
+            .collect();
+
+        for file in &due {
+            info!("retrying {}", file.url.as_str());
+            summary.retried += 1;
+            match source.read_snapshot_with(file, mode) {
+                Ok(snapshot) => {
+                    self.process_entries(&snapshot.entries, true);
+                    self.record_feed_tals(&file.tal, &snapshot.entries);
+                    self.record_observation(
+                        &file.tal,
+                        file.file_date,
+                        snapshot.entries.len() as u64,
+                    );
+                    let date = date_to_ts(file.file_date);
+                    self.failed_files
+                        .retain(|f| !(f.tal == file.tal && f.date == date));
+                    summary.recovered += 1;
+                    recovered.insert((file.tal.clone(), file.file_date));
+                    summary.files += 1;
+                    summary.skipped_rows += snapshot.skipped.len();
+                }
+                Err(e) => {
+                    warn!("retry of {} failed: {}", file.url, e);
+                    self.record_failure(file, &e.to_string());
+                }
             }
+        }
+        recovered
+    }
+
+    /// Fill the gaps of `policy` by interpolating adjacent date ranges, and
+    /// record the policy's hash in the archive. Bridged days are flagged as
+    /// interpolated.
+    pub fn fill_gaps(&mut self, policy: &GapPolicy) {
+        info!("filling known gaps ({} intervals)...", policy.gaps.len());
+        for gap in &policy.gaps {
+            let start_ts = date_to_ts(gap.start);
+            let end_ts = date_to_ts(gap.end);
 
             for (_prefix, records) in self.trie.iter_mut() {
-                for r in records.iter_mut() {
+                for r in records.iter_mut().filter(|r| gap.applies_to(&r.tal)) {
                     let mut should_compress = false;
                     for i in 0..r.ranges.len().saturating_sub(1) {
                         if start_ts - ONE_DAY_SECONDS == r.ranges[i].1
                             && end_ts + ONE_DAY_SECONDS == r.ranges[i + 1].0
                         {
-                            r.dates.extend(&dates);
+                            r.push_interpolated(start_ts, end_ts);
                             should_compress = true;
                         }
                     }
@@ -503,9 +1650,15 @@ impl RoasTrieMut {
                 }
             }
         }
+        self.gap_policy_hash = Some(policy.hash());
         info!("filling known gaps... done");
     }
 
+    /// [`GapPolicy::hash`] of the policy last applied by `fill_gaps`.
+    pub fn gap_policy_hash(&self) -> Option<&str> {
+        self.gap_policy_hash.as_deref()
+    }
+
     pub fn len(&self) -> usize {
         self.trie.len()
     }
@@ -575,9 +1728,9 @@ impl RoasTrie {
     }
 
     fn validate_bytes(&self) -> Result<()> {
+        let bytes = self.bytes.as_slice();
         let data: &ArchivedRoasTrieData =
-            rkyv::access::<_, rkyv::rancor::Error>(self.bytes.as_slice())
-                .map_err(|e| anyhow!("trie archive validation failed: {}", e))?;
+            rkyv::access::<_, rkyv::rancor::Error>(bytes).map_err(|e| archive_error(bytes, e))?;
         if data.format_version.to_native() != FORMAT_VERSION {
             return Err(anyhow!(
                 "unsupported trie format version {} (expected {})",
@@ -595,6 +1748,11 @@ impl RoasTrie {
         unsafe { rkyv::access_unchecked::<ArchivedRoasTrieData>(self.bytes.as_slice()) }
     }
 
+    /// Identifier of the dump that wrote this archive.
+    pub fn build_id(&self) -> u64 {
+        self.data().build_id.to_native()
+    }
+
     pub fn latest_date_ts(&self) -> i64 {
         self.data().latest_date.to_native()
     }
@@ -603,6 +1761,63 @@ impl RoasTrie {
         ts_to_date(self.latest_date_ts())
     }
 
+    /// Snapshots queued for retry by the next update, and those abandoned.
+    pub fn failed_files(&self) -> Vec<FailedFile> {
+        self.data()
+            .failed_files
+            .iter()
+            .map(|f| FailedFile {
+                url: f.url.to_string(),
+                tal: f.tal.to_string(),
+                date: f.date.to_native(),
+                attempts: f.attempts.to_native(),
+                last_attempt: f.last_attempt.to_native(),
+                error: f.error.to_string(),
+            })
+            .collect()
+    }
+
+    /// Latest ingested snapshot date of each feed (TAL).
+    pub fn tal_latest_dates(&self) -> Vec<(String, NaiveDate)> {
+        self.data()
+            .tal_latest
+            .iter()
+            .map(|e| (e.0.to_string(), ts_to_date(e.1.to_native())))
+            .collect()
+    }
+
+    /// Days held out as source anomalies, by feed and then date.
+    pub fn held_out_days(&self) -> Vec<(String, NaiveDate)> {
+        self.data()
+            .held_out
+            .iter()
+            .flat_map(|e| {
+                e.1.iter()
+                    .map(move |ts| (e.0.to_string(), ts_to_date(ts.to_native())))
+            })
+            .collect()
+    }
+
+    /// [`GapPolicy::hash`] of the known-gap policy last applied to the archive.
+    pub fn gap_policy_hash(&self) -> Option<String> {
+        self.data().gap_policy_hash.as_ref().map(|h| h.to_string())
+    }
+
+    /// The observation calendar, by feed and then date.
+    pub fn observations(&self) -> Vec<Observation> {
+        self.data()
+            .observations
+            .iter()
+            .flat_map(|e| {
+                e.1.iter().map(move |day| Observation {
+                    tal: e.0.to_string(),
+                    date: ts_to_date(day.0.to_native()),
+                    rows: day.1.to_native(),
+                })
+            })
+            .collect()
+    }
+
     pub fn counts(&self) -> (u64, u64) {
         let data = self.data();
         (data.ipv4_count.to_native(), data.ipv6_count.to_native())
@@ -617,47 +1832,105 @@ impl RoasTrie {
         self.len() == 0
     }
 
-    /// RPKI validation for a prefix/origin at a given date.
-    pub fn validate(&self, prefix: &IpNet, origin: u32, date_ts: i64) -> RpkiValidation {
-        let mut result = RpkiValidation::Unknown;
+    /// RPKI validation for a prefix/origin at a given date. Only ROAs covering
+    /// the prefix and active on the date are considered (RFC 6811), found by
+    /// walking its ancestors, so the cost is bounded by the prefix length. In
+    /// `strict` mode, ROAs whose state on the date is interpolated rather
+    /// than observed are ignored. The report lists every such VRP and why it
+    /// does or does not match.
+    pub fn validate(
+        &self,
+        prefix: &IpNet,
+        origin: u32,
+        date_ts: i64,
+        strict: bool,
+    ) -> ValidationReport {
         let prefix_len = prefix.prefix_len();
-        for (_p, records) in self.match_records(prefix) {
-            for r in records.iter() {
-                let r_origin = r.origin.to_native();
-                let r_max_len = r.max_len;
-                if r_origin == origin && r_max_len >= prefix_len && record_contains_date(r, date_ts)
-                {
-                    return RpkiValidation::Valid;
-                }
+        let mut vrps = Vec::new();
+        for (p, records) in self.data().trie.cover(prefix) {
+            for r in records.iter().filter(|r| {
+                record_contains_date(r, date_ts) && !(strict && record_interpolated_on(r, date_ts))
+            }) {
+                let vrp_origin = r.origin.to_native();
+                vrps.push(CoveringVrp {
+                    prefix: p,
+                    max_len: r.max_len,
+                    origin: vrp_origin,
+                    tal: r.tal.to_string(),
+                    status: VrpMatch::classify(vrp_origin, r.max_len, origin, prefix_len),
+                });
             }
-            result = RpkiValidation::Invalid;
         }
-        result
+        ValidationReport::from_vrps(vrps)
     }
 
-    /// All ROA records matching a prefix, including supernets and subnets.
-    pub fn lookup_prefix(&self, prefix: &IpNet) -> Vec<RoasLookupEntry> {
-        let mut entries = Vec::new();
-        for (p, records) in self.match_records(prefix) {
-            for r in records.iter() {
-                entries.push(archived_lookup_entry(p, r));
+    /// `(tal, prefix, max_len, origin)` of every record active on `date`.
+    pub fn records_on(&self, date: NaiveDate) -> Vec<(String, IpNet, u8, u32)> {
+        let date_ts = date_to_ts(date);
+        let mut out = Vec::new();
+        for (p, records) in self.data().trie.iter() {
+            for r in records.iter().filter(|r| record_contains_date(r, date_ts)) {
+                out.push((r.tal.to_string(), p, r.max_len, r.origin.to_native()));
             }
         }
-        entries
+        out
+    }
+
+    /// All ROA records of a prefix, its supernets and its subnets.
+    pub fn lookup_prefix(&self, prefix: &IpNet) -> Vec<RoasLookupEntry> {
+        self.lookup_prefix_iter(prefix)
+            .map(|r| r.to_entry())
+            .collect()
     }
 
-    /// Search ROAs with optional filters. `exact=true` restricts to the exact
-    /// prefix; `exact=false` includes supernets and subnets (matching the old
-    /// `matches()` semantics: the subtree of the shortest covering prefix).
-    pub fn search(
+    /// Lazy form of [`RoasTrie::lookup_prefix`]: records are borrowed from
+    /// the archive and converted only when asked.
+    pub fn lookup_prefix_iter<'a>(
+        &'a self,
+        prefix: &IpNet,
+    ) -> impl Iterator<Item = RoaRecordRef<'a>> + 'a {
+        self.match_records(prefix, None).flat_map(|(p, records)| {
+            records.iter().map(move |record| RoaRecordRef {
+                prefix: p,
+                record,
+                observed_only: false,
+            })
+        })
+    }
+
+    /// Search ROAs matching `query`.
+    pub fn search(&self, query: &SearchQuery) -> Vec<RoasLookupEntry> {
+        self.search_iter(query).map(|r| r.to_entry()).collect()
+    }
+
+    /// Lazy form of [`RoasTrie::search`]: the archive is walked as the
+    /// iterator is consumed, and matching records are borrowed rather than
+    /// converted, so counting or paginating results allocates only for the
+    /// records that are kept.
+    pub fn search_iter(&self, query: &SearchQuery) -> impl Iterator<Item = RoaRecordRef<'_>> + '_ {
+        self.search_iter_after(query, None)
+    }
+
+    /// [`RoasTrie::search_iter`] resumed after the result at `after` (in trie
+    /// order, e.g. the last result of a page): the walk starts at its prefix
+    /// instead of skipping the results before it.
+    pub fn search_iter_after(
         &self,
-        prefix: Option<IpNet>,
-        origin: Option<u32>,
-        max_len: Option<u8>,
-        date: Option<NaiveDate>,
-        current: Option<bool>,
-        exact: bool,
-    ) -> Vec<RoasLookupEntry> {
+        query: &SearchQuery,
+        after: Option<SearchPosition>,
+    ) -> impl Iterator<Item = RoaRecordRef<'_>> + '_ {
+        let SearchQuery {
+            prefix,
+            origin,
+            max_len,
+            date,
+            current,
+            window,
+            match_mode,
+            ref tal,
+            observed_only,
+        } = *query;
+        let tal = tal.as_deref().map(normalize_tal);
         let mut only_expired = false;
         let date_ts = match current {
             Some(true) => Some(self.latest_date_ts()),
@@ -669,76 +1942,233 @@ impl RoasTrie {
         };
         let latest = self.latest_date_ts();
 
-        let mut entries = Vec::new();
-        for (p, records) in self.select_records(prefix, exact) {
-            // deterministic order within a prefix (v1 was nondeterministic HashMap order)
-            let mut sorted: Vec<&ArchivedRoaRecord> = records.iter().collect();
-            sorted.sort_by_key(|r| (r.origin.to_native(), r.max_len));
-            for r in sorted {
-                if let Some(origin) = origin {
-                    if r.origin.to_native() != origin {
-                        continue;
-                    }
-                }
-                if let Some(max_len) = max_len {
-                    if r.max_len != max_len {
-                        continue;
-                    }
-                }
-                if let Some(date_ts) = date_ts {
-                    if !record_contains_date(r, date_ts) {
-                        continue;
-                    }
-                }
-                if only_expired && r.dates.iter().any(|range| range.1.to_native() >= latest) {
-                    continue;
-                }
-                entries.push(archived_lookup_entry(p, r));
-            }
-        }
-        entries
+        let start = after.as_ref().map(|a| a.prefix);
+        self.select_records(prefix, match_mode, origin, start)
+            .flat_map(|(p, records)| {
+                // deterministic order within a prefix (v1 was nondeterministic HashMap order)
+                let mut sorted: Vec<&ArchivedRoaRecord> = records.iter().collect();
+                sorted.sort_by_key(|r| (r.origin.to_native(), r.max_len, r.tal.as_str()));
+                sorted.into_iter().map(move |r| (p, r))
+            })
+            // only the records of the start prefix up to `after` are skipped
+            .skip_while(move |(p, r)| {
+                after
+                    .as_ref()
+                    .is_some_and(|a| a.reaches(*p, r.origin.to_native(), r.max_len, r.tal.as_str()))
+            })
+            .filter(move |(_, r)| {
+                origin.map_or(true, |origin| r.origin.to_native() == origin)
+                    && tal.as_ref().map_or(true, |tal| r.tal.as_str() == tal)
+                    && max_len.map_or(true, |max_len| r.max_len == max_len)
+                    && date_ts.map_or(true, |date_ts| {
+                        record_contains_date(r, date_ts)
+                            && !(observed_only && record_interpolated_on(r, date_ts))
+                    })
+            })
+            .map(move |(prefix, record)| RoaRecordRef {
+                prefix,
+                record,
+                observed_only,
+            })
+            .filter(move |r| {
+                (window == DateRangeFilter::default()
+                    || window.matches(&r.dates_ranges(), ts_to_date(latest)))
+                    && !(only_expired && r.last_seen_ts().is_some_and(|end| end >= latest))
+            })
     }
 
-    /// Select (prefix, records) pairs according to the prefix filter mode.
+    /// Select (prefix, records) pairs according to the prefix match mode, in
+    /// trie order and from `start` on. Without a prefix, an `origin` filter
+    /// is answered from the ASN index rather than by walking the whole trie.
     fn select_records(
         &self,
         prefix: Option<IpNet>,
-        exact: bool,
-    ) -> Vec<(IpNet, &ArchivedVec<ArchivedRoaRecord>)> {
-        match prefix {
-            Some(p) if exact => match self.data().trie.get(&p) {
-                Some(recs) => vec![(p, recs)],
-                None => vec![],
+        match_mode: MatchMode,
+        origin: Option<u32>,
+        start: Option<IpNet>,
+    ) -> ArchivedEntries<'_> {
+        let from_start = move |(p, _): &(IpNet, _)| start.is_some_and(|s| *p < s);
+        match (prefix, origin) {
+            (Some(p), _) => match match_mode {
+                MatchMode::Exact => Box::new(
+                    self.data()
+                        .trie
+                        .get(&p)
+                        .map(|recs| (p, recs))
+                        .into_iter()
+                        .skip_while(from_start),
+                ),
+                MatchMode::Covering => Box::new(self.data().trie.cover(&p).skip_while(from_start)),
+                MatchMode::CoveredBy => self.view_from(&p, start),
+                MatchMode::Overlapping => self.match_records(&p, start),
             },
-            Some(p) => self.match_records(&p),
-            None => self.data().trie.iter().collect(),
+            (None, Some(origin)) => Box::new(self.origin_records(origin).skip_while(from_start)),
+            (None, None) => {
+                let all_v4 = Ipv4Net::default();
+                let all_v6 = Ipv6Net::default();
+                Box::new(
+                    self.view_from(&IpNet::V4(all_v4), start)
+                        .chain(self.view_from(&IpNet::V6(all_v6), start)),
+                )
+            }
         }
     }
 
-    /// Entries matching `prefix` with old `IpnetTrie::matches()` semantics:
-    /// the shortest covering prefix and its entire subtree.
-    fn match_records<'a>(
-        &'a self,
-        prefix: &IpNet,
-    ) -> Vec<(IpNet, &'a ArchivedVec<ArchivedRoaRecord>)> {
+    /// Entries holding at least one record of `origin`, via the ASN index.
+    fn origin_records(&self, origin: u32) -> ArchivedEntries<'_> {
         let data = self.data();
-        let spm = match data.trie.get_spm(prefix) {
-            Some((spm, _)) => spm,
-            None => return vec![],
+        let Ok(idx) = data
+            .asn_index
+            .binary_search_by_key(&origin, |e| e.origin.to_native())
+        else {
+            return Box::new(std::iter::empty());
         };
-        match spm {
-            IpNet::V4(spm4) => match (&data.trie.t1).view_at(&spm4) {
-                Some(view) => view.iter().map(|(p, recs)| (IpNet::V4(p), recs)).collect(),
-                None => vec![],
+        let entry = &data.asn_index[idx];
+        let v4 = entry.v4.iter().filter_map(|p| {
+            Ipv4Net::new(Ipv4Addr::from(p.0.to_native()), p.1)
+                .ok()
+                .map(IpNet::V4)
+        });
+        let v6 = entry.v6.iter().filter_map(|p| {
+            Ipv6Net::new(Ipv6Addr::from(p.0.to_native()), p.1)
+                .ok()
+                .map(IpNet::V6)
+        });
+        Box::new(
+            v4.chain(v6)
+                .filter_map(|p| data.trie.get(&p).map(|recs| (p, recs))),
+        )
+    }
+
+    /// Entries of `prefix`, its supernets and its subnets: the ancestors of
+    /// `prefix`, shortest first, followed by the subtree rooted at it.
+    fn match_records(&self, prefix: &IpNet, start: Option<IpNet>) -> ArchivedEntries<'_> {
+        let prefix = *prefix;
+        Box::new(
+            self.data()
+                .trie
+                .cover(&prefix)
+                .filter(move |(p, _)| *p != prefix && start.map_or(true, |s| *p >= s))
+                .chain(self.view_from(&prefix, start)),
+        )
+    }
+
+    /// Entries of `root` and its subnets (the subtree rooted at `root`), in
+    /// trie order, from `start` on. The walk resumes at `start` instead of
+    /// skipping the entries before it.
+    fn view_from(&self, root: &IpNet, start: Option<IpNet>) -> ArchivedEntries<'_> {
+        let data = self.data();
+        // a start outside the subtree comes before or after all of it
+        let start = match start {
+            Some(s) if root.contains(&s) => Some(s),
+            Some(s) if s < *root => None,
+            Some(_) => return Box::new(std::iter::empty()),
+            None => None,
+        };
+        match (root, start) {
+            (IpNet::V4(r4), start) => match (&data.trie.t1).view_at(r4) {
+                Some(view) => {
+                    let iter = match start {
+                        Some(IpNet::V4(s4)) => view.iter_from(&s4, true),
+                        _ => view.iter(),
+                    };
+                    Box::new(iter.map(|(p, recs)| (IpNet::V4(p), recs)))
+                }
+                None => Box::new(std::iter::empty()),
             },
-            IpNet::V6(spm6) => match (&data.trie.t2).view_at(&spm6) {
-                Some(view) => view.iter().map(|(p, recs)| (IpNet::V6(p), recs)).collect(),
-                None => vec![],
+            (IpNet::V6(r6), start) => match (&data.trie.t2).view_at(r6) {
+                Some(view) => {
+                    let iter = match start {
+                        Some(IpNet::V6(s6)) => view.iter_from(&s6, true),
+                        _ => view.iter(),
+                    };
+                    Box::new(iter.map(|(p, recs)| (IpNet::V6(p), recs)))
+                }
+                None => Box::new(std::iter::empty()),
             },
         }
     }
 }
 
+/// Archived (prefix, records) pairs, produced lazily.
+type ArchivedEntries<'a> =
+    Box<dyn Iterator<Item = (IpNet, &'a ArchivedVec<ArchivedRoaRecord>)> + 'a>;
+
+/// A ROA record borrowed from a [`RoasTrie`] archive. The accessors read the
+/// archived data in place; [`RoaRecordRef::to_entry`] converts the record to
+/// an owned [`RoasLookupEntry`].
+#[derive(Clone, Copy)]
+pub struct RoaRecordRef<'a> {
+    prefix: IpNet,
+    record: &'a ArchivedRoaRecord,
+    /// Whether interpolated days are left out of the date ranges
+    observed_only: bool,
+}
+
+impl<'a> RoaRecordRef<'a> {
+    pub fn prefix(&self) -> IpNet {
+        self.prefix
+    }
+
+    pub fn origin(&self) -> u32 {
+        self.record.origin.to_native()
+    }
+
+    pub fn max_len(&self) -> u8 {
+        self.record.max_len
+    }
+
+    /// Trust anchor that published the ROA; empty when unknown.
+    pub fn tal(&self) -> &'a str {
+        self.record.tal.as_str()
+    }
+
+    /// First day the record was active.
+    pub fn first_seen(&self) -> Option<NaiveDate> {
+        self.first_seen_ts().map(ts_to_date)
+    }
+
+    /// Last day the record was active.
+    pub fn last_seen(&self) -> Option<NaiveDate> {
+        self.last_seen_ts().map(ts_to_date)
+    }
+
+    /// The record's date ranges, as in [`RoasLookupEntry::dates_ranges`].
+    pub fn dates_ranges(&self) -> Vec<(NaiveDate, NaiveDate)> {
+        self.ranges_ts()
+            .into_iter()
+            .map(|(start, end)| (ts_to_date(start), ts_to_date(end)))
+            .collect()
+    }
+
+    /// Convert to an owned entry.
+    pub fn to_entry(&self) -> RoasLookupEntry {
+        archived_lookup_entry(self.prefix, self.record, self.observed_only)
+    }
+
+    fn ranges_ts(&self) -> Vec<(i64, i64)> {
+        let ranges = native_ranges(&self.record.dates);
+        match self.observed_only && !self.record.interpolated.is_empty() {
+            true => subtract_ranges(&ranges, &native_ranges(&self.record.interpolated)),
+            false => ranges,
+        }
+    }
+
+    fn first_seen_ts(&self) -> Option<i64> {
+        match self.observed_only && !self.record.interpolated.is_empty() {
+            true => self.ranges_ts().first().map(|range| range.0),
+            false => self.record.dates.first().map(|range| range.0.to_native()),
+        }
+    }
+
+    fn last_seen_ts(&self) -> Option<i64> {
+        match self.observed_only && !self.record.interpolated.is_empty() {
+            true => self.ranges_ts().last().map(|range| range.1),
+            false => self.record.dates.last().map(|range| range.1.to_native()),
+        }
+    }
+}
+
 fn record_contains_date(r: &ArchivedRoaRecord, date_ts: i64) -> bool {
     r.dates.iter().any(|range| {
         let start = range.0.to_native();
@@ -747,21 +2177,59 @@ fn record_contains_date(r: &ArchivedRoaRecord, date_ts: i64) -> bool {
     })
 }
 
-fn archived_lookup_entry(prefix: IpNet, r: &ArchivedRoaRecord) -> RoasLookupEntry {
+fn native_ranges(ranges: &rkyv::Archived<Vec<(i64, i64)>>) -> Vec<(i64, i64)> {
+    ranges
+        .iter()
+        .map(|range| (range.0.to_native(), range.1.to_native()))
+        .collect()
+}
+
+/// Whether the record's state on `date_ts` is interpolated.
+fn record_interpolated_on(r: &ArchivedRoaRecord, date_ts: i64) -> bool {
+    r.interpolated
+        .iter()
+        .any(|range| date_ts >= range.0.to_native() && date_ts <= range.1.to_native())
+}
+
+/// Convert an archived record; with `observed_only`, its interpolated days
+/// are left out of the date ranges.
+fn archived_lookup_entry(
+    prefix: IpNet,
+    r: &ArchivedRoaRecord,
+    observed_only: bool,
+) -> RoasLookupEntry {
+    let to_dates = |ranges: Vec<(i64, i64)>| {
+        ranges
+            .into_iter()
+            .map(|(start, end)| (ts_to_date(start), ts_to_date(end)))
+            .collect()
+    };
+    let (dates, interpolated) = match (observed_only, r.interpolated.is_empty()) {
+        (_, true) => (native_ranges(&r.dates), Vec::new()),
+        (true, false) => (
+            subtract_ranges(&native_ranges(&r.dates), &native_ranges(&r.interpolated)),
+            Vec::new(),
+        ),
+        (false, false) => (native_ranges(&r.dates), native_ranges(&r.interpolated)),
+    };
     RoasLookupEntry {
         prefix,
         origin: r.origin.to_native(),
         max_len: r.max_len,
-        dates_ranges: r
-            .dates
+        tal: r.tal.to_string(),
+        objects: r
+            .objects
             .iter()
-            .map(|range| {
-                (
-                    ts_to_date(range.0.to_native()),
-                    ts_to_date(range.1.to_native()),
-                )
+            .map(|o| RoaObject {
+                uri: o.uri.to_string(),
+                not_before: o.not_before.as_ref().map(|t| t.to_native()),
+                not_after: o.not_after.as_ref().map(|t| t.to_native()),
             })
             .collect(),
+        object_count: r.object_count.to_native(),
+        expires: r.expires.as_ref().map(|t| t.to_native()),
+        dates_ranges: to_dates(dates),
+        interpolated_ranges: to_dates(interpolated),
     }
 }
 
@@ -769,9 +2237,9 @@ fn archived_lookup_entry(prefix: IpNet, r: &ArchivedRoaRecord) -> RoasLookupEntr
 // JSONL platform-agnostic transport format
 // ---------------------------------------------------------------------------
 
-/// One JSONL line: a single (prefix, max_len, origin) ROA record with compressed
-/// date ranges. This is the on-the-wire format for bootstrap downloads and
-/// remote backups — platform-agnostic and streamable.
+/// One JSONL line: a single (prefix, max_len, origin, tal) ROA record with
+/// compressed date ranges. This is the on-the-wire format for bootstrap
+/// downloads and remote backups — platform-agnostic and streamable.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct JsonlRecord {
     /// Prefix in CIDR notation, e.g. "1.1.1.0/24"
@@ -780,8 +2248,44 @@ pub struct JsonlRecord {
     pub m: u8,
     /// Origin ASN
     pub o: u32,
+    /// Trust anchor; omitted when unknown (transports written before TAL
+    /// provenance was recorded)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub t: String,
     /// Compressed date ranges as (start_ts, end_ts) pairs
     pub r: Vec<(i64, i64)>,
+    /// The interpolated days of `r`, as compressed ranges; omitted when none
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub i: Vec<(i64, i64)>,
+    /// ROA objects as (uri, not_before_ts, not_after_ts); omitted when unknown
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub u: Vec<(String, Option<i64>, Option<i64>)>,
+    /// Distinct ROA objects seen, when more than `u` keeps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    /// Latest validator-reported expiry; omitted when unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<i64>,
+}
+
+/// One JSONL line of the observation calendar: the ingested snapshot days of
+/// a single feed. Written after all records, one line per feed.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JsonlCoverage {
+    /// Trust anchor (feed) name
+    pub c: String,
+    /// Observed days as (date_ts, rows_count) pairs
+    pub d: Vec<(i64, u64)>,
+    /// Days held out as source anomalies, as date_ts; omitted when none
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub h: Vec<i64>,
+}
+
+/// Last JSONL line, present when a known-gap policy was applied: the
+/// policy's [`GapPolicy::hash`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JsonlGapPolicy {
+    pub g: String,
 }
 
 impl RoasTrieMut {
@@ -807,8 +2311,20 @@ impl RoasTrieMut {
             if trimmed.is_empty() {
                 continue;
             }
-            let rec: JsonlRecord = serde_json::from_str(trimmed)
-                .map_err(|e| anyhow!("JSONL parse error at line {}: {}", line_no, e))?;
+            let rec: JsonlRecord = match serde_json::from_str(trimmed) {
+                Ok(rec) => rec,
+                Err(e) => {
+                    if let Ok(coverage) = serde_json::from_str::<JsonlCoverage>(trimmed) {
+                        self.import_coverage(coverage);
+                        continue;
+                    }
+                    if let Ok(policy) = serde_json::from_str::<JsonlGapPolicy>(trimmed) {
+                        self.gap_policy_hash = Some(policy.g);
+                        continue;
+                    }
+                    return Err(anyhow!("JSONL parse error at line {}: {}", line_no, e));
+                }
+            };
             let prefix: IpNet = rec
                 .p
                 .parse()
@@ -828,22 +2344,61 @@ impl RoasTrieMut {
                 }
             }
 
-            let record = RoaRecordMut {
+            // transports carry no header; derive each TAL's high-water mark
+            // from the records it published
+            if let Some(&(_, end)) = rec.r.last() {
+                if !rec.t.is_empty() {
+                    let latest = self.tal_latest.entry(rec.t.clone()).or_insert(end);
+                    *latest = (*latest).max(end);
+                }
+            }
+
+            let object_count = rec.n.unwrap_or(rec.u.len() as u32);
+            let mut record = RoaRecordMut {
                 max_len: rec.m,
                 origin: rec.o,
+                tal: rec.t,
                 dates: HashSet::new(),
                 ranges: rec.r,
+                interpolated: rec.i,
+                objects: Vec::new(),
+                object_count: 0,
+                seen_objects: HashSet::new(),
+                objects_dropped: false,
+                expires: rec.x,
             };
+            for (uri, not_before, not_after) in rec.u {
+                record.push_object(&RoaObject {
+                    uri,
+                    not_before,
+                    not_after,
+                });
+            }
+            record.object_count = record.object_count.max(object_count);
+            record.objects_dropped = record.object_count as usize > record.objects.len();
 
-            // Merge with existing records for the same prefix + (max_len, origin)
+            // Merge with existing records for the same prefix + (max_len, origin, tal)
             match self.trie.get_mut(&prefix) {
                 Some(recs) => {
-                    if let Some(existing) = recs
-                        .iter_mut()
-                        .find(|r| r.max_len == record.max_len && r.origin == record.origin)
-                    {
+                    if let Some(existing) = recs.iter_mut().find(|r| {
+                        r.max_len == record.max_len
+                            && r.origin == record.origin
+                            && r.tal == record.tal
+                    }) {
                         existing.ranges.extend(record.ranges.iter().copied());
                         existing.full_compress();
+                        for &(start, end) in &record.interpolated {
+                            add_range(&mut existing.interpolated, start, end);
+                        }
+                        for object in &record.objects {
+                            existing.push_object(object);
+                        }
+                        // objects the transport no longer listed
+                        existing.object_count += record
+                            .object_count
+                            .saturating_sub(record.objects.len() as u32);
+                        existing.objects_dropped |= record.objects_dropped;
+                        existing.push_expires(record.expires);
                     } else {
                         recs.push(record);
                     }
@@ -863,6 +2418,23 @@ impl RoasTrieMut {
         Ok(())
     }
 
+    fn import_coverage(&mut self, coverage: JsonlCoverage) {
+        if let Some(&(end, _)) = coverage.d.iter().max() {
+            let latest = self.tal_latest.entry(coverage.c.clone()).or_insert(end);
+            *latest = (*latest).max(end);
+        }
+        if !coverage.h.is_empty() {
+            self.held_out
+                .entry(coverage.c.clone())
+                .or_default()
+                .extend(coverage.h);
+        }
+        self.observations
+            .entry(coverage.c)
+            .or_default()
+            .extend(coverage.d);
+    }
+
     /// Create a fresh builder from a JSONL file.
     pub fn from_jsonl(path: &str) -> Result<Self> {
         let mut builder = Self::new();
@@ -874,7 +2446,8 @@ impl RoasTrieMut {
 impl RoasTrie {
     /// Stream-export the archive to a `.jsonl[.gz]` file. Reads record data
     /// directly from the mmap'd archive and keeps only one serialized record
-    /// in memory at a time.
+    /// in memory at a time. The observation calendar follows the records as
+    /// one `JsonlCoverage` line per feed, then the applied gap policy's hash.
     pub fn export_jsonl(&self, path: &str) -> Result<()> {
         info!("exporting JSONL to {} ...", path);
         let mut writer: Box<dyn std::io::Write> =
@@ -884,16 +2457,26 @@ impl RoasTrie {
 
         for (prefix, records) in data.trie.iter() {
             for r in records.iter() {
-                let ranges: Vec<(i64, i64)> = r
-                    .dates
-                    .iter()
-                    .map(|range| (range.0.to_native(), range.1.to_native()))
-                    .collect();
                 let rec = JsonlRecord {
                     p: prefix.to_string(),
                     m: r.max_len,
                     o: r.origin.to_native(),
-                    r: ranges,
+                    t: r.tal.to_string(),
+                    r: native_ranges(&r.dates),
+                    i: native_ranges(&r.interpolated),
+                    u: r.objects
+                        .iter()
+                        .map(|o| {
+                            (
+                                o.uri.to_string(),
+                                o.not_before.as_ref().map(|t| t.to_native()),
+                                o.not_after.as_ref().map(|t| t.to_native()),
+                            )
+                        })
+                        .collect(),
+                    n: (r.object_count.to_native() as usize > r.objects.len())
+                        .then(|| r.object_count.to_native()),
+                    x: r.expires.as_ref().map(|t| t.to_native()),
                 };
                 serde_json::to_writer(&mut writer, &rec)?;
                 writer.write_all(b"\n")?;
@@ -904,6 +2487,30 @@ impl RoasTrie {
             }
         }
 
+        for e in data.observations.iter() {
+            let held_out = data.held_out.iter().find(|h| h.0 == e.0);
+            let coverage = JsonlCoverage {
+                c: e.0.to_string(),
+                d: e.1
+                    .iter()
+                    .map(|day| (day.0.to_native(), day.1.to_native()))
+                    .collect(),
+                h: held_out
+                    .map(|h| h.1.iter().map(|ts| ts.to_native()).collect())
+                    .unwrap_or_default(),
+            };
+            serde_json::to_writer(&mut writer, &coverage)?;
+            writer.write_all(b"\n")?;
+        }
+
+        if let Some(hash) = data.gap_policy_hash.as_ref() {
+            let policy = JsonlGapPolicy {
+                g: hash.to_string(),
+            };
+            serde_json::to_writer(&mut writer, &policy)?;
+            writer.write_all(b"\n")?;
+        }
+
         writer.flush()?;
         info!("exported {} prefixes to JSONL", count);
         Ok(())
@@ -914,7 +2521,7 @@ impl RoasTrie {
 pub use rkyv::vec::ArchivedVec;
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     fn make_entry(prefix: &str, asn: u32, max_len: i32, date: NaiveDate) -> RoaEntry {
@@ -924,9 +2531,30 @@ mod tests {
             max_len,
             asn,
             date,
+            object: None,
+            expires: None,
         }
     }
 
+    /// A temporary file path unique to this process and `name`.
+    pub(crate) fn temp_path(name: &str, extension: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "wayback-rpki-test-{}-{}.{}",
+            name,
+            std::process::id(),
+            extension
+        ))
+    }
+
+    /// Dump `builder` to a temporary archive and open it.
+    pub(crate) fn dump_and_open(builder: &mut RoasTrieMut, name: &str) -> RoasTrie {
+        let path = temp_path(name, "rkyv");
+        builder.dump(path.to_str().unwrap()).unwrap();
+        let trie = RoasTrie::open(path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(path);
+        trie
+    }
+
     fn build_test_trie(name: &str) -> RoasTrie {
         let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
         let entries = vec![
@@ -936,16 +2564,7 @@ mod tests {
         ];
         let mut trie = RoasTrieMut::new();
         trie.process_entries(&entries, true);
-        // serialize in-memory and re-open
-        let tmp = std::env::temp_dir().join(format!(
-            "wayback-rpki-test-{}-{}.rkyv",
-            name,
-            std::process::id()
-        ));
-        trie.dump(tmp.to_str().unwrap()).unwrap();
-        let t = RoasTrie::open(tmp.to_str().unwrap()).unwrap();
-        let _ = std::fs::remove_file(&tmp);
-        t
+        dump_and_open(&mut trie, name)
     }
 
     #[test]
@@ -953,7 +2572,10 @@ mod tests {
         let trie = build_test_trie(concat!("t", line!()));
         let prefix: IpNet = "1.1.1.0/24".parse().unwrap();
 
-        let results = trie.search(Some(prefix), None, None, None, None, true);
+        let results = trie.search(&SearchQuery {
+            prefix: Some(prefix),
+            ..Default::default()
+        });
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].prefix.to_string(), "1.1.1.0/24");
         assert_eq!(results[0].origin, 13335);
@@ -964,19 +2586,283 @@ mod tests {
         let trie = build_test_trie(concat!("t", line!()));
         let prefix: IpNet = "1.1.1.0/24".parse().unwrap();
 
-        let results = trie.search(Some(prefix), None, None, None, None, false);
+        let results = trie.search(&SearchQuery {
+            prefix: Some(prefix),
+            match_mode: MatchMode::Overlapping,
+            ..Default::default()
+        });
         let prefixes: Vec<String> = results.iter().map(|r| r.prefix.to_string()).collect();
         assert!(prefixes.contains(&"1.0.0.0/8".to_string()));
         assert!(prefixes.contains(&"1.1.1.0/24".to_string()));
         assert!(prefixes.contains(&"1.1.1.0/25".to_string()));
     }
 
+    #[test]
+    fn test_search_match_modes() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let entries = vec![
+            make_entry("10.0.0.0/8", 1, 8, date),
+            make_entry("10.1.0.0/16", 2, 16, date),
+            make_entry("10.1.1.0/24", 3, 24, date),
+            make_entry("10.2.0.0/16", 4, 16, date),
+        ];
+        let mut builder = RoasTrieMut::new();
+        builder.process_entries(&entries, true);
+        let trie = dump_and_open(&mut builder, "match-modes");
+
+        let prefix: IpNet = "10.1.0.0/16".parse().unwrap();
+        let matched = |mode| -> Vec<String> {
+            trie.search(&SearchQuery {
+                prefix: Some(prefix),
+                match_mode: mode,
+                ..Default::default()
+            })
+            .iter()
+            .map(|r| r.prefix.to_string())
+            .collect()
+        };
+        assert_eq!(matched(MatchMode::Exact), vec!["10.1.0.0/16"]);
+        assert_eq!(
+            matched(MatchMode::Covering),
+            vec!["10.0.0.0/8", "10.1.0.0/16"]
+        );
+        assert_eq!(
+            matched(MatchMode::CoveredBy),
+            vec!["10.1.0.0/16", "10.1.1.0/24"]
+        );
+        assert_eq!(
+            matched(MatchMode::Overlapping),
+            vec!["10.0.0.0/8", "10.1.0.0/16", "10.1.1.0/24"]
+        );
+
+        assert_eq!(MatchMode::parse("covered_by"), Some(MatchMode::CoveredBy));
+        assert!("subnets".parse::<MatchMode>().is_err());
+        assert_eq!(MatchMode::from_exact(false), MatchMode::Overlapping);
+    }
+
+    #[test]
+    fn test_search_date_windows() {
+        let day = |d| NaiveDate::from_ymd_opt(2021, 3, d).unwrap();
+        let mut builder = RoasTrieMut::new();
+        let spans: [(u32, &[(u32, u32)]); 4] = [
+            (1, &[(1, 10)]),
+            (2, &[(5, 6)]),
+            (3, &[(1, 3), (20, 25)]),
+            (4, &[(12, 15)]),
+        ];
+        for (asn, ranges) in spans {
+            for (start, end) in ranges {
+                for d in *start..=*end {
+                    builder.process_entries(&vec![make_entry("10.0.0.0/8", asn, 8, day(d))], true);
+                }
+            }
+        }
+        let trie = dump_and_open(&mut builder, "date-windows");
+
+        let origins = |window: DateRangeFilter| -> Vec<u32> {
+            trie.search(&SearchQuery {
+                window,
+                ..Default::default()
+            })
+            .iter()
+            .map(|r| r.origin)
+            .collect()
+        };
+        let window = |from, until, mode| DateRangeFilter {
+            from,
+            until,
+            mode,
+            ..Default::default()
+        };
+        assert_eq!(
+            origins(window(Some(day(4)), Some(day(7)), RangeMode::Overlap)),
+            vec![1, 2]
+        );
+        assert_eq!(
+            origins(window(Some(day(4)), Some(day(7)), RangeMode::Within)),
+            vec![1]
+        );
+        // an open `until` runs to the latest date
+        assert_eq!(
+            origins(window(Some(day(21)), None, RangeMode::Within)),
+            vec![3]
+        );
+        assert_eq!(
+            origins(window(None, Some(day(2)), RangeMode::Overlap)),
+            vec![1, 3]
+        );
+        // a within window needs a start; without one it is rejected, and matches nothing
+        assert!(window(None, Some(day(2)), RangeMode::Within)
+            .check()
+            .is_err());
+        assert!(origins(window(None, Some(day(2)), RangeMode::Within)).is_empty());
+
+        let first_seen = DateRangeFilter {
+            first_seen_from: Some(day(4)),
+            first_seen_until: Some(day(12)),
+            ..Default::default()
+        };
+        assert_eq!(origins(first_seen), vec![2, 4]);
+        let last_seen = DateRangeFilter {
+            last_seen_from: Some(day(15)),
+            ..Default::default()
+        };
+        assert_eq!(origins(last_seen), vec![3, 4]);
+        let last_seen = DateRangeFilter {
+            last_seen_until: Some(day(10)),
+            ..Default::default()
+        };
+        assert_eq!(origins(last_seen), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_search_iter_borrows_records() {
+        let trie = build_test_trie(concat!("t", line!()));
+        let all = trie.search(&SearchQuery::default());
+        let refs: Vec<RoaRecordRef> = trie.search_iter(&SearchQuery::default()).collect();
+        assert_eq!(refs.len(), all.len());
+        for (r, entry) in refs.iter().zip(&all) {
+            assert_eq!(r.prefix(), entry.prefix);
+            assert_eq!(r.origin(), entry.origin);
+            assert_eq!(r.max_len(), entry.max_len);
+            assert_eq!(r.tal(), entry.tal);
+            assert_eq!(r.first_seen(), entry.dates_ranges.first().map(|d| d.0));
+            assert_eq!(r.last_seen(), entry.dates_ranges.last().map(|d| d.1));
+            assert_eq!(&r.to_entry(), entry);
+        }
+
+        let prefix: IpNet = "1.1.1.0/24".parse().unwrap();
+        let lazy: Vec<RoasLookupEntry> = trie
+            .lookup_prefix_iter(&prefix)
+            .map(|r| r.to_entry())
+            .collect();
+        assert_eq!(lazy, trie.lookup_prefix(&prefix));
+    }
+
+    #[test]
+    fn test_sort_keys_and_cursors() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let mut builder = RoasTrieMut::new();
+        for (prefix, asn, days) in [
+            ("1.0.0.0/8", 300, 2..=9),
+            ("1.1.0.0/16", 100, 4..=5),
+            ("2.0.0.0/8", 200, 1..=3),
+        ] {
+            for d in days {
+                builder.process_entries(&vec![make_entry(prefix, asn, 24, day(d))], true);
+            }
+        }
+        let trie = dump_and_open(&mut builder, "sort");
+        let mut records: Vec<RoaRecordRef> = trie.search_iter(&SearchQuery::default()).collect();
+        let origins =
+            |records: &[RoaRecordRef]| records.iter().map(|r| r.origin()).collect::<Vec<_>>();
+        assert_eq!(origins(&records), vec![300, 100, 200]);
+        SortKey::Origin.sort(&mut records);
+        assert_eq!(origins(&records), vec![100, 200, 300]);
+        SortKey::FirstSeen.sort(&mut records);
+        assert_eq!(origins(&records), vec![200, 300, 100]);
+        let mut entries: Vec<RoasLookupEntry> = records.iter().map(|r| r.to_entry()).collect();
+        SortKey::LastSeen.sort(&mut entries);
+        assert_eq!(
+            entries.iter().map(|e| e.origin).collect::<Vec<_>>(),
+            vec![200, 100, 300]
+        );
+        assert_eq!("first_seen".parse::<SortKey>().unwrap(), SortKey::FirstSeen);
+
+        let mut cursor = SearchCursor {
+            build_id: trie.build_id(),
+            query: 0xdead_beef,
+            page: 3,
+            last: SortKey::FirstSeen.position(&entries[1]),
+        };
+        assert_eq!(SearchCursor::decode(&cursor.encode()).unwrap(), cursor);
+        cursor.last.prefix = "2001:db8::/32".parse().unwrap();
+        cursor.last.tal = String::new();
+        assert!(cursor.encode().bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(SearchCursor::decode(&cursor.encode()).unwrap(), cursor);
+        assert!(SearchCursor::decode("not-a-cursor").is_err());
+
+        // every dump gets a new build id
+        let rebuilt = dump_and_open(&mut builder, "sort");
+        assert_ne!(rebuilt.build_id(), trie.build_id());
+    }
+
+    #[test]
+    fn test_search_resumes_after_position() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let mut entries = Vec::new();
+        for (prefix, max_len) in [
+            ("10.0.0.0/8", 24),
+            ("10.1.0.0/16", 24),
+            ("10.1.1.0/24", 24),
+            ("10.1.2.0/24", 24),
+            ("10.2.0.0/16", 16),
+            ("11.0.0.0/8", 8),
+            ("2001:db8::/32", 48),
+            ("2001:db8:1::/48", 48),
+        ] {
+            for asn in [65001, 65002] {
+                entries.push(make_entry(prefix, asn, max_len, date));
+            }
+        }
+        let mut builder = RoasTrieMut::new();
+        builder.process_entries(&entries, true);
+        let trie = dump_and_open(&mut builder, "resume");
+
+        let queries = [
+            (None, None, MatchMode::Exact),
+            (None, Some(65002), MatchMode::Exact),
+            (Some("10.1.0.0/16"), None, MatchMode::Exact),
+            (Some("10.1.0.0/16"), None, MatchMode::Covering),
+            (Some("10.1.0.0/16"), None, MatchMode::CoveredBy),
+            (Some("10.1.0.0/16"), None, MatchMode::Overlapping),
+            (Some("2001:db8::/32"), None, MatchMode::CoveredBy),
+        ];
+        for (prefix, origin, mode) in queries {
+            let prefix = prefix.map(|p| p.parse::<IpNet>().unwrap());
+            let search = |after: Option<SearchPosition>| {
+                trie.search_iter_after(
+                    &SearchQuery {
+                        prefix,
+                        origin,
+                        match_mode: mode,
+                        ..Default::default()
+                    },
+                    after,
+                )
+                .map(|r| r.to_entry())
+                .collect::<Vec<_>>()
+            };
+            let all = search(None);
+            assert!(!all.is_empty());
+            // results come in position order, so each page resumes the last
+            let positions: Vec<SearchPosition> =
+                all.iter().map(|e| SortKey::Prefix.position(e)).collect();
+            assert!(positions.windows(2).all(|w| w[0] < w[1]), "{:?}", mode);
+
+            let mut paged = Vec::new();
+            let mut after = None;
+            loop {
+                let page: Vec<_> = search(after).into_iter().take(3).collect();
+                match page.last() {
+                    Some(last) => after = Some(SortKey::Prefix.position(last)),
+                    None => break,
+                }
+                paged.extend(page);
+            }
+            assert_eq!(paged, all, "{:?} {:?}", prefix, mode);
+        }
+    }
+
     #[test]
     fn test_search_exact_no_match_returns_empty() {
         let trie = build_test_trie(concat!("t", line!()));
         let prefix: IpNet = "2.2.2.0/24".parse().unwrap();
 
-        let results = trie.search(Some(prefix), None, None, None, None, true);
+        let results = trie.search(&SearchQuery {
+            prefix: Some(prefix),
+            ..Default::default()
+        });
         assert!(results.is_empty());
     }
 
@@ -984,10 +2870,54 @@ mod tests {
     fn test_search_no_prefix_returns_all() {
         let trie = build_test_trie(concat!("t", line!()));
 
-        let results = trie.search(None, None, None, None, None, true);
+        let results = trie.search(&SearchQuery::default());
         assert_eq!(results.len(), 3);
     }
 
+    #[test]
+    fn test_search_by_asn_uses_index() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let mut entry = make_entry("1.1.1.0/24", 13335, 24, date);
+        entry.tal = "apnic".to_string();
+        let entries = vec![
+            make_entry("1.0.0.0/8", 64512, 8, date),
+            make_entry("1.1.1.0/24", 13335, 24, date),
+            make_entry("1.1.1.0/24", 13335, 32, date),
+            entry,
+            make_entry("1.1.1.0/25", 64513, 25, date),
+            make_entry("2606:4700::/32", 13335, 48, date),
+        ];
+        let mut builder = RoasTrieMut::new();
+        builder.process_entries(&entries, true);
+        let trie = dump_and_open(&mut builder, "asn-index");
+
+        let index = &trie.data().asn_index;
+        let origins: Vec<u32> = index.iter().map(|e| e.origin.to_native()).collect();
+        assert_eq!(origins, vec![13335, 64512, 64513]);
+        assert_eq!((index[0].v4.len(), index[0].v6.len()), (1, 1));
+
+        for asn in [13335, 64512, 64513, 65000] {
+            let scanned: Vec<RoasLookupEntry> = trie
+                .search(&SearchQuery::default())
+                .into_iter()
+                .filter(|e| e.origin == asn)
+                .collect();
+            let indexed = trie.search(&SearchQuery {
+                origin: Some(asn),
+                ..Default::default()
+            });
+            assert_eq!(indexed, scanned);
+        }
+        assert_eq!(
+            trie.search(&SearchQuery {
+                origin: Some(13335),
+                ..Default::default()
+            })
+            .len(),
+            4
+        );
+    }
+
     #[test]
     fn test_validate() {
         let trie = build_test_trie(concat!("t", line!()));
@@ -995,11 +2925,77 @@ mod tests {
         let ts = date_to_ts(date);
 
         let prefix: IpNet = "1.1.1.0/24".parse().unwrap();
-        assert_eq!(trie.validate(&prefix, 13335, ts), RpkiValidation::Valid);
-        assert_eq!(trie.validate(&prefix, 64512, ts), RpkiValidation::Invalid);
+        assert_eq!(
+            trie.validate(&prefix, 13335, ts, false).result,
+            RpkiValidation::Valid
+        );
+        assert_eq!(
+            trie.validate(&prefix, 64512, ts, false).result,
+            RpkiValidation::Invalid
+        );
 
         let unknown: IpNet = "9.9.9.0/24".parse().unwrap();
-        assert_eq!(trie.validate(&unknown, 13335, ts), RpkiValidation::Unknown);
+        assert_eq!(
+            trie.validate(&unknown, 13335, ts, false).result,
+            RpkiValidation::Unknown
+        );
+    }
+
+    #[test]
+    fn test_validate_considers_only_covering_roas() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let entries = vec![
+            make_entry("10.0.0.0/8", 1, 8, date),
+            make_entry("10.1.0.0/24", 2, 24, date),
+            make_entry("10.2.0.0/16", 3, 16, date),
+        ];
+        let mut builder = RoasTrieMut::new();
+        builder.process_entries(&entries, true);
+        let trie = dump_and_open(&mut builder, "covering");
+
+        let ts = date_to_ts(date);
+        let route: IpNet = "10.1.0.0/16".parse().unwrap();
+        // neither a more-specific nor a sibling ROA validates the route
+        let report = trie.validate(&route, 2, ts, false);
+        assert_eq!(report.result, RpkiValidation::Invalid);
+        assert_eq!(
+            report.vrps,
+            vec![CoveringVrp {
+                prefix: "10.0.0.0/8".parse().unwrap(),
+                max_len: 8,
+                origin: 1,
+                tal: "test".to_string(),
+                status: VrpMatch::OriginMismatch,
+            }]
+        );
+        assert_eq!(
+            trie.validate(&route, 3, ts, false).result,
+            RpkiValidation::Invalid
+        );
+        let report = trie.validate(&route, 1, ts, false);
+        assert_eq!(report.result, RpkiValidation::Invalid);
+        assert_eq!(report.vrps[0].status, VrpMatch::MaxLengthExceeded);
+        let uncovered: IpNet = "11.0.0.0/16".parse().unwrap();
+        assert_eq!(
+            trie.validate(&uncovered, 1, ts, false).result,
+            RpkiValidation::Unknown
+        );
+        // a ROA that is not active on the date does not invalidate the route
+        let next_day = date_to_ts(date.succ_opt().unwrap());
+        let report = trie.validate(&route, 2, next_day, false);
+        assert_eq!(report.result, RpkiValidation::Unknown);
+        assert!(report.vrps.is_empty());
+
+        let prefixes: Vec<String> = trie
+            .search(&SearchQuery {
+                prefix: Some(route),
+                match_mode: MatchMode::Overlapping,
+                ..Default::default()
+            })
+            .iter()
+            .map(|r| r.prefix.to_string())
+            .collect();
+        assert_eq!(prefixes, vec!["10.0.0.0/8", "10.1.0.0/24"]);
     }
 
     #[test]
@@ -1010,6 +3006,552 @@ mod tests {
         assert_eq!(results.len(), 3);
     }
 
+    #[test]
+    fn test_format_2_archive_is_migrated() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let mut old_trie = JointPrefixMap::new();
+        old_trie.insert(
+            "1.1.1.0/24".parse::<IpNet>().unwrap(),
+            vec![RoaRecordV2 {
+                max_len: 24,
+                origin: 13335,
+                dates: vec![(date_to_ts(day(1)), date_to_ts(day(1)))],
+            }],
+        );
+        let old = RoasTrieDataV2 {
+            format_version: 2,
+            latest_date: date_to_ts(day(1)),
+            ipv4_count: 1,
+            ipv6_count: 0,
+            trie: old_trie,
+        };
+        let path = temp_path("format-2", "rkyv");
+        std::fs::write(&path, rkyv::to_bytes::<rkyv::rancor::Error>(&old).unwrap()).unwrap();
+
+        let err = RoasTrie::open(path.to_str().unwrap())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("format 2") && err.contains("convert"), "{err}");
+        let mut builder = RoasTrieMut::load(path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(path);
+        assert_eq!(builder.get_latest_date(), day(1));
+
+        // the next snapshot adopts the record rather than starting a new one
+        let mut entry = make_entry("1.1.1.0/24", 13335, 24, day(2));
+        entry.tal = "ripencc".to_string();
+        builder.process_entries(&vec![entry], false);
+        let trie = dump_and_open(&mut builder, "format-2-migrated");
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].tal, "ripencc");
+        assert_eq!(results[0].dates_ranges, vec![(day(1), day(2))]);
+    }
+
+    #[test]
+    fn test_same_roa_kept_per_tal() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let mut ripe = make_entry("1.1.1.0/24", 13335, 24, date);
+        ripe.tal = "ripencc".to_string();
+        let mut apnic = make_entry("1.1.1.0/24", 13335, 24, date.succ_opt().unwrap());
+        apnic.tal = "apnic".to_string();
+
+        let mut builder = RoasTrieMut::new();
+        builder.process_entries(&vec![ripe], false);
+        builder.process_entries(&vec![apnic], false);
+        let jsonl = temp_path("tal", "jsonl");
+        let trie = dump_and_open(&mut builder, "tal");
+
+        let results = trie.search(&SearchQuery {
+            origin: Some(13335),
+            ..Default::default()
+        });
+        let tals: Vec<&str> = results.iter().map(|r| r.tal.as_str()).collect();
+        assert_eq!(tals, vec!["apnic", "ripencc"]);
+
+        let results = trie.search(&SearchQuery {
+            tal: Some("RIPE".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].dates_ranges, vec![(date, date)]);
+
+        // TAL survives the JSONL transport
+        trie.export_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let mut reloaded = RoasTrieMut::from_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let trie = dump_and_open(&mut reloaded, "tal-reloaded");
+        let results = trie.search(&SearchQuery {
+            tal: Some("apnic".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].tal, "apnic");
+
+        let _ = std::fs::remove_file(jsonl);
+    }
+
+    #[test]
+    fn test_roa_objects_are_deduplicated_and_transported() {
+        let object = |uri: &str, not_after: i64| RoaObject {
+            uri: uri.to_string(),
+            not_before: Some(0),
+            not_after: Some(not_after),
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let mut builder = RoasTrieMut::new();
+        for (day, obj) in [
+            (date, object("rsync://a/1.roa", 100)),
+            (date.succ_opt().unwrap(), object("rsync://a/1.roa", 100)),
+            (date.succ_opt().unwrap(), object("rsync://a/1.roa", 200)),
+        ] {
+            let mut entry = make_entry("1.1.1.0/24", 13335, 24, day);
+            entry.object = Some(obj);
+            builder.process_entries(&vec![entry], false);
+        }
+
+        let jsonl = temp_path("objects", "jsonl");
+        let trie = dump_and_open(&mut builder, "objects");
+        let expected = vec![
+            object("rsync://a/1.roa", 100),
+            object("rsync://a/1.roa", 200),
+        ];
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results[0].objects, expected);
+
+        trie.export_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let mut reloaded = RoasTrieMut::from_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let trie = dump_and_open(&mut reloaded, "objects-reloaded");
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results[0].objects, expected);
+
+        let _ = std::fs::remove_file(jsonl);
+    }
+
+    #[test]
+    fn test_roa_objects_are_capped() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let object = |i: usize| RoaObject {
+            uri: format!("rsync://a/{}.roa", i),
+            not_before: None,
+            not_after: None,
+        };
+        let mut builder = RoasTrieMut::new();
+        // reissued 20 times, and the latest object seen again
+        for i in (0..20).chain([19]) {
+            let mut entry = make_entry("1.1.1.0/24", 13335, 24, date);
+            entry.object = Some(object(i));
+            builder.process_entries(&vec![entry], false);
+        }
+        let expected: Vec<RoaObject> = [0].into_iter().chain(13..20).map(object).collect();
+        let record = &builder.trie.get(&"1.1.1.0/24".parse().unwrap()).unwrap()[0];
+        assert_eq!(record.objects, expected);
+        assert_eq!(record.object_count, 20);
+
+        // the count survives a dump and a JSONL round trip
+        let jsonl = temp_path("objects-capped", "jsonl");
+        let trie = dump_and_open(&mut builder, "objects-capped");
+        let entry = &trie.lookup_prefix(&"1.1.1.0/24".parse().unwrap())[0];
+        assert_eq!(
+            (entry.objects.clone(), entry.object_count),
+            (expected.clone(), 20)
+        );
+        trie.export_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let mut reloaded = RoasTrieMut::from_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(jsonl);
+        let mut entry = make_entry("1.1.1.0/24", 13335, 24, date);
+        entry.object = Some(object(19));
+        reloaded.process_entries(&vec![entry], false);
+        let record = &reloaded.trie.get(&"1.1.1.0/24".parse().unwrap()).unwrap()[0];
+        assert_eq!(
+            (record.objects.clone(), record.object_count),
+            (expected, 20)
+        );
+
+        // a dropped object seen again after a reload is not counted twice
+        let path = temp_path("objects-reload", "rkyv");
+        builder.dump(path.to_str().unwrap()).unwrap();
+        let mut reopened = RoasTrieMut::load(path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(path);
+        for _ in 0..2 {
+            let mut entry = make_entry("1.1.1.0/24", 13335, 24, date);
+            entry.object = Some(object(5));
+            reopened.process_entries(&vec![entry], false);
+        }
+        let record = &reopened.trie.get(&"1.1.1.0/24".parse().unwrap()).unwrap()[0];
+        assert_eq!(record.object_count, 20);
+        assert_eq!(record.objects.last(), Some(&object(5)));
+    }
+
+    #[test]
+    fn test_roa_end_tells_expiry_from_revocation() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let entry = |last: u32, not_after: NaiveDate| RoasLookupEntry {
+            prefix: "1.1.1.0/24".parse().unwrap(),
+            origin: 13335,
+            max_len: 24,
+            tal: "apnic".to_string(),
+            dates_ranges: vec![(day(1), day(last))],
+            interpolated_ranges: Vec::new(),
+            objects: vec![RoaObject {
+                uri: "rsync://a/1.roa".to_string(),
+                not_before: None,
+                not_after: Some(date_to_ts(not_after) + 3600),
+            }],
+            object_count: 1,
+            expires: None,
+        };
+        assert_eq!(entry(10, day(10)).end(day(20)), Some(RoaEnd::Expired));
+        assert_eq!(entry(10, day(11)).end(day(20)), Some(RoaEnd::Expired));
+        assert_eq!(entry(10, day(30)).end(day(20)), Some(RoaEnd::Revoked));
+        assert_eq!(entry(20, day(20)).end(day(20)), None);
+
+        let mut unknown = entry(10, day(10));
+        unknown.objects.clear();
+        assert_eq!(unknown.end(day(20)), None);
+        unknown.expires = Some(date_to_ts(day(30)));
+        assert_eq!(unknown.end(day(20)), Some(RoaEnd::Revoked));
+    }
+
+    #[test]
+    fn test_roa_expiry_keeps_the_latest() {
+        let mut builder = RoasTrieMut::new();
+        for (d, expires) in [(1, Some(300)), (2, Some(500)), (3, Some(400)), (4, None)] {
+            let date = NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+            let entry = make_entry("1.1.1.0/24", 13335, 24, date).with_expires(expires);
+            builder.process_entries(&vec![entry], false);
+        }
+        let record = &builder.trie.get(&"1.1.1.0/24".parse().unwrap()).unwrap()[0];
+        assert_eq!(record.expires, Some(500));
+
+        // the expiry survives a dump and a JSONL round trip
+        let jsonl = temp_path("expires", "jsonl");
+        let trie = dump_and_open(&mut builder, "expires");
+        assert_eq!(
+            trie.lookup_prefix(&"1.1.1.0/24".parse().unwrap())[0].expires,
+            Some(500)
+        );
+        trie.export_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let reloaded = RoasTrieMut::from_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(jsonl);
+        let record = &reloaded.trie.get(&"1.1.1.0/24".parse().unwrap()).unwrap()[0];
+        assert_eq!(record.expires, Some(500));
+    }
+
+    /// Source with one ROA per day of January 2024 up to `until`, whose
+    /// snapshots for the days in `broken` fail to read.
+    struct FlakySource {
+        until: u32,
+        broken: std::sync::Mutex<Vec<NaiveDate>>,
+    }
+
+    impl RoaSource for FlakySource {
+        fn name(&self) -> String {
+            "flaky".to_string()
+        }
+
+        fn tals(&self) -> Vec<String> {
+            vec!["test".to_string()]
+        }
+
+        fn list_snapshots(
+            &self,
+            tal: &str,
+            from: Option<NaiveDate>,
+            _until: Option<NaiveDate>,
+        ) -> Vec<RoaFile> {
+            (1..=self.until)
+                .map(|d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap())
+                .filter(|d| from.map_or(true, |f| *d >= f))
+                .map(|d| RoaFile {
+                    url: format!("flaky://{}/{}", tal, d),
+                    tal: tal.to_string(),
+                    file_date: d,
+                    rows_count: 0,
+                    processed: false,
+                })
+                .collect()
+        }
+
+        fn read_snapshot(&self, file: &RoaFile) -> Result<Vec<RoaEntry>> {
+            if self.broken.lock().unwrap().contains(&file.file_date) {
+                return Err(anyhow!("connection reset"));
+            }
+            Ok(vec![make_entry("1.1.1.0/24", 13335, 24, file.file_date)])
+        }
+    }
+
+    #[test]
+    fn test_failed_files_are_retried_with_backoff() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let mut source = FlakySource {
+            until: 3,
+            broken: std::sync::Mutex::new(vec![day(2)]),
+        };
+        let mut builder = RoasTrieMut::new();
+        let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.pending_retries, 1);
+        assert_eq!(builder.get_latest_date(), day(3));
+        assert_eq!(builder.failed_files()[0].date, date_to_ts(day(2)));
+
+        // the queue survives a dump and load
+        let path = temp_path("retry", "rkyv");
+        builder.dump(path.to_str().unwrap()).unwrap();
+        let trie = RoasTrie::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(trie.failed_files(), builder.failed_files());
+        let mut builder = RoasTrieMut::load(path.to_str().unwrap()).unwrap();
+        let _ = std::fs::remove_file(path);
+
+        // still within the backoff: not retried yet
+        source.until = 4;
+        source.broken.lock().unwrap().clear();
+        let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!((summary.files, summary.retried), (1, 0));
+        assert_eq!(summary.pending_retries, 1);
+
+        builder.failed_files[0].last_attempt -= RETRY_BACKOFF_SECONDS;
+        let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!((summary.retried, summary.recovered), (1, 1));
+        assert_eq!(summary.pending_retries, 0);
+        assert!(builder.failed_files().is_empty());
+
+        // the recovered day closes the gap between the ranges
+        let trie = dump_and_open(&mut builder, "retry");
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results[0].dates_ranges, vec![(day(1), day(4))]);
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles_up_to_cap() {
+        let failed = |attempts| FailedFile {
+            url: String::new(),
+            tal: "test".to_string(),
+            date: 0,
+            attempts,
+            last_attempt: 0,
+            error: String::new(),
+        };
+        assert_eq!(failed(1).next_attempt(), RETRY_BACKOFF_SECONDS);
+        assert_eq!(failed(3).next_attempt(), 4 * RETRY_BACKOFF_SECONDS);
+        assert_eq!(failed(40).next_attempt(), RETRY_BACKOFF_MAX_SECONDS);
+        assert!(!failed(RETRY_MAX_ATTEMPTS - 1).abandoned());
+        assert!(failed(RETRY_MAX_ATTEMPTS).abandoned());
+    }
+
+    #[test]
+    fn test_failed_file_is_abandoned_after_max_attempts() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let mut source = FlakySource {
+            until: 3,
+            broken: std::sync::Mutex::new(vec![day(2)]),
+        };
+        let mut builder = RoasTrieMut::new();
+        builder.update(&source, None, ParseMode::Strict).unwrap();
+        for attempt in 2..=RETRY_MAX_ATTEMPTS {
+            builder.failed_files[0].last_attempt -= RETRY_BACKOFF_MAX_SECONDS;
+            let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+            assert_eq!(summary.retried, 1);
+            assert_eq!(builder.failed_files()[0].attempts, attempt);
+        }
+        let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!((summary.pending_retries, summary.abandoned), (0, 1));
+        assert!(builder.failed_files()[0].abandoned());
+
+        // an abandoned file is kept for reporting but never retried
+        source.until = 4;
+        source.broken.lock().unwrap().clear();
+        builder.failed_files[0].last_attempt -= RETRY_BACKOFF_MAX_SECONDS;
+        let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!((summary.files, summary.retried), (1, 0));
+        assert_eq!(summary.abandoned, 1);
+        let trie = dump_and_open(&mut builder, "abandoned");
+        assert!(trie.failed_files()[0].abandoned());
+    }
+
+    #[test]
+    fn test_observations_are_archived_and_transported() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let mut builder = RoasTrieMut::new();
+        let mut entry = make_entry("1.1.1.0/24", 13335, 24, day(1));
+        entry.tal = "ripencc".to_string();
+        builder.process_entries(&vec![entry], true);
+        builder.record_observation("ripencc", day(1), 1);
+        builder.record_observation("ripencc", day(3), 0);
+        builder.record_observation("apnic", day(2), 5);
+        let expected = builder.observations();
+        assert_eq!(expected[0].tal, "apnic");
+
+        let jsonl = temp_path("observations", "jsonl");
+        let trie = dump_and_open(&mut builder, "observations");
+        assert_eq!(trie.observations(), expected);
+
+        trie.export_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let reloaded = RoasTrieMut::from_jsonl(jsonl.to_str().unwrap()).unwrap();
+        assert_eq!(reloaded.observations(), expected);
+        // a feed's high-water mark also covers days it observed without records
+        assert_eq!(
+            reloaded.tal_latest_dates(),
+            vec![
+                ("apnic".to_string(), day(2)),
+                ("ripencc".to_string(), day(3))
+            ]
+        );
+
+        let _ = std::fs::remove_file(jsonl);
+    }
+
+    #[test]
+    fn test_held_out_day_carries_previous_state_forward() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let prefixes = ["1.0.0.0/24", "1.0.1.0/24", "1.0.2.0/24", "1.0.3.0/24"];
+        let mut builder = RoasTrieMut::new().with_anomaly_config(AnomalyConfig {
+            drop_ratio: 0.6,
+            hold_out: true,
+        });
+        // day 2 is a truncated snapshot: one of four ROAs, plus a stray one
+        for (d, published) in [(1, &prefixes[..]), (2, &prefixes[..1]), (3, &prefixes[..])] {
+            let mut entries: Vec<RoaEntry> = published
+                .iter()
+                .map(|p| make_entry(p, 13335, 24, day(d)))
+                .collect();
+            if d == 2 {
+                entries.push(make_entry("9.9.9.0/24", 64512, 24, day(d)));
+            }
+            builder.process_entries(&entries, false);
+            builder.record_observation("test", day(d), entries.len() as u64);
+        }
+
+        let anomalies = builder.check_anomalies(|_, d| d == day(2));
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].kind, crate::AnomalyKind::RecordDrop);
+        assert!(anomalies[0].held_out);
+        assert_eq!(builder.held_out_days(), vec![("test".to_string(), day(2))]);
+
+        let trie = dump_and_open(&mut builder, "hold-out");
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results.len(), 4);
+        for r in &results {
+            assert_eq!(r.dates_ranges, vec![(day(1), day(3))]);
+            assert_eq!(r.interpolated_ranges, vec![(day(2), day(2))]);
+        }
+        assert_eq!(trie.held_out_days(), vec![("test".to_string(), day(2))]);
+    }
+
+    #[test]
+    fn test_fill_gaps_respects_gap_tal_and_records_policy() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let mut builder = RoasTrieMut::new();
+        for tal in ["apnic", "ripencc"] {
+            for d in [1, 4] {
+                let mut entry = make_entry("1.1.1.0/24", 13335, 24, day(d));
+                entry.tal = tal.to_string();
+                builder.process_entries(&vec![entry], false);
+            }
+        }
+        let policy = GapPolicy::from_toml(
+            "[[gap]]\nstart = \"2024-01-02\"\nend = \"2024-01-03\"\ntal = \"ripencc\"",
+        )
+        .unwrap();
+        builder.fill_gaps(&policy);
+        assert_eq!(builder.gap_policy_hash(), Some(policy.hash().as_str()));
+
+        let jsonl = temp_path("gap-policy", "jsonl");
+        let trie = dump_and_open(&mut builder, "gap-policy");
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results[0].tal, "apnic");
+        assert_eq!(results[0].dates_ranges.len(), 2);
+        assert_eq!(results[1].tal, "ripencc");
+        assert_eq!(results[1].dates_ranges, vec![(day(1), day(4))]);
+        assert_eq!(trie.gap_policy_hash(), Some(policy.hash()));
+
+        trie.export_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let reloaded = RoasTrieMut::from_jsonl(jsonl.to_str().unwrap()).unwrap();
+        assert_eq!(reloaded.gap_policy_hash(), Some(policy.hash().as_str()));
+
+        let _ = std::fs::remove_file(jsonl);
+    }
+
+    #[test]
+    fn test_interpolated_days_are_flagged() {
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let mut builder = RoasTrieMut::new();
+        for d in [1, 4, 5] {
+            builder.process_entries(&vec![make_entry("1.1.1.0/24", 13335, 24, day(d))], false);
+        }
+        let policy =
+            GapPolicy::from_toml("[[gap]]\nstart = \"2024-01-02\"\nend = \"2024-01-03\"").unwrap();
+        builder.fill_gaps(&policy);
+
+        let jsonl = temp_path("interpolated", "jsonl");
+        let trie = dump_and_open(&mut builder, "interpolated");
+
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results[0].dates_ranges, vec![(day(1), day(5))]);
+        assert_eq!(results[0].interpolated_ranges, vec![(day(2), day(3))]);
+
+        // observed-only searches treat the bridged days as absent
+        let results = trie.search(&SearchQuery {
+            observed_only: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            results[0].dates_ranges,
+            vec![(day(1), day(1)), (day(4), day(5))]
+        );
+        assert!(results[0].interpolated_ranges.is_empty());
+        assert!(trie
+            .search(&SearchQuery {
+                date: Some(day(2)),
+                observed_only: true,
+                ..Default::default()
+            })
+            .is_empty());
+        assert_eq!(
+            trie.search(&SearchQuery {
+                date: Some(day(2)),
+                ..Default::default()
+            })
+            .len(),
+            1
+        );
+
+        let prefix: IpNet = "1.1.1.0/24".parse().unwrap();
+        let ts = date_to_ts(day(2));
+        assert_eq!(
+            trie.validate(&prefix, 13335, ts, false).result,
+            RpkiValidation::Valid
+        );
+        assert_eq!(
+            trie.validate(&prefix, 13335, ts, true).result,
+            RpkiValidation::Unknown
+        );
+
+        // the flags survive the JSONL transport, and an observed day clears them
+        trie.export_jsonl(jsonl.to_str().unwrap()).unwrap();
+        let mut reloaded = RoasTrieMut::from_jsonl(jsonl.to_str().unwrap()).unwrap();
+        reloaded.process_entries(&vec![make_entry("1.1.1.0/24", 13335, 24, day(2))], true);
+        let trie = dump_and_open(&mut reloaded, "interpolated-reloaded");
+        let results = trie.search(&SearchQuery::default());
+        assert_eq!(results[0].dates_ranges, vec![(day(1), day(5))]);
+        assert_eq!(results[0].interpolated_ranges, vec![(day(3), day(3))]);
+
+        let _ = std::fs::remove_file(jsonl);
+    }
+
+    #[test]
+    fn test_subtract_ranges() {
+        let d = ONE_DAY_SECONDS;
+        assert_eq!(
+            subtract_ranges(&[(0, 9 * d)], &[(2 * d, 3 * d), (9 * d, 9 * d)]),
+            vec![(0, d), (4 * d, 8 * d)]
+        );
+        assert_eq!(subtract_ranges(&[(0, d)], &[(0, d)]), vec![]);
+        assert_eq!(
+            subtract_ranges(&[(0, 0), (5 * d, 6 * d)], &[(d, 2 * d)]),
+            vec![(0, 0), (5 * d, 6 * d)]
+        );
+    }
+
     #[test]
     fn import_jsonl_reports_physical_line_number() {
         let path = std::env::temp_dir().join(format!(
@@ -0,0 +1,489 @@
+//! Pluggable sources of historical ROA snapshots.
+//!
+//! A [`RoaSource`] lists the daily snapshots it can provide for a date range
+//! and reads each one into a batch of [`RoaEntry`] values. `rebuild` and
+//! [`RoasTrieMut::update`](crate::RoasTrieMut::update) only talk to this trait,
+//! so new inputs can be added without touching the trie code.
+
+use crate::{
+    crawl_tal_after, get_tal_urls_from, is_remote_url, parse_roas_csv_with, truncation_anomaly,
+    Anomaly, FailedFile, ParseMode, ParsedSnapshot, RoaCache, RoaCsvErrorKind, RoaEntry, RoaFile,
+};
+use anyhow::Result;
+use chrono::NaiveDate;
+use tracing::warn;
+
+/// A provider of dated ROA snapshots.
+pub trait RoaSource: Send + Sync {
+    /// Human-readable description used in logs.
+    fn name(&self) -> String;
+
+    /// Names of the feeds this source publishes snapshots for, e.g. TAL names.
+    fn tals(&self) -> Vec<String>;
+
+    /// Names of every feed of the source, including those a filter (such as
+    /// `update --tal`) leaves out of [`RoaSource::tals`]. `update` pins where
+    /// each of them resumes before it advances the archive.
+    fn all_tals(&self) -> Vec<String> {
+        self.tals()
+    }
+
+    /// List the snapshots of one feed whose date falls within `[from, until]`.
+    fn list_snapshots(
+        &self,
+        tal: &str,
+        from: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Vec<RoaFile>;
+
+    /// Read all ROA entries of a single snapshot.
+    fn read_snapshot(&self, file: &RoaFile) -> Result<Vec<RoaEntry>>;
+
+    /// Read a snapshot under the given [`ParseMode`]. Sources that cannot skip
+    /// individual rows fall back to [`RoaSource::read_snapshot`].
+    fn read_snapshot_with(&self, file: &RoaFile, _mode: ParseMode) -> Result<ParsedSnapshot> {
+        Ok(ParsedSnapshot {
+            entries: self.read_snapshot(file)?,
+            skipped: Vec::new(),
+        })
+    }
+
+    /// List the snapshots of every feed within `[from, until]`, sorted by date.
+    fn list_all_snapshots(
+        &self,
+        from: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Vec<RoaFile> {
+        let mut files: Vec<RoaFile> = self
+            .tals()
+            .iter()
+            .flat_map(|tal| self.list_snapshots(tal, from, until))
+            .collect();
+        files.sort_by_key(|f| f.file_date);
+        files
+    }
+}
+
+/// The RIPE NCC `roas.csv.xz` archive, either at `ftp.ripe.net` or a local
+/// mirror with the same `<tal>.tal/YYYY/MM/DD/roas.csv.xz` layout. Remote
+/// files can be read through a [`RoaCache`].
+pub struct RipeArchive {
+    root: String,
+    tal: Option<String>,
+    cache: Option<RoaCache>,
+}
+
+impl Default for RipeArchive {
+    fn default() -> Self {
+        Self::new(crate::RIPE_RPKI_ROOT, None)
+    }
+}
+
+impl RipeArchive {
+    /// Archive rooted at `root`, optionally limited to a single TAL.
+    pub fn new(root: &str, tal: Option<String>) -> Self {
+        RipeArchive {
+            root: root.trim_end_matches('/').to_string(),
+            tal,
+            cache: None,
+        }
+    }
+
+    /// Read remote files through `cache`, downloading each one only once.
+    pub fn with_cache(mut self, cache: RoaCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Local path (or URL, without a cache) to parse `file` from.
+    fn snapshot_path(&self, file: &RoaFile) -> Result<String> {
+        match &self.cache {
+            Some(cache) => Ok(cache.fetch(file)?.to_string_lossy().to_string()),
+            None => Ok(file.url.clone()),
+        }
+    }
+
+    /// Parse `file`, evicting its cached copy if it cannot be read, so that
+    /// a retry downloads it again instead of re-reading the same bytes.
+    fn parse_snapshot(&self, file: &RoaFile, mode: ParseMode) -> Result<ParsedSnapshot> {
+        let parsed = parse_roas_csv_with(self.snapshot_path(file)?.as_str(), mode);
+        if let (Err(e), Some(cache)) = (&parsed, &self.cache) {
+            if is_remote_url(&file.url)
+                && matches!(
+                    e.kind,
+                    RoaCsvErrorKind::Io(_) | RoaCsvErrorKind::MissingHeader
+                )
+                && cache.evict(&file.tal, file.file_date)?
+            {
+                warn!("evicted unreadable cached file for {}", file.url);
+            }
+        }
+        Ok(parsed?)
+    }
+
+    fn tal_url(&self, tal: &str) -> String {
+        format!("{}/{}.tal", self.root, tal)
+    }
+}
+
+/// TAL names of `<root>/<tal>.tal` URLs.
+fn tal_names(urls: &[String]) -> Vec<String> {
+    urls.iter()
+        .map(|url| {
+            url.rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches(".tal")
+                .to_string()
+        })
+        .collect()
+}
+
+impl RoaSource for RipeArchive {
+    fn name(&self) -> String {
+        let name = match &self.tal {
+            Some(tal) => format!("{} ({})", self.root, tal),
+            None => self.root.clone(),
+        };
+        match &self.cache {
+            Some(cache) => format!("{} via cache {}", name, cache.dir().display()),
+            None => name,
+        }
+    }
+
+    fn tals(&self) -> Vec<String> {
+        tal_names(&get_tal_urls_from(&self.root, self.tal.clone()))
+    }
+
+    fn all_tals(&self) -> Vec<String> {
+        match self.tal {
+            Some(_) => tal_names(&get_tal_urls_from(&self.root, None)),
+            None => self.tals(),
+        }
+    }
+
+    fn list_snapshots(
+        &self,
+        tal: &str,
+        from: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Vec<RoaFile> {
+        crawl_tal_after(self.tal_url(tal).as_str(), from, until)
+    }
+
+    fn read_snapshot(&self, file: &RoaFile) -> Result<Vec<RoaEntry>> {
+        Ok(self.parse_snapshot(file, ParseMode::Strict)?.entries)
+    }
+
+    fn read_snapshot_with(&self, file: &RoaFile, mode: ParseMode) -> Result<ParsedSnapshot> {
+        self.parse_snapshot(file, mode)
+    }
+}
+
+/// Outcome of ingesting a batch of snapshots, reported at the end of
+/// `rebuild` and `update`.
+#[derive(Debug, Default, Clone)]
+pub struct IngestSummary {
+    /// Snapshots read successfully (possibly with skipped rows).
+    pub files: usize,
+    /// Snapshots that could not be read, with the reason.
+    pub failed: Vec<(String, String)>,
+    /// Malformed rows skipped in [`ParseMode::Lenient`].
+    pub skipped_rows: usize,
+    /// Previously failed snapshots retried, and how many of those succeeded.
+    pub retried: usize,
+    pub recovered: usize,
+    /// Failed snapshots still queued for retry after this run.
+    pub pending_retries: usize,
+    /// Failed snapshots given up on after too many attempts.
+    pub abandoned: usize,
+    /// Source anomalies flagged among the ingested snapshots.
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl IngestSummary {
+    /// Count the pending and abandoned files of a retry queue.
+    pub fn count_retries(&mut self, failed: &[FailedFile]) {
+        self.abandoned = failed.iter().filter(|f| f.abandoned()).count();
+        self.pending_retries = failed.len() - self.abandoned;
+    }
+
+    /// Record the result of reading `file`, logging failures and skipped rows.
+    pub fn record(&mut self, file: &RoaFile, result: &Result<ParsedSnapshot>) {
+        match result {
+            Ok(snapshot) => {
+                self.files += 1;
+                self.skipped_rows += snapshot.skipped.len();
+                if let Some(first) = snapshot.skipped.first() {
+                    warn!(
+                        "skipped {} malformed row(s) in {}, first: {}",
+                        snapshot.skipped.len(),
+                        file.url,
+                        first
+                    );
+                }
+            }
+            Err(e) => {
+                warn!("failed to process {}: {}", file.url, e);
+                self.failed.push((file.url.clone(), e.to_string()));
+                self.anomalies.extend(truncation_anomaly(file, e));
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for IngestSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} file(s) processed, {} failed, {} malformed row(s) skipped",
+            self.files,
+            self.failed.len(),
+            self.skipped_rows
+        )?;
+        if self.retried > 0 || self.pending_retries > 0 {
+            write!(
+                f,
+                ", {} of {} retried file(s) recovered, {} pending retry",
+                self.recovered, self.retried, self.pending_retries
+            )?;
+        }
+        if self.abandoned > 0 {
+            write!(f, ", {} abandoned after repeated failures", self.abandoned)?;
+        }
+        if !self.anomalies.is_empty() {
+            let held_out = self.anomalies.iter().filter(|a| a.held_out).count();
+            write!(
+                f,
+                ", {} source anomal{} flagged ({} held out)",
+                self.anomalies.len(),
+                if self.anomalies.len() == 1 {
+                    "y"
+                } else {
+                    "ies"
+                },
+                held_out
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::roas_trie::tests::dump_and_open;
+    use crate::{RoasTrieMut, SearchQuery};
+
+    /// In-memory source with one fixed ROA per (feed, day), optionally
+    /// limited to one feed like `update --tal`.
+    struct FixedSource {
+        files: Vec<(&'static str, NaiveDate)>,
+        only: Option<&'static str>,
+    }
+
+    impl RoaSource for FixedSource {
+        fn name(&self) -> String {
+            "fixed".to_string()
+        }
+
+        fn tals(&self) -> Vec<String> {
+            match self.only {
+                Some(tal) => vec![tal.to_string()],
+                None => self.all_tals(),
+            }
+        }
+
+        fn all_tals(&self) -> Vec<String> {
+            let mut tals: Vec<String> = self.files.iter().map(|(t, _)| t.to_string()).collect();
+            tals.dedup();
+            tals
+        }
+
+        fn list_snapshots(
+            &self,
+            tal: &str,
+            from: Option<NaiveDate>,
+            until: Option<NaiveDate>,
+        ) -> Vec<RoaFile> {
+            self.files
+                .iter()
+                .filter(|(t, _)| *t == tal)
+                .map(|(_, d)| d)
+                .filter(|d| from.map_or(true, |f| **d >= f) && until.map_or(true, |u| **d <= u))
+                .map(|d| RoaFile {
+                    url: format!("fixed://{}/{}", tal, d),
+                    tal: tal.to_string(),
+                    file_date: *d,
+                    rows_count: 0,
+                    processed: false,
+                })
+                .collect()
+        }
+
+        fn read_snapshot(&self, file: &RoaFile) -> Result<Vec<RoaEntry>> {
+            Ok(vec![RoaEntry {
+                tal: file.tal.clone(),
+                prefix: "1.1.1.0/24".parse().unwrap(),
+                max_len: 24,
+                asn: 13335,
+                date: file.file_date,
+                object: None,
+                expires: None,
+            }])
+        }
+    }
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, d).unwrap()
+    }
+
+    #[test]
+    fn unreadable_cached_file_is_evicted() {
+        let dir =
+            std::env::temp_dir().join(format!("wayback-rpki-source-cache-{}", std::process::id()));
+        let cache = RoaCache::new(dir.to_str().unwrap());
+        let archive = RipeArchive::new("https://rpki.invalid", None).with_cache(cache.clone());
+        let file = RoaFile {
+            url: "https://rpki.invalid/ripencc.tal/2024/01/01/roas.csv.xz".to_string(),
+            tal: "ripencc".to_string(),
+            file_date: day(1),
+            rows_count: 0,
+            processed: false,
+        };
+        // framed like xz, but the stream inside is garbage
+        let path = cache.path_for("ripencc", day(1));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"\xFD7zXZ\x00garbageYZ").unwrap();
+
+        assert!(archive
+            .read_snapshot_with(&file, ParseMode::Lenient)
+            .is_err());
+        assert!(!cache.contains("ripencc", day(1)));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn update_consumes_custom_source() {
+        let source = FixedSource {
+            files: vec![("test", day(1)), ("test", day(2)), ("test", day(3))],
+            only: None,
+        };
+
+        let mut builder = RoasTrieMut::new();
+        let first = source.list_snapshots("test", None, Some(day(1)));
+        builder.process_entries(&source.read_snapshot(&first[0]).unwrap(), false);
+        builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!(builder.get_latest_date(), day(3));
+
+        let trie = dump_and_open(&mut builder, "source-custom");
+        let results = trie.search(&SearchQuery {
+            origin: Some(13335),
+            ..Default::default()
+        });
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].dates_ranges, vec![(day(1), day(3))]);
+    }
+
+    #[test]
+    fn update_catches_up_each_tal_separately() {
+        let mut builder = RoasTrieMut::new();
+        // an earlier `update --tal ripencc` ran ahead of apnic
+        let earlier = FixedSource {
+            files: vec![("apnic", day(1)), ("ripencc", day(1)), ("ripencc", day(2))],
+            only: None,
+        };
+        builder.update(&earlier, None, ParseMode::Strict).unwrap();
+        assert_eq!(
+            builder.tal_latest_dates(),
+            vec![
+                ("apnic".to_string(), day(1)),
+                ("ripencc".to_string(), day(2))
+            ]
+        );
+
+        let source = FixedSource {
+            files: vec![
+                ("apnic", day(1)),
+                ("apnic", day(2)),
+                ("apnic", day(3)),
+                ("ripencc", day(1)),
+                ("ripencc", day(2)),
+                ("ripencc", day(3)),
+            ],
+            only: None,
+        };
+        let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!(summary.files, 3);
+        assert_eq!(builder.observations().len(), 6);
+
+        let trie = dump_and_open(&mut builder, "source-per-tal");
+        assert_eq!(
+            trie.tal_latest_dates(),
+            vec![
+                ("apnic".to_string(), day(3)),
+                ("ripencc".to_string(), day(3))
+            ]
+        );
+        let results = trie.search(&SearchQuery {
+            tal: Some("apnic".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(results[0].dates_ranges, vec![(day(1), day(3))]);
+    }
+
+    #[test]
+    fn update_of_one_tal_does_not_skip_the_others_on_a_markless_archive() {
+        // e.g. loaded from a JSONL transport: data up to day 1, no marks
+        let mut builder = RoasTrieMut::new();
+        for tal in ["apnic", "ripencc"] {
+            let file = RoaFile {
+                url: String::new(),
+                tal: tal.to_string(),
+                file_date: day(1),
+                rows_count: 0,
+                processed: false,
+            };
+            let entries = FixedSource {
+                files: vec![],
+                only: None,
+            }
+            .read_snapshot(&file)
+            .unwrap();
+            builder.process_entries(&entries, false);
+        }
+        assert!(builder.tal_latest_dates().is_empty());
+
+        let files = vec![
+            ("apnic", day(2)),
+            ("apnic", day(3)),
+            ("ripencc", day(2)),
+            ("ripencc", day(3)),
+        ];
+        let ripencc_only = FixedSource {
+            files: files.clone(),
+            only: Some("ripencc"),
+        };
+        builder
+            .update(&ripencc_only, None, ParseMode::Strict)
+            .unwrap();
+        assert_eq!(
+            builder.tal_latest_dates(),
+            vec![
+                ("apnic".to_string(), day(1)),
+                ("ripencc".to_string(), day(3))
+            ]
+        );
+
+        let all = FixedSource { files, only: None };
+        let summary = builder.update(&all, None, ParseMode::Strict).unwrap();
+        assert_eq!(summary.files, 2);
+        let trie = dump_and_open(&mut builder, "source-markless");
+        let results = trie.search(&SearchQuery {
+            tal: Some("apnic".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(results[0].dates_ranges, vec![(day(1), day(3))]);
+    }
+}
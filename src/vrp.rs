@@ -0,0 +1,1038 @@
+//! Parsers for validated ROA payload (VRP) exports produced by relying-party
+//! software, and a [`RoaSource`] over a directory of such daily exports.
+//!
+//! Supported inputs (see [`VrpFormat`]), plain or compressed:
+//! - rpki-client `json` output (`rpki-client.json`), and rpkiviews `.tgz`
+//!   snapshots containing it
+//! - Routinator `csv`, `csvext` and `jsonext` output
+//! - RIPE NCC RPKI Validator `export.json`
+
+use crate::{
+    parse_validity_time, ParseMode, ParsedSnapshot, RoaCsvError, RoaCsvErrorKind, RoaEntry,
+    RoaFile, RoaObject, RoaSource,
+};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use ipnet::IpNet;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::Path;
+use std::sync::OnceLock;
+use tracing::warn;
+
+/// Feed name used for [`RoaFile::tal`] by [`VrpDirectory`]: a VRP export
+/// covers all trust anchors at once.
+pub const VRP_FEED: &str = "vrps";
+
+/// Name of the JSON document inside an rpkiviews tarball.
+const RPKI_CLIENT_JSON_NAME: &str = "rpki-client.json";
+
+/// Map the trust-anchor names used by different validators onto the TAL
+/// names of the RIPE archive (`afrinic`, `apnic`, `arin`, `lacnic`, `ripencc`).
+/// Unrecognized names are lower-cased and returned as-is.
+pub fn normalize_tal(name: &str) -> String {
+    let lower = name.trim().to_lowercase();
+    let lower = lower.strip_suffix(".tal").unwrap_or(&lower);
+    for (needle, tal) in [
+        ("afrinic", "afrinic"),
+        ("apnic", "apnic"),
+        ("arin", "arin"),
+        ("lacnic", "lacnic"),
+        ("ripe", "ripencc"),
+    ] {
+        if lower.starts_with(needle) {
+            return tal.to_string();
+        }
+    }
+    lower.to_string()
+}
+
+/// Validators have emitted the ASN both as `13335` and as `"AS13335"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonAsn {
+    Number(u32),
+    Text(String),
+}
+
+impl JsonAsn {
+    fn value(&self) -> std::result::Result<u32, RoaCsvErrorKind> {
+        match self {
+            JsonAsn::Number(n) => Ok(*n),
+            JsonAsn::Text(s) => parse_asn(s),
+        }
+    }
+}
+
+fn parse_asn(s: &str) -> std::result::Result<u32, RoaCsvErrorKind> {
+    s.trim()
+        .trim_start_matches("AS")
+        .parse::<u32>()
+        .map_err(|_| RoaCsvErrorKind::InvalidAsn(s.to_string()))
+}
+
+fn parse_prefix(s: &str) -> std::result::Result<IpNet, RoaCsvErrorKind> {
+    s.trim()
+        .parse::<IpNet>()
+        .map_err(|_| RoaCsvErrorKind::InvalidPrefix(s.to_string()))
+}
+
+/// A CSV max length of `prefix`: empty means the prefix length, anything else
+/// must be a length between the prefix length and the address width.
+fn parse_max_len(s: &str, prefix: IpNet) -> std::result::Result<i32, RoaCsvErrorKind> {
+    match s.trim() {
+        "" => Ok(prefix.prefix_len() as i32),
+        t => match t.parse::<u8>() {
+            Ok(l) if l >= prefix.prefix_len() && l <= prefix.max_prefix_len() => Ok(l as i32),
+            _ => Err(RoaCsvErrorKind::InvalidMaxLength(s.to_string())),
+        },
+    }
+}
+
+/// Fail on `error` in [`ParseMode::Strict`]; otherwise add it to `skipped`.
+fn skip_or_fail(mode: ParseMode, error: RoaCsvError, skipped: &mut Vec<RoaCsvError>) -> Result<()> {
+    match mode {
+        ParseMode::Strict => Err(error.into()),
+        ParseMode::Lenient => {
+            skipped.push(error);
+            Ok(())
+        }
+    }
+}
+
+/// Routinator `jsonext` lists the ROA objects a VRP was derived from.
+#[derive(Deserialize)]
+struct JsonVrpSource {
+    tal: Option<String>,
+    uri: Option<String>,
+    validity: Option<JsonValidity>,
+}
+
+#[derive(Deserialize)]
+struct JsonValidity {
+    #[serde(rename = "notBefore")]
+    not_before: Option<String>,
+    #[serde(rename = "notAfter")]
+    not_after: Option<String>,
+}
+
+impl JsonVrpSource {
+    fn object(&self) -> Option<RoaObject> {
+        let validity = self.validity.as_ref();
+        self.uri.as_ref().map(|uri| RoaObject {
+            uri: uri.clone(),
+            not_before: validity
+                .and_then(|v| v.not_before.as_deref())
+                .and_then(parse_validity_time),
+            not_after: validity
+                .and_then(|v| v.not_after.as_deref())
+                .and_then(parse_validity_time),
+        })
+    }
+}
+
+/// One VRP in any of the JSON exports: rpki-client and the RIPE validator
+/// use `ta`, Routinator `jsonext` nests the TAL in `source`.
+#[derive(Deserialize)]
+struct JsonVrp {
+    asn: JsonAsn,
+    prefix: String,
+    #[serde(rename = "maxLength")]
+    max_length: Option<u8>,
+    ta: Option<String>,
+    /// rpki-client: the earliest expiry along the ROA's certificate chain,
+    /// as a Unix timestamp
+    expires: Option<i64>,
+    #[serde(default)]
+    source: Vec<JsonVrpSource>,
+}
+
+#[derive(Deserialize)]
+struct JsonVrpMetadata {
+    /// rpki-client
+    buildtime: Option<String>,
+    /// Routinator
+    #[serde(rename = "generatedTime")]
+    generated_time: Option<String>,
+    /// rpki-client and Routinator
+    generated: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct JsonVrpDump {
+    metadata: Option<JsonVrpMetadata>,
+    /// Decoded one VRP at a time, so that a malformed one can be skipped.
+    roas: Vec<serde_json::Value>,
+}
+
+impl JsonVrp {
+    /// The entries of this VRP on `date`: one per TAL it was published under.
+    fn entries(&self, date: NaiveDate) -> std::result::Result<Vec<RoaEntry>, RoaCsvErrorKind> {
+        let prefix = parse_prefix(&self.prefix)?;
+        let asn = self.asn.value()?;
+        let max_len = match self.max_length {
+            None => prefix.prefix_len(),
+            Some(l) if l >= prefix.prefix_len() && l <= prefix.max_prefix_len() => l,
+            Some(l) => return Err(RoaCsvErrorKind::InvalidMaxLength(l.to_string())),
+        } as i32;
+
+        let mut origins: Vec<(String, Option<RoaObject>)> =
+            self.ta.iter().map(|ta| (normalize_tal(ta), None)).collect();
+        origins.extend(
+            self.source
+                .iter()
+                .filter_map(|s| s.tal.as_deref().map(|tal| (normalize_tal(tal), s.object()))),
+        );
+        if origins.is_empty() {
+            origins.push((String::new(), None));
+        }
+        Ok(origins
+            .into_iter()
+            .map(|(tal, object)| RoaEntry {
+                tal,
+                prefix,
+                max_len,
+                asn,
+                date,
+                object,
+                expires: self.expires,
+            })
+            .collect())
+    }
+}
+
+impl JsonVrpMetadata {
+    /// The day the snapshot was produced.
+    fn date(&self) -> Option<NaiveDate> {
+        for time in [&self.buildtime, &self.generated_time]
+            .into_iter()
+            .flatten()
+        {
+            if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(time) {
+                return Some(dt.naive_utc().date());
+            }
+        }
+        self.generated
+            .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+            .map(|dt| dt.naive_utc().date())
+    }
+}
+
+/// Read the whole document at `path`, decompressing by extension. For tarballs
+/// (`.tar`, `.tgz`, `.tar.gz`), the first member named `member` is read instead.
+fn read_document(path: &str, member: &str) -> Result<Vec<u8>> {
+    let path = path.strip_prefix("file://").unwrap_or(path);
+    let mut bytes = Vec::new();
+    if is_tarball(path) {
+        let mut archive = tar::Archive::new(oneio::get_reader(path)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let is_member = entry
+                .path()?
+                .file_name()
+                .map(|name| name == member)
+                .unwrap_or(false);
+            if is_member {
+                entry.read_to_end(&mut bytes)?;
+                return Ok(bytes);
+            }
+        }
+        return Err(anyhow!("{} not found in tarball {}", member, path));
+    }
+    oneio::get_reader(path)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn is_tarball(path: &str) -> bool {
+    path.ends_with(".tar") || path.ends_with(".tgz") || path.ends_with(".tar.gz")
+}
+
+/// Parse any of the JSON VRP exports into ROA entries stamped with `date`,
+/// falling back to the export's own metadata timestamp. Malformed VRPs are
+/// reported with their 1-based position in `roas` as the line.
+fn parse_vrp_json(path: &str, date: Option<NaiveDate>, mode: ParseMode) -> Result<ParsedSnapshot> {
+    let bytes = read_document(path, RPKI_CLIENT_JSON_NAME)?;
+    let dump: JsonVrpDump =
+        serde_json::from_slice(&bytes).map_err(|e| anyhow!("invalid VRP JSON {}: {}", path, e))?;
+
+    let date = match date.or_else(|| dump.metadata.as_ref().and_then(|m| m.date())) {
+        Some(d) => d,
+        None => return Err(anyhow!("no snapshot date for {}", path)),
+    };
+
+    let mut roas = HashSet::new();
+    let mut skipped = Vec::new();
+    for (idx, value) in dump.roas.into_iter().enumerate() {
+        let entries = JsonVrp::deserialize(value)
+            .map_err(|e| RoaCsvErrorKind::InvalidRecord(e.to_string()))
+            .and_then(|roa| roa.entries(date));
+        match entries {
+            Ok(entries) => roas.extend(entries),
+            Err(kind) => {
+                let error = RoaCsvError {
+                    file: path.to_string(),
+                    line: idx + 1,
+                    column: None,
+                    kind,
+                };
+                skip_or_fail(mode, error, &mut skipped)?;
+            }
+        }
+    }
+    Ok(ParsedSnapshot {
+        entries: roas.into_iter().collect(),
+        skipped,
+    })
+}
+
+/// Parse rpki-client `json` output (or an rpkiviews `.tgz` containing it) into
+/// ROA entries stamped with `date`. When `date` is `None`, the snapshot's
+/// `metadata.buildtime` is used.
+pub fn parse_rpki_client_json(path: &str, date: Option<NaiveDate>) -> Result<Vec<RoaEntry>> {
+    Ok(parse_vrp_json(path, date, ParseMode::Strict)?.entries)
+}
+
+/// Parse Routinator `jsonext` output. The TAL of each VRP is taken from its
+/// `source` objects; a VRP published under several TALs yields one entry per TAL.
+pub fn parse_routinator_jsonext(path: &str, date: Option<NaiveDate>) -> Result<Vec<RoaEntry>> {
+    Ok(parse_vrp_json(path, date, ParseMode::Strict)?.entries)
+}
+
+/// Parse a RIPE NCC RPKI Validator `export.json`. The export carries no
+/// timestamp, so `date` is required.
+pub fn parse_ripe_validator_json(path: &str, date: NaiveDate) -> Result<Vec<RoaEntry>> {
+    Ok(parse_vrp_json(path, Some(date), ParseMode::Strict)?.entries)
+}
+
+/// One parsed CSV row: TAL, prefix, max length, ASN and the ROA object.
+type CsvRow = (String, IpNet, i32, u32, Option<RoaObject>);
+
+/// A malformed CSV row: the 1-based column at fault and what is wrong with it.
+type CsvRowError = (usize, RoaCsvErrorKind);
+
+/// Parse a CSV export whose rows are read by `parse_row`, after checking that
+/// the first line starts with `header`. `parse_row` is also given the index
+/// of the `Trust Anchor` column, if the header has one.
+fn parse_vrp_csv(
+    path: &str,
+    header: &str,
+    parse_row: impl Fn(&[&str], Option<usize>) -> std::result::Result<CsvRow, CsvRowError>,
+    date: NaiveDate,
+    mode: ParseMode,
+) -> Result<ParsedSnapshot> {
+    let path = path.strip_prefix("file://").unwrap_or(path);
+    let mut roas = HashSet::new();
+    let mut skipped = Vec::new();
+    let mut tal_column = None;
+    for (idx, line) in oneio::read_lines_lossy(path)?.enumerate() {
+        let line = line?;
+        let line = line.trim_start_matches('\u{feff}').trim_end();
+        if idx == 0 {
+            if !line.starts_with(header) {
+                return Err(anyhow!("{}: expected CSV header '{}'", path, header));
+            }
+            tal_column = line.split(',').position(|c| c.trim() == "Trust Anchor");
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        match parse_row(&fields, tal_column) {
+            Ok((tal, prefix, max_len, asn, object)) => {
+                roas.insert(RoaEntry {
+                    tal,
+                    prefix,
+                    max_len,
+                    asn,
+                    date,
+                    object,
+                    expires: None,
+                });
+            }
+            Err((column, kind)) => {
+                let error = RoaCsvError {
+                    file: path.to_string(),
+                    line: idx + 1,
+                    column: Some(column),
+                    kind,
+                };
+                skip_or_fail(mode, error, &mut skipped)?;
+            }
+        }
+    }
+    Ok(ParsedSnapshot {
+        entries: roas.into_iter().collect(),
+        skipped,
+    })
+}
+
+/// Parse the 0-based column `idx` of a CSV row with `parse`, tagging errors
+/// with the 1-based column.
+fn csv_column<'a, T>(
+    fields: &[&'a str],
+    idx: usize,
+    parse: impl FnOnce(&'a str) -> std::result::Result<T, RoaCsvErrorKind>,
+) -> std::result::Result<T, CsvRowError> {
+    let field = fields
+        .get(idx)
+        .copied()
+        .ok_or((idx + 1, RoaCsvErrorKind::MissingColumn))?;
+    parse(field).map_err(|kind| (idx + 1, kind))
+}
+
+/// A Routinator `csv` row: `ASN,IP Prefix,Max Length,Trust Anchor`.
+fn routinator_csv_row(
+    fields: &[&str],
+    _tal_column: Option<usize>,
+) -> std::result::Result<CsvRow, CsvRowError> {
+    let prefix = csv_column(fields, 1, parse_prefix)?;
+    Ok((
+        csv_column(fields, 3, |s| Ok(normalize_tal(s)))?,
+        prefix,
+        csv_column(fields, 2, |s| parse_max_len(s, prefix))?,
+        csv_column(fields, 0, parse_asn)?,
+        None,
+    ))
+}
+
+/// A Routinator `csvext` row: `URI,ASN,IP Prefix,Max Length,Not Before,Not After`,
+/// with the TAL taken from the `tal_column`, if any.
+fn routinator_csvext_row(
+    fields: &[&str],
+    tal_column: Option<usize>,
+) -> std::result::Result<CsvRow, CsvRowError> {
+    let prefix = csv_column(fields, 2, parse_prefix)?;
+    let tal = match tal_column {
+        Some(idx) => csv_column(fields, idx, |s| Ok(normalize_tal(s)))?,
+        None => String::new(),
+    };
+    Ok((
+        tal,
+        prefix,
+        csv_column(fields, 3, |s| parse_max_len(s, prefix))?,
+        csv_column(fields, 1, parse_asn)?,
+        Some(RoaObject {
+            uri: csv_column(fields, 0, |s| Ok(s.trim().to_string()))?,
+            not_before: fields.get(4).and_then(|t| parse_validity_time(t)),
+            not_after: fields.get(5).and_then(|t| parse_validity_time(t)),
+        }),
+    ))
+}
+
+/// Parse Routinator `csv` output (`ASN,IP Prefix,Max Length,Trust Anchor`).
+pub fn parse_routinator_csv(path: &str, date: NaiveDate) -> Result<Vec<RoaEntry>> {
+    let parsed = parse_vrp_csv(
+        path,
+        ROUTINATOR_CSV_HEADER,
+        routinator_csv_row,
+        date,
+        ParseMode::Strict,
+    )?;
+    Ok(parsed.entries)
+}
+
+/// Parse Routinator `csvext` output
+/// (`URI,ASN,IP Prefix,Max Length,Not Before,Not After`). The TAL is read from
+/// a `Trust Anchor` column when the header has one; without it entries carry
+/// an empty TAL. The URI and validity columns are kept as the entry's
+/// [`RoaObject`].
+pub fn parse_routinator_csvext(path: &str, date: NaiveDate) -> Result<Vec<RoaEntry>> {
+    let parsed = parse_vrp_csv(
+        path,
+        CSVEXT_HEADER,
+        routinator_csvext_row,
+        date,
+        ParseMode::Strict,
+    )?;
+    Ok(parsed.entries)
+}
+
+const ROUTINATOR_CSV_HEADER: &str = "ASN,IP Prefix,Max Length,Trust Anchor";
+const CSVEXT_HEADER: &str = "URI,ASN,IP Prefix,Max Length";
+
+/// Known VRP export formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VrpFormat {
+    /// rpki-client `json` output, or an rpkiviews tarball containing it.
+    RpkiClientJson,
+    /// Routinator `csv`: `ASN,IP Prefix,Max Length,Trust Anchor`.
+    RoutinatorCsv,
+    /// Routinator `csvext`: `URI,ASN,IP Prefix,Max Length,Not Before,Not After`.
+    RoutinatorCsvExt,
+    /// Routinator `jsonext`: VRPs with their `source` ROA objects.
+    RoutinatorJsonExt,
+    /// RIPE NCC RPKI Validator `export.json`.
+    RipeValidatorJson,
+}
+
+impl VrpFormat {
+    /// Guess the format of a file from its name alone. CSV and JSON files
+    /// need [`VrpFormat::detect`] to tell the individual formats apart.
+    pub fn from_path(path: &str) -> Option<VrpFormat> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        if is_tarball(name) {
+            return Some(VrpFormat::RpkiClientJson);
+        }
+        let name = name.strip_suffix(".gz").unwrap_or(name);
+        let name = name.strip_suffix(".xz").unwrap_or(name);
+        let name = name.strip_suffix(".bz2").unwrap_or(name);
+        if name.ends_with(".csv") {
+            return Some(VrpFormat::RoutinatorCsv);
+        }
+        if name == "export.json" {
+            return Some(VrpFormat::RipeValidatorJson);
+        }
+        if name.ends_with(".json") {
+            return Some(VrpFormat::RpkiClientJson);
+        }
+        None
+    }
+
+    /// Detect the format of a file from its header, falling back to its name.
+    pub fn detect(path: &str) -> Result<VrpFormat> {
+        let by_name =
+            VrpFormat::from_path(path).ok_or_else(|| anyhow!("unknown VRP export: {}", path))?;
+        if is_tarball(path) {
+            return Ok(by_name);
+        }
+
+        // peek at the first few KiB of the decompressed document
+        let local = path.strip_prefix("file://").unwrap_or(path);
+        let mut head = Vec::new();
+        oneio::get_reader(local)?
+            .take(4096)
+            .read_to_end(&mut head)?;
+        let head = String::from_utf8_lossy(&head);
+        let head = head.trim_start_matches('\u{feff}').trim_start();
+
+        if head.starts_with('{') {
+            return Ok(
+                if head.contains("\"buildtime\"") || head.contains("\"buildmachine\"") {
+                    VrpFormat::RpkiClientJson
+                } else if head.contains("\"source\"") || head.contains("\"generatedTime\"") {
+                    VrpFormat::RoutinatorJsonExt
+                } else if head.contains("\"ta\"") && !head.contains("\"metadata\"") {
+                    VrpFormat::RipeValidatorJson
+                } else {
+                    by_name
+                },
+            );
+        }
+        if head.starts_with(ROUTINATOR_CSV_HEADER) {
+            return Ok(VrpFormat::RoutinatorCsv);
+        }
+        if head.starts_with(CSVEXT_HEADER) {
+            return Ok(VrpFormat::RoutinatorCsvExt);
+        }
+        Err(anyhow!("unrecognized VRP export header in {}", path))
+    }
+
+    /// Parse `path` in this format, stamping entries with `date`, failing on
+    /// the first malformed VRP. See [`VrpFormat::parse_with`].
+    pub fn parse(&self, path: &str, date: NaiveDate) -> Result<Vec<RoaEntry>> {
+        Ok(self.parse_with(path, date, ParseMode::Strict)?.entries)
+    }
+
+    /// Parse `path` in this format, stamping entries with `date`. In
+    /// [`ParseMode::Lenient`], malformed VRPs are skipped and returned
+    /// alongside the entries; file-level errors fail in either mode.
+    pub fn parse_with(
+        &self,
+        path: &str,
+        date: NaiveDate,
+        mode: ParseMode,
+    ) -> Result<ParsedSnapshot> {
+        match self {
+            VrpFormat::RpkiClientJson
+            | VrpFormat::RoutinatorJsonExt
+            | VrpFormat::RipeValidatorJson => parse_vrp_json(path, Some(date), mode),
+            VrpFormat::RoutinatorCsv => {
+                parse_vrp_csv(path, ROUTINATOR_CSV_HEADER, routinator_csv_row, date, mode)
+            }
+            VrpFormat::RoutinatorCsvExt => {
+                parse_vrp_csv(path, CSVEXT_HEADER, routinator_csvext_row, date, mode)
+            }
+        }
+    }
+}
+
+/// Parse a VRP export of any supported format, detected from its header.
+pub fn parse_vrp_export(path: &str, date: NaiveDate) -> Result<Vec<RoaEntry>> {
+    VrpFormat::detect(path)?.parse(path, date)
+}
+
+/// Parse a VRP export of any supported format under the given [`ParseMode`].
+pub fn parse_vrp_export_with(
+    path: &str,
+    date: NaiveDate,
+    mode: ParseMode,
+) -> Result<ParsedSnapshot> {
+    VrpFormat::detect(path)?.parse_with(path, date, mode)
+}
+
+/// Extract the snapshot date from a path: the last `YYYY/MM/DD`,
+/// `YYYY-MM-DD` or `YYYYMMDD` sequence in it.
+fn date_from_path(path: &str) -> Option<NaiveDate> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN
+        .get_or_init(|| Regex::new(r"(\d{4})[/\-]?(\d{2})[/\-]?(\d{2})").unwrap())
+        .captures_iter(path)
+        .filter_map(|cap| {
+            NaiveDate::from_ymd_opt(
+                cap[1].parse().ok()?,
+                cap[2].parse().ok()?,
+                cap[3].parse().ok()?,
+            )
+        })
+        .last()
+}
+
+/// A local directory tree of daily VRP exports in any supported format, e.g.
+/// rpkiviews snapshots or saved Routinator output. Each file's date comes from its path; if several
+/// files share a date, only the first one (in path order) is used.
+pub struct VrpDirectory {
+    root: String,
+    tal: Option<String>,
+}
+
+impl VrpDirectory {
+    /// Directory rooted at `root`; if `tal` is set, only ROAs of that TAL are read.
+    /// The filter does not change the feed: every day still counts as ingested
+    /// for [`VRP_FEED`], so a filtered directory should not drive `update`.
+    pub fn new(root: &str, tal: Option<String>) -> Self {
+        VrpDirectory {
+            root: root
+                .strip_prefix("file://")
+                .unwrap_or(root)
+                .trim_end_matches('/')
+                .to_string(),
+            tal: tal.map(|t| normalize_tal(&t)),
+        }
+    }
+
+    fn walk(dir: &Path, files: &mut Vec<String>) {
+        let read_dir = match std::fs::read_dir(dir) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("failed to list {}: {}", dir.display(), e);
+                return;
+            }
+        };
+        for entry in read_dir.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                Self::walk(&path, files);
+            } else if let Some(p) = path.to_str() {
+                files.push(p.to_string());
+            }
+        }
+    }
+}
+
+impl RoaSource for VrpDirectory {
+    fn name(&self) -> String {
+        match &self.tal {
+            Some(tal) => format!("{} ({})", self.root, tal),
+            None => self.root.clone(),
+        }
+    }
+
+    fn tals(&self) -> Vec<String> {
+        vec![VRP_FEED.to_string()]
+    }
+
+    fn list_snapshots(
+        &self,
+        tal: &str,
+        from: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Vec<RoaFile> {
+        let mut paths = Vec::new();
+        Self::walk(Path::new(&self.root), &mut paths);
+        paths.sort();
+
+        let mut seen_dates = HashSet::new();
+        let mut files = Vec::new();
+        for path in paths {
+            if VrpFormat::from_path(&path).is_none() {
+                continue;
+            }
+            let Some(file_date) = date_from_path(&path) else {
+                warn!("skipping {}: no date in path", path);
+                continue;
+            };
+            if from.is_some_and(|f| file_date < f) || until.is_some_and(|u| file_date > u) {
+                continue;
+            }
+            if !seen_dates.insert(file_date) {
+                warn!(
+                    "skipping {}: another export already covers {}",
+                    path, file_date
+                );
+                continue;
+            }
+            files.push(RoaFile {
+                url: path,
+                tal: tal.to_string(),
+                file_date,
+                rows_count: 0,
+                processed: false,
+            });
+        }
+        files
+    }
+
+    fn read_snapshot(&self, file: &RoaFile) -> Result<Vec<RoaEntry>> {
+        Ok(self.read_snapshot_with(file, ParseMode::Strict)?.entries)
+    }
+
+    fn read_snapshot_with(&self, file: &RoaFile, mode: ParseMode) -> Result<ParsedSnapshot> {
+        let mut parsed = parse_vrp_export_with(&file.url, file.file_date, mode)?;
+        if let Some(tal) = &self.tal {
+            parsed.entries.retain(|e| &e.tal == tal);
+        }
+        Ok(parsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const RPKI_CLIENT_JSON: &str = r#"{
+        "metadata": {"buildmachine": "test", "buildtime": "2024-01-15T03:04:05Z", "roas": 2},
+        "roas": [
+            {"asn": 13335, "prefix": "1.1.1.0/24", "maxLength": 24, "ta": "apnic", "expires": 1705708800},
+            {"asn": "AS64512", "prefix": "2001:db8::/32", "maxLength": 48, "ta": "ripe", "expires": 1705708800}
+        ]
+    }"#;
+
+    fn unique_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("wayback-rpki-vrp-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_rpki_client_json() {
+        let dir = unique_dir("json");
+        let path = dir.join("rpki-client.json");
+        std::fs::write(&path, RPKI_CLIENT_JSON).unwrap();
+
+        let mut roas = parse_rpki_client_json(path.to_str().unwrap(), None).unwrap();
+        roas.sort_by_key(|r| r.asn);
+        assert_eq!(roas.len(), 2);
+        assert_eq!(roas[0].asn, 13335);
+        assert_eq!(roas[0].tal, "apnic");
+        assert_eq!(roas[0].date, NaiveDate::from_ymd_opt(2024, 1, 15).unwrap());
+        assert_eq!(roas[0].expires, Some(1705708800));
+        assert_eq!(roas[1].asn, 64512);
+        assert_eq!(roas[1].tal, "ripencc");
+        assert_eq!(roas[1].max_len, 48);
+
+        // an explicit snapshot date wins over metadata
+        let date = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
+        let roas = parse_rpki_client_json(path.to_str().unwrap(), Some(date)).unwrap();
+        assert!(roas.iter().all(|r| r.date == date));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_vrp_directory_reads_rpkiviews_tarballs() {
+        let dir = unique_dir("tgz");
+        for day in ["14", "15"] {
+            let path = dir.join(format!("2024/01/{day}/rpki-202401{day}T000000Z.tgz"));
+            let writer = oneio::get_writer(path.to_str().unwrap()).unwrap();
+            let mut builder = tar::Builder::new(writer);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(RPKI_CLIENT_JSON.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(
+                    &mut header,
+                    "rpki-20240115T000000Z/output/rpki-client.json",
+                    RPKI_CLIENT_JSON.as_bytes(),
+                )
+                .unwrap();
+            builder.into_inner().unwrap().flush().unwrap();
+        }
+
+        let source = VrpDirectory::new(dir.to_str().unwrap(), Some("apnic".to_string()));
+        let files = source.list_all_snapshots(NaiveDate::from_ymd_opt(2024, 1, 15), None);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].tal, VRP_FEED);
+        assert_eq!(
+            files[0].file_date,
+            NaiveDate::from_ymd_opt(2024, 1, 15).unwrap()
+        );
+
+        let roas = source.read_snapshot(&files[0]).unwrap();
+        assert_eq!(roas.len(), 1);
+        assert_eq!(roas[0].asn, 13335);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_normalize_tal() {
+        assert_eq!(normalize_tal("ripe"), "ripencc");
+        assert_eq!(normalize_tal("RIPE NCC RPKI Root"), "ripencc");
+        assert_eq!(normalize_tal("arin.tal"), "arin");
+        assert_eq!(normalize_tal("Custom"), "custom");
+    }
+
+    #[test]
+    fn test_detect_and_parse_routinator_and_validator_exports() {
+        let dir = unique_dir("formats");
+        let date = NaiveDate::from_ymd_opt(2020, 6, 1).unwrap();
+        let exports = [
+            (
+                "vrps.csv",
+                "ASN,IP Prefix,Max Length,Trust Anchor\nAS13335,1.1.1.0/24,24,apnic\n",
+                VrpFormat::RoutinatorCsv,
+            ),
+            (
+                "vrps-ext.csv.gz",
+                "URI,ASN,IP Prefix,Max Length,Not Before,Not After\n\
+                 rsync://rpki.apnic.net/a.roa,AS13335,1.1.1.0/24,,2020-01-01 00:00:00,2021-01-01 00:00:00\n",
+                VrpFormat::RoutinatorCsvExt,
+            ),
+            (
+                "vrps.json",
+                r#"{"metadata":{"generated":1590969600,"generatedTime":"2020-06-01T00:00:00Z"},
+                    "roas":[{"asn":"AS13335","prefix":"1.1.1.0/24","maxLength":24,
+                    "source":[{"type":"roa","uri":"rsync://rpki.apnic.net/a.roa","tal":"apnic",
+                    "validity":{"notBefore":"2020-01-01T00:00:00Z","notAfter":"2021-01-01T00:00:00Z"}}]}]}"#,
+                VrpFormat::RoutinatorJsonExt,
+            ),
+            (
+                "export.json",
+                r#"{"roas":[{"asn":"AS13335","prefix":"1.1.1.0/24","maxLength":24,"ta":"APNIC RPKI Root"}]}"#,
+                VrpFormat::RipeValidatorJson,
+            ),
+        ];
+
+        for (name, contents, format) in exports {
+            let path = dir.join(name);
+            let path = path.to_str().unwrap();
+            let mut writer = oneio::get_writer(path).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+            drop(writer);
+
+            assert_eq!(VrpFormat::detect(path).unwrap(), format, "{name}");
+            let roas = parse_vrp_export(path, date).unwrap();
+            assert_eq!(roas.len(), 1, "{name}");
+            assert_eq!(roas[0].asn, 13335);
+            assert_eq!(roas[0].prefix.to_string(), "1.1.1.0/24");
+            assert_eq!(roas[0].max_len, 24);
+            assert_eq!(roas[0].date, date);
+            if format != VrpFormat::RoutinatorCsvExt {
+                assert_eq!(roas[0].tal, "apnic", "{name}");
+            }
+            match format {
+                VrpFormat::RoutinatorCsvExt | VrpFormat::RoutinatorJsonExt => {
+                    let object = roas[0].object.as_ref().unwrap();
+                    assert_eq!(object.uri, "rsync://rpki.apnic.net/a.roa", "{name}");
+                    assert_eq!(object.not_before, Some(1577836800), "{name}");
+                    assert_eq!(object.not_after, Some(1609459200), "{name}");
+                }
+                _ => assert!(roas[0].object.is_none(), "{name}"),
+            }
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_csvext_reads_tal_and_checks_max_length() {
+        let dir = unique_dir("csvext");
+        let path = dir.join("vrps.csv");
+        std::fs::write(
+            &path,
+            "URI,ASN,IP Prefix,Max Length,Not Before,Not After,Trust Anchor\n\
+             rsync://rpki.apnic.net/a.roa,AS13335,1.1.1.0/24,24,,,APNIC RPKI Root\n\
+             rsync://rpki.apnic.net/b.roa,AS13335,1.1.2.0/24,33,,,apnic\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        let date = NaiveDate::from_ymd_opt(2020, 6, 1).unwrap();
+
+        let parsed = VrpFormat::RoutinatorCsvExt
+            .parse_with(path, date, ParseMode::Lenient)
+            .unwrap();
+        assert_eq!(parsed.entries.len(), 1);
+        assert_eq!(parsed.entries[0].tal, "apnic");
+        assert_eq!(
+            (parsed.skipped[0].line, parsed.skipped[0].column),
+            (3, Some(4))
+        );
+        assert!(matches!(
+            parsed.skipped[0].kind,
+            RoaCsvErrorKind::InvalidMaxLength(_)
+        ));
+        assert!(parse_routinator_csvext(path, date).is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_vrp_directory_skips_malformed_rows_when_lenient() {
+        let dir = unique_dir("lenient");
+        std::fs::write(
+            dir.join("routinator-2020-06-01.csv"),
+            "ASN,IP Prefix,Max Length,Trust Anchor\n\
+             AS13335,1.1.1.0/24,24,apnic\n\
+             ASxyz,1.0.0.0/24,24,apnic\n\
+             AS13335,1.0.0.0/24\n\
+             AS13335,1.0.1.0/24,16,apnic\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("rpki-client-2020-06-02.json"),
+            r#"{"metadata": {"buildtime": "2020-06-02T00:00:00Z"},
+                "roas": [
+                    {"asn": 13335, "prefix": "1.1.1.0/24", "maxLength": 24, "ta": "apnic"},
+                    {"asn": 13335, "prefix": "1.1.1.0/33", "maxLength": 24, "ta": "apnic"},
+                    {"asn": 13335, "prefix": "1.1.1.0/24", "maxLength": 16, "ta": "apnic"},
+                    {"prefix": "1.1.1.0/24", "ta": "apnic"}
+                ]}"#,
+        )
+        .unwrap();
+
+        let source = VrpDirectory::new(dir.to_str().unwrap(), None);
+        let files = source.list_all_snapshots(None, None);
+        let csv = source
+            .read_snapshot_with(&files[0], ParseMode::Lenient)
+            .unwrap();
+        assert_eq!(csv.entries.len(), 1);
+        let skipped: Vec<_> = csv.skipped.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(skipped, vec![(3, Some(1)), (4, Some(4)), (5, Some(3))]);
+        assert!(matches!(
+            csv.skipped[0].kind,
+            RoaCsvErrorKind::InvalidAsn(_)
+        ));
+
+        let json = source
+            .read_snapshot_with(&files[1], ParseMode::Lenient)
+            .unwrap();
+        assert_eq!(json.entries.len(), 1);
+        let kinds: Vec<_> = json
+            .skipped
+            .iter()
+            .map(|e| (e.line, e.kind.clone()))
+            .collect();
+        assert!(matches!(kinds[0], (2, RoaCsvErrorKind::InvalidPrefix(_))));
+        assert!(matches!(
+            kinds[1],
+            (3, RoaCsvErrorKind::InvalidMaxLength(_))
+        ));
+        assert!(matches!(kinds[2], (4, RoaCsvErrorKind::InvalidRecord(_))));
+
+        // strict mode fails the file on its first malformed row
+        assert!(source.read_snapshot(&files[0]).is_err());
+        assert!(source.read_snapshot(&files[1]).is_err());
+
+        // an update counts the skipped rows of both files
+        let mut builder = crate::RoasTrieMut::new();
+        let summary = builder.update(&source, None, ParseMode::Lenient).unwrap();
+        assert_eq!((summary.files, summary.skipped_rows), (2, 6));
+        let mut builder = crate::RoasTrieMut::new();
+        let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!((summary.files, summary.failed.len()), (0, 2));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_held_out_vrp_export_day_removes_its_records() {
+        let dir = unique_dir("hold-out");
+        let prefixes = ["1.0.0.0/24", "1.0.1.0/24", "1.0.2.0/24", "1.0.3.0/24"];
+        // day 2 is truncated: one of four ROAs, plus a stray one
+        for (day, published) in [(1, &prefixes[..]), (2, &prefixes[..1]), (3, &prefixes[..])] {
+            let mut csv = String::from("ASN,IP Prefix,Max Length,Trust Anchor\n");
+            for prefix in published {
+                csv.push_str(&format!("AS13335,{prefix},24,apnic\n"));
+            }
+            if day == 2 {
+                csv.push_str("AS64512,9.9.9.0/24,24,ripe\n");
+            }
+            std::fs::write(dir.join(format!("routinator-2020-06-0{day}.csv")), csv).unwrap();
+        }
+
+        let source = VrpDirectory::new(dir.to_str().unwrap(), None);
+        let mut builder = crate::RoasTrieMut::new().with_anomaly_config(crate::AnomalyConfig {
+            drop_ratio: 0.6,
+            hold_out: true,
+        });
+        let summary = builder.update(&source, None, ParseMode::Strict).unwrap();
+        assert_eq!(summary.anomalies.len(), 1);
+        assert!(summary.anomalies[0].held_out);
+
+        let rkyv = dir.join("trie.rkyv");
+        builder.dump(rkyv.to_str().unwrap()).unwrap();
+        let trie = crate::RoasTrie::open(rkyv.to_str().unwrap()).unwrap();
+        assert!(trie
+            .lookup_prefix(&"9.9.9.0/24".parse().unwrap())
+            .is_empty());
+        let day = |d| NaiveDate::from_ymd_opt(2020, 6, d).unwrap();
+        for prefix in &prefixes[1..] {
+            let records = trie.lookup_prefix(&prefix.parse().unwrap());
+            assert_eq!(records[0].dates_ranges, vec![(day(1), day(3))]);
+            assert_eq!(records[0].interpolated_ranges, vec![(day(2), day(2))]);
+        }
+        assert_eq!(trie.held_out_days(), vec![(VRP_FEED.to_string(), day(2))]);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_vrp_directory_imports_daily_exports() {
+        let dir = unique_dir("daily");
+        for (day, asn) in [
+            ("2020-06-01", 13335),
+            ("2020-06-02", 13335),
+            ("2020-06-03", 64512),
+        ] {
+            std::fs::write(
+                dir.join(format!("routinator-{day}.csv")),
+                format!("ASN,IP Prefix,Max Length,Trust Anchor\nAS{asn},1.1.1.0/24,24,apnic\n"),
+            )
+            .unwrap();
+        }
+
+        let source = VrpDirectory::new(dir.to_str().unwrap(), None);
+        let mut builder = crate::RoasTrieMut::new();
+        for file in source.list_all_snapshots(None, None) {
+            builder.process_entries(&source.read_snapshot(&file).unwrap(), false);
+        }
+        let rkyv = dir.join("trie.rkyv");
+        builder.dump(rkyv.to_str().unwrap()).unwrap();
+        let trie = crate::RoasTrie::open(rkyv.to_str().unwrap()).unwrap();
+
+        let results = trie.search(&crate::SearchQuery {
+            origin: Some(13335),
+            ..Default::default()
+        });
+        assert_eq!(
+            results[0].dates_ranges,
+            vec![(
+                NaiveDate::from_ymd_opt(2020, 6, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2020, 6, 2).unwrap()
+            )]
+        );
+        assert_eq!(
+            trie.get_latest_date(),
+            NaiveDate::from_ymd_opt(2020, 6, 3).unwrap()
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}